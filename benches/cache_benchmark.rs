@@ -0,0 +1,136 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use readb::cache::{ArcCache, Cache, Key, LfuCache, LruCache, TinyLfuCache};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Tracks the bytes currently allocated, to check the memory used by a cache
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const KEYS: usize = 100_000;
+const CAPACITY: usize = 1_000;
+const ACCESSES: usize = 200_000;
+
+// Draws keys from a zipf distribution, a few keys get most of the accesses
+fn zipf_workload(exponent: f64, seed: u64) -> Vec<Key> {
+    let weights: Vec<f64> = (1..=KEYS)
+        .map(|i| 1.0 / (i as f64).powf(exponent))
+        .collect();
+    let total: f64 = weights.iter().sum();
+    let mut cumulative = Vec::with_capacity(KEYS);
+    let mut sum = 0.0;
+    for w in weights {
+        sum += w / total;
+        cumulative.push(sum);
+    }
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..ACCESSES)
+        .map(|_| {
+            let sample: f64 = rng.gen();
+            let rank = cumulative.partition_point(|&c| c < sample).min(KEYS - 1);
+            (rank as u64 * 64, 64)
+        })
+        .collect()
+}
+
+// Replays the workload the way the database uses a cache, a miss is followed by a put
+fn replay<C: Cache>(cache: &mut C, workload: &[Key]) -> usize {
+    let mut hits = 0;
+    for key in workload {
+        match cache.get(key) {
+            Some(_) => hits += 1,
+            None => cache.put(*key, vec![0; key.1]),
+        }
+    }
+    hits
+}
+
+fn hit_ratio<C: Cache>(workload: &[Key]) -> f64 {
+    let mut cache = C::new(CAPACITY);
+    replay(&mut cache, workload) as f64 / workload.len() as f64
+}
+
+fn benchmark_caches(c: &mut Criterion) {
+    for exponent in [0.8, 1.0, 1.2] {
+        let workload = zipf_workload(exponent, 42);
+        println!(
+            "zipf({}) hit ratios: lfu {:.3}, lru {:.3}, arc {:.3}, w-tinylfu {:.3}",
+            exponent,
+            hit_ratio::<LfuCache>(&workload),
+            hit_ratio::<LruCache>(&workload),
+            hit_ratio::<ArcCache>(&workload),
+            hit_ratio::<TinyLfuCache>(&workload),
+        );
+
+        let mut group = c.benchmark_group(format!("cache zipf({})", exponent));
+        group.sample_size(10);
+        group.bench_function("lfu", |b| {
+            b.iter(|| replay(&mut LfuCache::new(CAPACITY), black_box(&workload)))
+        });
+        group.bench_function("lru", |b| {
+            b.iter(|| replay(&mut LruCache::new(CAPACITY), black_box(&workload)))
+        });
+        group.bench_function("arc", |b| {
+            b.iter(|| replay(&mut ArcCache::new(CAPACITY), black_box(&workload)))
+        });
+        group.bench_function("w-tinylfu", |b| {
+            b.iter(|| replay(&mut TinyLfuCache::new(CAPACITY), black_box(&workload)))
+        });
+        group.finish();
+    }
+}
+
+// Memory used by a full cache, before and after hammering a few hot keys
+fn hot_key_memory<C: Cache>(hits: usize) -> (usize, usize) {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let mut cache = C::new(CAPACITY);
+    for i in 0..CAPACITY as u64 {
+        cache.put((i * 64, 64), vec![0; 64]);
+    }
+    let full = ALLOCATED.load(Ordering::Relaxed) - before;
+
+    for i in 0..hits {
+        black_box(cache.get(&((i % 4) as u64 * 64, 64)));
+    }
+    (full, ALLOCATED.load(Ordering::Relaxed) - before)
+}
+
+fn benchmark_hot_keys(c: &mut Criterion) {
+    for hits in [10_000, 1_000_000] {
+        let (full, after) = hot_key_memory::<LfuCache>(hits);
+        println!(
+            "lfu memory after {} hot key hits: {} bytes (full cache {} bytes)",
+            hits, after, full
+        );
+    }
+
+    let mut cache = LfuCache::new(CAPACITY);
+    for i in 0..CAPACITY as u64 {
+        cache.put((i * 64, 64), vec![0; 64]);
+    }
+    c.bench_function("lfu hot key get", |b| {
+        b.iter(|| cache.get(black_box(&(0, 64))))
+    });
+}
+
+criterion_group!(benches, benchmark_caches, benchmark_hot_keys);
+criterion_main!(benches);
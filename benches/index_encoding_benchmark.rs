@@ -0,0 +1,86 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use readb::{Database, DatabaseSettings, DefaultDatabase, IndexFormat, IndexType};
+use std::collections::HashMap;
+use std::path::Path;
+
+const KEYS: u64 = 200_000;
+const VALUE_SIZE: u64 = 100;
+
+fn settings(path: &Path, index_format: IndexFormat) -> DatabaseSettings {
+    DatabaseSettings {
+        path: Some(path.to_path_buf()),
+        cache_size: None,
+        index_type: IndexType::HashMap,
+        index_format,
+        ..Default::default()
+    }
+}
+
+// Writes a database the way previous versions did, with the index serialized by bincode
+fn write_bincode_database(path: &Path) {
+    let index: HashMap<String, (u64, usize)> = (0..KEYS)
+        .map(|i| {
+            let key = format!("user/{:08}/profile", i);
+            (key, (i * VALUE_SIZE, VALUE_SIZE as usize))
+        })
+        .collect();
+
+    std::fs::write(path.join(".rdb.index"), bincode::serialize(&index).unwrap()).unwrap();
+    std::fs::write(path.join(".rdb.type"), "HashMap").unwrap();
+    std::fs::write(
+        path.join(".rdb.data"),
+        vec![0u8; (KEYS * VALUE_SIZE) as usize],
+    )
+    .unwrap();
+}
+
+fn index_size(path: &Path) -> u64 {
+    std::fs::metadata(path.join(".rdb.index")).unwrap().len()
+}
+
+fn benchmark_index_encoding(c: &mut Criterion) {
+    let bincode_dir = tempfile::tempdir().unwrap();
+    write_bincode_database(bincode_dir.path());
+
+    // Opening and persisting the database rewrites its index in the compact format
+    let compact_dir = tempfile::tempdir().unwrap();
+    write_bincode_database(compact_dir.path());
+    DefaultDatabase::new(settings(compact_dir.path(), IndexFormat::Compact))
+        .persist()
+        .unwrap();
+
+    for dir in [&bincode_dir, &compact_dir] {
+        let mut db = DefaultDatabase::new(settings(dir.path(), IndexFormat::Bincode));
+        assert!(db.get("user/00000001/profile").unwrap().is_some());
+    }
+
+    println!(
+        "Index of {} keys: {} bytes with bincode, {} bytes compressed",
+        KEYS,
+        index_size(bincode_dir.path()),
+        index_size(compact_dir.path())
+    );
+
+    let mut group = c.benchmark_group(format!("Open an index of {} keys", KEYS));
+    group.sample_size(10);
+    group.bench_function("bincode", |b| {
+        b.iter(|| {
+            black_box(DefaultDatabase::new(settings(
+                bincode_dir.path(),
+                IndexFormat::Bincode,
+            )))
+        })
+    });
+    group.bench_function("compressed", |b| {
+        b.iter(|| {
+            black_box(DefaultDatabase::new(settings(
+                compact_dir.path(),
+                IndexFormat::Compact,
+            )))
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, benchmark_index_encoding);
+criterion_main!(benches);
@@ -0,0 +1,81 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use readb::cache::{Cache, LfuCache, ShardedCache};
+use readb::{Database, DatabaseSettings, DefaultDatabase, LLDatabase};
+use std::path::Path;
+use std::sync::Arc;
+
+const ITEMS: usize = 10_000;
+const THREADS: usize = 8;
+
+// Every thread reads all keys in its own random order through its own database handle
+fn read_in_threads<C: Cache + Send + Sync>(handles: &mut [LLDatabase<C>], keys: &[Vec<String>]) {
+    std::thread::scope(|s| {
+        for (db, keys) in handles.iter_mut().zip(keys) {
+            s.spawn(move || {
+                for key in keys {
+                    black_box(db.get(key).unwrap());
+                }
+            });
+        }
+    });
+}
+
+fn open<C: Cache + Send + Sync>(path: &Path, cache: C) -> LLDatabase<C> {
+    LLDatabase::with_cache(
+        DatabaseSettings {
+            path: Some(path.to_path_buf()),
+            ..Default::default()
+        },
+        cache,
+    )
+}
+
+fn benchmark_multithreaded_read(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let mut db = DefaultDatabase::new_default(dir.path().to_path_buf());
+        for i in 0..ITEMS {
+            db.put(
+                format!("key{}", i).as_str(),
+                format!("value{}", i).as_bytes(),
+            )
+            .unwrap();
+        }
+        db.persist().unwrap();
+    }
+
+    let keys: Vec<Vec<String>> = (0..THREADS)
+        .map(|t| {
+            let mut keys: Vec<String> = (0..ITEMS).map(|i| format!("key{}", i)).collect();
+            keys.shuffle(&mut rand::rngs::StdRng::seed_from_u64(t as u64));
+            keys
+        })
+        .collect();
+
+    let mut group = c.benchmark_group(format!("Read {} items in {} threads", ITEMS, THREADS));
+    group.sample_size(10);
+
+    // Each handle has its own cache
+    let mut handles: Vec<_> = (0..THREADS)
+        .map(|_| open(dir.path(), LfuCache::new(ITEMS)))
+        .collect();
+    group.bench_function("cache per handle", |b| {
+        b.iter(|| read_in_threads(&mut handles, &keys))
+    });
+
+    // All handles share one sharded cache
+    let cache: Arc<ShardedCache<LfuCache>> = Arc::new(ShardedCache::new(ITEMS));
+    let mut handles: Vec<_> = (0..THREADS)
+        .map(|_| open(dir.path(), cache.clone()))
+        .collect();
+    group.bench_function("shared sharded cache", |b| {
+        b.iter(|| read_in_threads(&mut handles, &keys))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, benchmark_multithreaded_read);
+criterion_main!(benches);
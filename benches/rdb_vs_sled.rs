@@ -1,11 +1,9 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use rand::seq::SliceRandom;
 use rand::SeedableRng;
-use readb::{Database, DatabaseSettings, DefaultDatabase, IndexTable, IndexType};
-use redb::{Database as RDatabase, Error, ReadableTable, TableDefinition};
-use sled;
+use readb::{Database, DatabaseSettings, DefaultDatabase, IndexType};
+use redb::{Database as RDatabase, TableDefinition};
 use std::cmp::max;
-use std::fs::write;
 
 fn benchmark_retrieval_from_db(c: &mut Criterion) {
     // Helper function to create data for our benchmark.
@@ -107,7 +107,7 @@ fn benchmark_write(c: &mut Criterion) {
                             let _ = readb_instance.get(black_box(key)).unwrap();
                         }
                         Operation::Write((key, value)) => {
-                            let _ = readb_instance
+                            readb_instance
                                 .put(black_box(key), black_box(value.as_bytes()))
                                 .unwrap();
                         }
@@ -123,6 +123,7 @@ fn benchmark_write(c: &mut Criterion) {
                     match task {
                         Operation::Read(key) => {
                             tx.commit().unwrap();
+                            drop(tx);
 
                             tx = readb_tx_instance.tx().unwrap();
                             let _ = readb_instance.get(black_box(key)).unwrap();
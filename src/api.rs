@@ -1,7 +1,17 @@
-use crate::cache::lfu::LfuCache;
-use crate::databases::lazy_loader_db::LLDatabase;
+use crate::cache::LfuCache;
+pub use crate::databases::lazy_loader_db::LLDatabase;
 
 pub type DefaultDatabase = LLDatabase<LfuCache>;
 
+pub use crate::databases::clock::{Clock, SystemClock};
+pub use crate::databases::db_trait::CompareAndSwapError;
 pub use crate::databases::db_trait::Database;
 pub use crate::databases::db_trait::DatabaseSettings;
+pub use crate::databases::db_trait::DatabaseStats;
+pub use crate::databases::db_trait::Preload;
+pub use crate::databases::merge::MergeOperator;
+pub use crate::databases::secondary::Extractor;
+pub use crate::databases::tree::Tree;
+pub use crate::databases::watch::{Event, Subscriber};
+#[cfg(feature = "write")]
+pub use crate::transactions::batch::Batch;
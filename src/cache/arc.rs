@@ -0,0 +1,299 @@
+use crate::cache::list::List;
+use crate::cache::pinned::Pinned;
+use crate::cache::{Cache, CacheLimits, CacheStats, Key, Value, DEFAULT_CAPACITY};
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Slot {
+    Recent(usize),        // resident, seen once
+    Frequent(usize),      // resident, seen at least twice
+    RecentGhost(usize),   // evicted from recent
+    FrequentGhost(usize), // evicted from frequent
+}
+
+/// Adaptive replacement cache, balances between recency and frequency based on the hits of
+/// recently evicted keys.
+///
+/// Without an entry limit, the number of remembered evicted keys follows the number of resident
+/// entries.
+pub struct ArcCache {
+    limits: CacheLimits,
+    counters: CacheStats,
+    pinned: Pinned,
+    bytes: usize,  // total size of the cached values
+    target: usize, // target number of entries in `recent`
+    map: HashMap<Key, Slot>,
+    recent: List<(Key, Value)>,
+    frequent: List<(Key, Value)>,
+    recent_ghosts: List<Key>,
+    frequent_ghosts: List<Key>,
+}
+
+impl ArcCache {
+    pub fn new(capacity: usize) -> ArcCache {
+        Self::with_limits(CacheLimits {
+            entries: Some(capacity),
+            ..Default::default()
+        })
+    }
+
+    pub fn with_limits(limits: CacheLimits) -> ArcCache {
+        ArcCache {
+            limits,
+            counters: CacheStats::default(),
+            pinned: Pinned::default(),
+            bytes: 0,
+            target: 0,
+            map: HashMap::new(),
+            recent: List::new(),
+            frequent: List::new(),
+            recent_ghosts: List::new(),
+            frequent_ghosts: List::new(),
+        }
+    }
+
+    fn resident(&self) -> usize {
+        self.recent.len() + self.frequent.len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.limits.entries.unwrap_or(self.resident()).max(1)
+    }
+
+    // Moves the least recently used entry of one of the resident lists to its ghost list,
+    // returns false if the cache is empty
+    fn replace(&mut self, frequent_ghost_hit: bool) -> bool {
+        let from_recent = !self.recent.is_empty()
+            && (self.recent.len() > self.target
+                || (frequent_ghost_hit && self.recent.len() == self.target)
+                || self.frequent.is_empty());
+
+        let evicted = match from_recent {
+            true => self.recent.pop_back(),
+            false => self.frequent.pop_back(),
+        };
+        let Some((key, value)) = evicted else {
+            return false;
+        };
+
+        self.bytes -= value.len();
+        self.counters.evictions += 1;
+        let slot = match from_recent {
+            true => Slot::RecentGhost(self.recent_ghosts.push_front(key)),
+            false => Slot::FrequentGhost(self.frequent_ghosts.push_front(key)),
+        };
+        self.map.insert(key, slot);
+        true
+    }
+
+    fn trim_ghosts(&mut self) {
+        let capacity = self.capacity();
+        while self.recent.len() + self.recent_ghosts.len() > capacity {
+            match self.recent_ghosts.pop_back() {
+                Some(key) => drop(self.map.remove(&key)),
+                None => break,
+            }
+        }
+        while self.resident() + self.recent_ghosts.len() + self.frequent_ghosts.len() > 2 * capacity
+        {
+            match self.frequent_ghosts.pop_back() {
+                Some(key) => drop(self.map.remove(&key)),
+                None => break,
+            }
+        }
+    }
+
+    fn lookup(&mut self, key: &Key) -> Option<Value> {
+        match *self.map.get(key)? {
+            Slot::Recent(index) => {
+                let (key, value) = self.recent.remove(index);
+                let cached = value.clone();
+                self.map
+                    .insert(key, Slot::Frequent(self.frequent.push_front((key, value))));
+                Some(cached)
+            }
+            Slot::Frequent(index) => {
+                self.frequent.move_to_front(index);
+                Some(self.frequent.get(index).1.clone())
+            }
+            Slot::RecentGhost(_) | Slot::FrequentGhost(_) => None,
+        }
+    }
+}
+
+impl Cache for ArcCache {
+    fn new(size: usize) -> Self {
+        Self::new(size)
+    }
+
+    fn new_default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+
+    fn with_limits(limits: CacheLimits) -> Self {
+        Self::with_limits(limits)
+    }
+
+    fn get(&mut self, key: &Key) -> Option<Value> {
+        if let Some(value) = self.pinned.get(key) {
+            self.counters.hits += 1;
+            return Some(value);
+        }
+
+        let value = self.lookup(key);
+        self.counters.record(&value);
+        value
+    }
+
+    fn put(&mut self, key: Key, value: Value) {
+        if !self.limits.admits(value.len()) {
+            return;
+        }
+
+        // Hits on ghosts shift the target towards the list that would have kept the key
+        let frequent_ghost_hit = match self.map.remove(&key) {
+            Some(Slot::Recent(index)) => {
+                self.bytes -= self.recent.remove(index).1.len();
+                None
+            }
+            Some(Slot::Frequent(index)) => {
+                self.bytes -= self.frequent.remove(index).1.len();
+                Some(false)
+            }
+            Some(Slot::RecentGhost(index)) => {
+                let delta = (self.frequent_ghosts.len() / self.recent_ghosts.len()).max(1);
+                self.target = (self.target + delta).min(self.capacity());
+                self.recent_ghosts.remove(index);
+                Some(false)
+            }
+            Some(Slot::FrequentGhost(index)) => {
+                let delta = (self.recent_ghosts.len() / self.frequent_ghosts.len()).max(1);
+                self.target = self.target.saturating_sub(delta);
+                self.frequent_ghosts.remove(index);
+                Some(true)
+            }
+            None => None,
+        };
+
+        while self
+            .limits
+            .exceeded_by(self.resident() + 1, self.bytes + value.len())
+            && self.replace(frequent_ghost_hit == Some(true))
+        {}
+
+        self.bytes += value.len();
+        let slot = match frequent_ghost_hit {
+            Some(_) => Slot::Frequent(self.frequent.push_front((key, value))),
+            None => Slot::Recent(self.recent.push_front((key, value))),
+        };
+        self.map.insert(key, slot);
+        self.trim_ghosts();
+    }
+
+    fn invalidate(&mut self) {
+        *self = ArcCache {
+            counters: self.counters,
+            ..Self::with_limits(self.limits)
+        };
+    }
+
+    fn remove(&mut self, key: &Key) -> Option<Value> {
+        // Ghosts of a removed key would adapt the target for unrelated data
+        let (_, value) = match self.map.remove(key)? {
+            Slot::Recent(index) => self.recent.remove(index),
+            Slot::Frequent(index) => self.frequent.remove(index),
+            Slot::RecentGhost(index) => {
+                self.recent_ghosts.remove(index);
+                return None;
+            }
+            Slot::FrequentGhost(index) => {
+                self.frequent_ghosts.remove(index);
+                return None;
+            }
+        };
+        self.bytes -= value.len();
+        Some(value)
+    }
+
+    fn pin(&mut self, key: Key, value: Value) {
+        self.pinned.pin(key, value);
+    }
+
+    fn unpin(&mut self, key: &Key) {
+        if let Some(value) = self.pinned.unpin(key) {
+            self.put(*key, value);
+        }
+    }
+
+    fn hot_keys(&self) -> Vec<Key> {
+        let frequent = self.frequent.iter().map(|(key, _)| *key);
+        frequent
+            .chain(self.recent.iter().map(|(key, _)| *key))
+            .collect()
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.resident() + self.pinned.len(),
+            bytes: self.bytes + self.pinned.bytes(),
+            ..self.counters
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::arc::ArcCache;
+    use crate::cache::{Cache, CacheLimits};
+
+    #[test]
+    fn frequent_entries_survive_scans() {
+        let mut cache = ArcCache::new(4);
+        cache.put((1, 1), vec![1]);
+        cache.get(&(1, 1));
+        cache.put((2, 1), vec![2]);
+        cache.get(&(2, 1));
+
+        // A scan over keys that are only read once
+        for i in 10..20 {
+            cache.put((i, 1), vec![i as u8]);
+        }
+
+        assert_eq!(cache.get(&(1, 1)), Some(vec![1]));
+        assert_eq!(cache.get(&(2, 1)), Some(vec![2]));
+        assert_eq!(cache.get(&(10, 1)), None);
+        assert!(cache.resident() <= 4);
+    }
+
+    #[test]
+    fn ghost_hits_adapt_target() {
+        let mut cache = ArcCache::new(2);
+        cache.put((1, 1), vec![1]);
+        cache.get(&(1, 1));
+        cache.put((2, 1), vec![2]);
+        cache.put((3, 1), vec![3]);
+        assert_eq!(cache.get(&(2, 1)), None);
+
+        // Re-inserting a key evicted from `recent` favours recency, the key goes to `frequent`
+        cache.put((2, 1), vec![2]);
+        assert_eq!(cache.target, 1);
+        assert_eq!(cache.get(&(2, 1)), Some(vec![2]));
+        assert_eq!(cache.get(&(1, 1)), None);
+        assert_eq!(cache.get(&(3, 1)), Some(vec![3]));
+    }
+
+    #[test]
+    fn evict_by_bytes() {
+        let mut cache = ArcCache::with_limits(CacheLimits {
+            bytes: Some(10),
+            ..Default::default()
+        });
+        cache.put((1, 4), vec![1; 4]);
+        cache.put((2, 4), vec![2; 4]);
+        cache.put((3, 4), vec![3; 4]);
+
+        assert_eq!(cache.bytes, 8);
+        assert_eq!(cache.get(&(1, 4)), None);
+        assert_eq!(cache.get(&(3, 4)), Some(vec![3; 4]));
+    }
+}
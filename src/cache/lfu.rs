@@ -1,22 +1,134 @@
-use crate::cache::{Cache, Key, Value};
-use std::cmp::Reverse;
-use std::collections::{hash_map::Entry, BinaryHeap, HashMap};
+use crate::cache::pinned::Pinned;
+use crate::cache::{Cache, CacheLimits, CacheStats, Key, Value, DEFAULT_CAPACITY};
+use std::collections::HashMap;
 
-const DEFAULT_CAPACITY: usize = 1024;
+// Frequencies saturate at this value, so there is a fixed number of buckets
+const MAX_FREQUENCY: usize = 64;
+// Frequencies are halved after this many hits per cached entry, so formerly hot entries can age out
+const AGING_PERIOD: usize = 16;
+const NIL: usize = usize::MAX;
 
+struct Node {
+    key: Key,
+    value: Value,
+    frequency: usize,
+    prev: usize,
+    next: usize,
+}
+
+/// Evicts the least frequently used entry, and among those the least recently used one.
+///
+/// Entries are kept in one linked list per frequency, so all operations are O(1) and the memory
+/// used only depends on the number of cached entries.
 pub struct LfuCache {
-    capacity: usize,
-    map: HashMap<Key, (Value, usize)>, // key to (value, frequency)
-    frequencies: BinaryHeap<Reverse<(usize, Key)>>, // Reverse heap of (frequency, key)
+    limits: CacheLimits,
+    counters: CacheStats,
+    pinned: Pinned,
+    bytes: usize,                 // total size of the cached values
+    map: HashMap<Key, usize>,     // key to node
+    nodes: Vec<Option<Node>>,     // slab of nodes, `None` for free slots
+    free: Vec<usize>,             // free slots in `nodes`
+    buckets: Vec<(usize, usize)>, // (head, tail) of the list for each frequency
+    min_frequency: usize,         // lowest frequency with a non-empty bucket
+    hits: usize,                  // hits since the last aging
 }
 
 impl LfuCache {
     pub fn new(capacity: usize) -> LfuCache {
+        Self::with_limits(CacheLimits {
+            entries: Some(capacity),
+            ..Default::default()
+        })
+    }
+
+    pub fn with_limits(limits: CacheLimits) -> LfuCache {
         LfuCache {
-            capacity,
+            limits,
+            counters: CacheStats::default(),
+            pinned: Pinned::default(),
+            bytes: 0,
             map: HashMap::new(),
-            frequencies: BinaryHeap::new(),
+            nodes: Vec::new(),
+            free: Vec::new(),
+            buckets: vec![(NIL, NIL); MAX_FREQUENCY + 1],
+            min_frequency: 1,
+            hits: 0,
+        }
+    }
+
+    fn node(&self, slot: usize) -> &Node {
+        self.nodes[slot].as_ref().unwrap()
+    }
+
+    fn node_mut(&mut self, slot: usize) -> &mut Node {
+        self.nodes[slot].as_mut().unwrap()
+    }
+
+    // Pushes a node to the front of the bucket of its frequency
+    fn link(&mut self, slot: usize) {
+        let frequency = self.node(slot).frequency;
+        let head = self.buckets[frequency].0;
+        let node = self.node_mut(slot);
+        node.prev = NIL;
+        node.next = head;
+        match head {
+            NIL => self.buckets[frequency].1 = slot,
+            head => self.node_mut(head).prev = slot,
+        }
+        self.buckets[frequency].0 = slot;
+    }
+
+    fn unlink(&mut self, slot: usize) {
+        let node = self.node(slot);
+        let (frequency, prev, next) = (node.frequency, node.prev, node.next);
+        match prev {
+            NIL => self.buckets[frequency].0 = next,
+            prev => self.node_mut(prev).next = next,
+        }
+        match next {
+            NIL => self.buckets[frequency].1 = prev,
+            next => self.node_mut(next).prev = prev,
+        }
+    }
+
+    fn update_min_frequency(&mut self) {
+        self.min_frequency = (self.min_frequency..=MAX_FREQUENCY)
+            .chain(1..self.min_frequency)
+            .find(|&f| self.buckets[f].1 != NIL)
+            .unwrap_or(1);
+    }
+
+    // Evicts the least frequently used entry, returns false if the cache is empty
+    fn evict(&mut self) -> bool {
+        self.update_min_frequency();
+        let slot = self.buckets[self.min_frequency].1;
+        if slot == NIL {
+            return false;
+        }
+
+        self.unlink(slot);
+        let node = self.nodes[slot].take().unwrap();
+        self.free.push(slot);
+        self.map.remove(&node.key);
+        self.bytes -= node.value.len();
+        self.counters.evictions += 1;
+        true
+    }
+
+    // Halves all frequencies, keeping the order of entries within a bucket
+    fn age(&mut self) {
+        for frequency in 2..=MAX_FREQUENCY {
+            while self.buckets[frequency].1 != NIL {
+                let slot = self.buckets[frequency].1;
+                self.unlink(slot);
+                self.node_mut(slot).frequency = frequency / 2;
+                self.link(slot);
+            }
         }
+
+        self.hits = 0;
+        self.min_frequency = 1;
+        self.update_min_frequency();
     }
 }
 
@@ -29,49 +141,135 @@ impl Cache for LfuCache {
         Self::new(DEFAULT_CAPACITY)
     }
 
+    fn with_limits(limits: CacheLimits) -> Self {
+        Self::with_limits(limits)
+    }
+
     fn get(&mut self, key: &Key) -> Option<Value> {
-        match self.map.get_mut(key) {
-            Some((value, frequency)) => {
-                *frequency += 1;
-                self.frequencies.push(Reverse((*frequency, *key)));
-                Some(value.clone())
+        if let Some(value) = self.pinned.get(key) {
+            self.counters.hits += 1;
+            return Some(value);
+        }
+
+        let Some(&slot) = self.map.get(key) else {
+            self.counters.misses += 1;
+            return None;
+        };
+        self.counters.hits += 1;
+        let frequency = self.node(slot).frequency;
+        if frequency < MAX_FREQUENCY {
+            self.unlink(slot);
+            self.node_mut(slot).frequency += 1;
+            self.link(slot);
+            if frequency == self.min_frequency && self.buckets[frequency].1 == NIL {
+                self.min_frequency += 1;
             }
-            None => None,
         }
+
+        let value = self.node(slot).value.clone();
+        self.hits += 1;
+        if self.hits >= AGING_PERIOD * self.map.len() {
+            self.age();
+        }
+        Some(value)
     }
 
     fn put(&mut self, key: Key, value: Value) {
-        if self.map.len() == self.capacity {
-            while let Some(Reverse((_, evicted_key))) = self.frequencies.pop() {
-                if let Entry::Occupied(e) = self.map.entry(evicted_key) {
-                    if e.get().1 > 1 {
-                        continue;
-                    }
-                    e.remove_entry();
-                    break;
-                }
-            }
+        if !self.limits.admits(value.len()) {
+            return;
         }
+        self.remove(&key);
+
+        while self
+            .limits
+            .exceeded_by(self.map.len() + 1, self.bytes + value.len())
+            && self.evict()
+        {}
 
-        self.map.insert(key, (value, 1));
-        self.frequencies.push(Reverse((1, key)));
+        self.bytes += value.len();
+        let node = Node {
+            key,
+            value,
+            frequency: 1,
+            prev: NIL,
+            next: NIL,
+        };
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                self.nodes[slot] = Some(node);
+                slot
+            }
+            None => {
+                self.nodes.push(Some(node));
+                self.nodes.len() - 1
+            }
+        };
+        self.link(slot);
+        self.map.insert(key, slot);
+        self.min_frequency = 1;
     }
 
     fn invalidate(&mut self) {
-        self.map.clear();
-        self.frequencies.clear();
+        *self = LfuCache {
+            counters: self.counters,
+            ..Self::with_limits(self.limits)
+        };
+    }
+
+    fn remove(&mut self, key: &Key) -> Option<Value> {
+        let slot = self.map.remove(key)?;
+        self.unlink(slot);
+        let node = self.nodes[slot].take().unwrap();
+        self.free.push(slot);
+        self.bytes -= node.value.len();
+        Some(node.value)
+    }
+
+    fn pin(&mut self, key: Key, value: Value) {
+        self.pinned.pin(key, value);
+    }
+
+    fn unpin(&mut self, key: &Key) {
+        if let Some(value) = self.pinned.unpin(key) {
+            self.put(*key, value);
+        }
+    }
+
+    // Most frequently used first, and within a frequency most recently used first
+    fn hot_keys(&self) -> Vec<Key> {
+        let mut keys = Vec::with_capacity(self.map.len());
+        for &(head, _) in self.buckets.iter().rev() {
+            let mut slot = head;
+            while slot != NIL {
+                keys.push(self.node(slot).key);
+                slot = self.node(slot).next;
+            }
+        }
+        keys
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.map.len() + self.pinned.len(),
+            bytes: self.bytes + self.pinned.bytes(),
+            ..self.counters
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::cache::lfu::LfuCache;
-    use crate::cache::Cache;
+    use crate::cache::{Cache, CacheLimits, Key};
+
+    fn frequency(cache: &LfuCache, key: &Key) -> usize {
+        cache.node(cache.map[key]).frequency
+    }
 
     #[test]
     fn create_lfu_cache() {
         let cache = LfuCache::new(2);
-        assert_eq!(cache.capacity, 2);
+        assert_eq!(cache.limits.entries, Some(2));
         assert_eq!(cache.map.len(), 0);
     }
 
@@ -117,6 +315,118 @@ mod tests {
         cache.get(&(1, 0));
         cache.get(&(1, 0));
 
-        assert_eq!(cache.map.get(&(1, 0)).unwrap().1, 3);
+        assert_eq!(frequency(&cache, &(1, 0)), 3);
+    }
+
+    #[test]
+    fn evict_by_bytes() {
+        let mut cache = LfuCache::with_limits(CacheLimits {
+            bytes: Some(10),
+            ..Default::default()
+        });
+        cache.put((1, 4), vec![1; 4]);
+        cache.put((2, 4), vec![2; 4]);
+        cache.get(&(2, 4));
+        cache.put((3, 4), vec![3; 4]);
+
+        assert_eq!(cache.get(&(1, 4)), None);
+        assert_eq!(cache.get(&(2, 4)), Some(vec![2; 4]));
+        assert_eq!(cache.get(&(3, 4)), Some(vec![3; 4]));
+        assert_eq!(cache.bytes, 8);
+
+        // A single value larger than the budget evicts nothing
+        cache.put((4, 11), vec![4; 11]);
+        assert_eq!(cache.get(&(4, 11)), None);
+        assert_eq!(cache.map.len(), 2);
+    }
+
+    #[test]
+    fn reject_oversized_entries() {
+        let mut cache = LfuCache::with_limits(CacheLimits {
+            entries: Some(2),
+            max_entry_bytes: Some(4),
+            ..Default::default()
+        });
+        cache.put((1, 4), vec![1; 4]);
+        cache.put((2, 5), vec![2; 5]);
+
+        assert_eq!(cache.get(&(1, 4)), Some(vec![1; 4]));
+        assert_eq!(cache.get(&(2, 5)), None);
+    }
+
+    #[test]
+    fn hot_keys_stay_bounded() {
+        let mut cache = LfuCache::new(8);
+        for i in 0..8 {
+            cache.put((i, 1), vec![i as u8]);
+        }
+        for _ in 0..100_000 {
+            cache.get(&(1, 1));
+        }
+        for i in 8..1000 {
+            cache.put((i, 1), vec![i as u8]);
+        }
+
+        assert_eq!(cache.map.len(), 8);
+        assert!(cache.nodes.len() <= 8);
+        assert!(frequency(&cache, &(1, 1)) <= super::MAX_FREQUENCY);
+        assert_eq!(cache.get(&(1, 1)), Some(vec![1]));
+    }
+
+    #[test]
+    fn evict_least_recent_among_equals() {
+        let mut cache = LfuCache::new(2);
+        cache.put((1, 0), vec![1]);
+        cache.put((2, 0), vec![2]);
+        cache.get(&(1, 0));
+        cache.get(&(2, 0));
+        cache.put((3, 0), vec![3]);
+
+        assert_eq!(cache.get(&(1, 0)), None);
+        assert_eq!(cache.get(&(2, 0)), Some(vec![2]));
+    }
+
+    #[test]
+    fn hot_keys_by_frequency() {
+        let mut cache = LfuCache::new(3);
+        cache.put((1, 0), vec![1]);
+        cache.put((2, 0), vec![2]);
+        cache.put((3, 0), vec![3]);
+        cache.get(&(2, 0));
+        cache.get(&(2, 0));
+        cache.get(&(1, 0));
+
+        assert_eq!(cache.hot_keys(), vec![(2, 0), (1, 0), (3, 0)]);
+    }
+
+    #[test]
+    fn pinned_entries_are_not_evicted() {
+        let mut cache = LfuCache::new(1);
+        cache.pin((1, 0), vec![1]);
+        cache.put((2, 0), vec![2]);
+        cache.put((3, 0), vec![3]);
+
+        assert_eq!(cache.get(&(1, 0)), Some(vec![1]));
+        assert_eq!(cache.get(&(2, 0)), None);
+        assert_eq!(cache.stats().entries, 2);
+
+        // Once unpinned, the value competes with the others again
+        cache.unpin(&(1, 0));
+        cache.put((4, 0), vec![4]);
+        assert_eq!(cache.get(&(1, 0)), None);
+    }
+
+    #[test]
+    fn frequencies_age() {
+        let mut cache = LfuCache::new(2);
+        cache.put((1, 0), vec![1]);
+        for _ in 0..15 {
+            cache.get(&(1, 0));
+        }
+        assert_eq!(frequency(&cache, &(1, 0)), 16);
+
+        // The 16th hit on a single entry halves all frequencies
+        cache.get(&(1, 0));
+        assert_eq!(frequency(&cache, &(1, 0)), 8);
     }
 }
@@ -0,0 +1,153 @@
+const NIL: usize = usize::MAX;
+
+struct Node<T> {
+    value: Option<T>,
+    prev: usize,
+    next: usize,
+}
+
+/// A doubly linked list stored in a slab, so that nodes can be unlinked by index in O(1).
+///
+/// Indices stay valid until the node is removed, the front of the list is the most recently
+/// pushed or moved node.
+pub(crate) struct List<T> {
+    nodes: Vec<Node<T>>,
+    free: Vec<usize>,
+    head: usize,
+    tail: usize,
+    len: usize,
+}
+
+impl<T> List<T> {
+    pub(crate) fn new() -> Self {
+        List {
+            nodes: Vec::new(),
+            free: Vec::new(),
+            head: NIL,
+            tail: NIL,
+            len: 0,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub(crate) fn get(&self, index: usize) -> &T {
+        self.nodes[index].value.as_ref().unwrap()
+    }
+
+    pub(crate) fn back(&self) -> Option<&T> {
+        match self.tail {
+            NIL => None,
+            tail => Some(self.get(tail)),
+        }
+    }
+
+    /// Iterates from the front to the back of the list.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &T> {
+        std::iter::successors(Some(self.head).filter(|&i| i != NIL), |&i| {
+            Some(self.nodes[i].next).filter(|&next| next != NIL)
+        })
+        .map(|i| self.get(i))
+    }
+
+    pub(crate) fn push_front(&mut self, value: T) -> usize {
+        let node = Node {
+            value: Some(value),
+            prev: NIL,
+            next: NIL,
+        };
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+
+        self.link_front(index);
+        self.len += 1;
+        index
+    }
+
+    pub(crate) fn remove(&mut self, index: usize) -> T {
+        self.unlink(index);
+        self.free.push(index);
+        self.len -= 1;
+        self.nodes[index].value.take().unwrap()
+    }
+
+    pub(crate) fn pop_back(&mut self) -> Option<T> {
+        match self.tail {
+            NIL => None,
+            tail => Some(self.remove(tail)),
+        }
+    }
+
+    pub(crate) fn move_to_front(&mut self, index: usize) {
+        if self.head != index {
+            self.unlink(index);
+            self.link_front(index);
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    fn link_front(&mut self, index: usize) {
+        self.nodes[index].prev = NIL;
+        self.nodes[index].next = self.head;
+        match self.head {
+            NIL => self.tail = index,
+            head => self.nodes[head].prev = index,
+        }
+        self.head = index;
+    }
+
+    fn unlink(&mut self, index: usize) {
+        let (prev, next) = (self.nodes[index].prev, self.nodes[index].next);
+        match prev {
+            NIL => self.head = next,
+            prev => self.nodes[prev].next = next,
+        }
+        match next {
+            NIL => self.tail = prev,
+            next => self.nodes[next].prev = prev,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::List;
+
+    #[test]
+    fn push_move_and_pop() {
+        let mut list = List::new();
+        let a = list.push_front('a');
+        list.push_front('b');
+        let c = list.push_front('c');
+
+        list.move_to_front(a);
+        assert_eq!(list.pop_back(), Some('b'));
+        assert_eq!(list.remove(c), 'c');
+        assert_eq!(list.len(), 1);
+
+        // Freed slots are reused
+        assert_eq!(list.push_front('d'), c);
+        assert_eq!(list.iter().collect::<Vec<_>>(), vec![&'d', &'a']);
+        assert_eq!(list.back(), Some(&'a'));
+        assert_eq!(list.pop_back(), Some('a'));
+        assert_eq!(list.pop_back(), Some('d'));
+        assert!(list.is_empty());
+    }
+}
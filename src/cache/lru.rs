@@ -0,0 +1,170 @@
+use crate::cache::list::List;
+use crate::cache::pinned::Pinned;
+use crate::cache::{Cache, CacheLimits, CacheStats, Key, Value, DEFAULT_CAPACITY};
+use std::collections::HashMap;
+
+/// Evicts the least recently used entry.
+pub struct LruCache {
+    limits: CacheLimits,
+    counters: CacheStats,
+    pinned: Pinned,
+    bytes: usize,                // total size of the cached values
+    map: HashMap<Key, usize>,    // key to node in the recency list
+    recency: List<(Key, Value)>, // most recently used first
+}
+
+impl LruCache {
+    pub fn new(capacity: usize) -> LruCache {
+        Self::with_limits(CacheLimits {
+            entries: Some(capacity),
+            ..Default::default()
+        })
+    }
+
+    pub fn with_limits(limits: CacheLimits) -> LruCache {
+        LruCache {
+            limits,
+            counters: CacheStats::default(),
+            pinned: Pinned::default(),
+            bytes: 0,
+            map: HashMap::new(),
+            recency: List::new(),
+        }
+    }
+}
+
+impl Cache for LruCache {
+    fn new(size: usize) -> Self {
+        Self::new(size)
+    }
+
+    fn new_default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+
+    fn with_limits(limits: CacheLimits) -> Self {
+        Self::with_limits(limits)
+    }
+
+    fn get(&mut self, key: &Key) -> Option<Value> {
+        if let Some(value) = self.pinned.get(key) {
+            self.counters.hits += 1;
+            return Some(value);
+        }
+
+        let value = self.map.get(key).copied().map(|index| {
+            self.recency.move_to_front(index);
+            self.recency.get(index).1.clone()
+        });
+        self.counters.record(&value);
+        value
+    }
+
+    fn put(&mut self, key: Key, value: Value) {
+        if !self.limits.admits(value.len()) {
+            return;
+        }
+        self.remove(&key);
+
+        while self
+            .limits
+            .exceeded_by(self.map.len() + 1, self.bytes + value.len())
+        {
+            match self.recency.pop_back() {
+                Some((evicted_key, evicted)) => {
+                    self.map.remove(&evicted_key);
+                    self.bytes -= evicted.len();
+                    self.counters.evictions += 1;
+                }
+                None => break,
+            }
+        }
+
+        self.bytes += value.len();
+        let index = self.recency.push_front((key, value));
+        self.map.insert(key, index);
+    }
+
+    fn invalidate(&mut self) {
+        self.map.clear();
+        self.recency.clear();
+        self.pinned.clear();
+        self.bytes = 0;
+    }
+
+    fn remove(&mut self, key: &Key) -> Option<Value> {
+        let index = self.map.remove(key)?;
+        let (_, value) = self.recency.remove(index);
+        self.bytes -= value.len();
+        Some(value)
+    }
+
+    fn pin(&mut self, key: Key, value: Value) {
+        self.pinned.pin(key, value);
+    }
+
+    fn unpin(&mut self, key: &Key) {
+        if let Some(value) = self.pinned.unpin(key) {
+            self.put(*key, value);
+        }
+    }
+
+    fn hot_keys(&self) -> Vec<Key> {
+        self.recency.iter().map(|(key, _)| *key).collect()
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.map.len() + self.pinned.len(),
+            bytes: self.bytes + self.pinned.bytes(),
+            ..self.counters
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::lru::LruCache;
+    use crate::cache::{Cache, CacheLimits};
+
+    #[test]
+    fn evict_least_recent() {
+        let mut cache = LruCache::new(2);
+        cache.put((1, 1), vec![1]);
+        cache.put((2, 1), vec![2]);
+        cache.get(&(1, 1));
+        cache.put((3, 1), vec![3]);
+
+        assert_eq!(cache.get(&(1, 1)), Some(vec![1]));
+        assert_eq!(cache.get(&(2, 1)), None);
+        assert_eq!(cache.get(&(3, 1)), Some(vec![3]));
+    }
+
+    #[test]
+    fn remove_entry() {
+        let mut cache = LruCache::new(2);
+        cache.put((1, 1), vec![1]);
+        cache.put((2, 1), vec![2]);
+
+        assert_eq!(cache.remove(&(1, 1)), Some(vec![1]));
+        assert_eq!(cache.remove(&(1, 1)), None);
+        assert_eq!(cache.get(&(1, 1)), None);
+        assert_eq!(cache.stats().bytes, 1);
+    }
+
+    #[test]
+    fn evict_by_bytes() {
+        let mut cache = LruCache::with_limits(CacheLimits {
+            bytes: Some(10),
+            ..Default::default()
+        });
+        cache.put((1, 4), vec![1; 4]);
+        cache.put((2, 4), vec![2; 4]);
+        cache.put((3, 8), vec![3; 8]);
+
+        assert_eq!(cache.get(&(1, 4)), None);
+        assert_eq!(cache.get(&(2, 4)), None);
+        assert_eq!(cache.get(&(3, 8)), Some(vec![3; 8]));
+        assert_eq!(cache.bytes, 8);
+    }
+}
@@ -1,14 +1,127 @@
+//! Caches for values read from the data file.
+//!
+//! [`LLDatabase`](crate::LLDatabase) is generic over the [`Cache`] trait, so any of the caches
+//! below or a custom implementation can be plugged in.
+
+/// Location of a value in the data file, its offset and length.
 pub type Key = (u64, usize);
 type Value = Vec<u8>;
 
+// Number of entries the caches hold unless they're given other limits
+pub(crate) const DEFAULT_CAPACITY: usize = 1024;
+
+/// Bounds on what a [`Cache`] may hold, `None` leaves the respective bound open.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheLimits {
+    /// Maximum number of cached entries.
+    pub entries: Option<usize>,
+    /// Maximum total size of the cached values in bytes.
+    pub bytes: Option<usize>,
+    /// Values larger than this many bytes are never cached.
+    pub max_entry_bytes: Option<usize>,
+}
+
+impl CacheLimits {
+    /// Whether a value of `len` bytes may be cached at all.
+    // `Option::is_none_or` would need Rust 1.82
+    #[allow(clippy::unnecessary_map_or)]
+    pub fn admits(&self, len: usize) -> bool {
+        self.max_entry_bytes.map_or(true, |max| len <= max)
+            && self.bytes.map_or(true, |max| len <= max)
+    }
+
+    /// Whether `entries` values with a total size of `bytes` exceed the limits.
+    pub fn exceeded_by(&self, entries: usize, bytes: usize) -> bool {
+        self.entries.is_some_and(|max| entries > max) || self.bytes.is_some_and(|max| bytes > max)
+    }
+}
+
+/// Counters and current size of a [`Cache`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of lookups that found a value.
+    pub hits: u64,
+    /// Number of lookups that found nothing.
+    pub misses: u64,
+    /// Number of values evicted to stay within the limits.
+    pub evictions: u64,
+    /// Number of cached values.
+    pub entries: usize,
+    /// Total size of the cached values in bytes.
+    pub bytes: usize,
+}
+
+impl CacheStats {
+    pub(crate) fn record(&mut self, value: &Option<Value>) {
+        match value {
+            Some(_) => self.hits += 1,
+            None => self.misses += 1,
+        }
+    }
+}
+
+impl std::ops::Add for CacheStats {
+    type Output = CacheStats;
+
+    fn add(self, other: CacheStats) -> CacheStats {
+        CacheStats {
+            hits: self.hits + other.hits,
+            misses: self.misses + other.misses,
+            evictions: self.evictions + other.evictions,
+            entries: self.entries + other.entries,
+            bytes: self.bytes + other.bytes,
+        }
+    }
+}
+
 pub trait Cache {
     fn new(size: usize) -> Self;
     fn new_default() -> Self;
+    fn with_limits(limits: CacheLimits) -> Self;
 
     fn get(&mut self, key: &Key) -> Option<Value>;
     fn put(&mut self, key: Key, value: Value);
 
     fn invalidate(&mut self);
+
+    /// Drops the cached value of `key` and returns it, pinned values are kept. Caches that don't
+    /// implement this are invalidated entirely, which is correct but throws away the hot values.
+    fn remove(&mut self, _key: &Key) -> Option<Value> {
+        self.invalidate();
+        None
+    }
+
+    /// Keeps a value resident regardless of the eviction policy, until it was unpinned as often
+    /// as it was pinned. Pinned values don't count towards the limits. Caches that don't
+    /// implement this never pin anything.
+    fn pin(&mut self, _key: Key, _value: Value) {}
+
+    /// Releases a pin, once the last pin of a value is released it is cached like any other.
+    fn unpin(&mut self, _key: &Key) {}
+
+    /// Returns the cached keys, the ones most worth keeping first. Used to persist the hot set
+    /// of a database, caches that don't implement this are never preloaded from a hot set.
+    fn hot_keys(&self) -> Vec<Key> {
+        Vec::new()
+    }
+
+    /// Reports the counters and current size of the cache, custom caches report nothing unless
+    /// they implement this.
+    fn stats(&self) -> CacheStats {
+        CacheStats::default()
+    }
 }
 
-pub(crate) mod lfu;
+mod arc;
+mod lfu;
+pub(crate) mod list;
+mod lru;
+mod pinned;
+mod sharded;
+mod tiny_lfu;
+
+pub use arc::ArcCache;
+pub use lfu::LfuCache;
+pub use lru::LruCache;
+pub use sharded::ShardedCache;
+pub use tiny_lfu::TinyLfuCache;
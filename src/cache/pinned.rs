@@ -0,0 +1,76 @@
+use crate::cache::{Key, Value};
+use std::collections::HashMap;
+
+/// Values kept resident regardless of the eviction policy, next to the regular entries of a
+/// cache. Every `pin` needs its own `unpin`, as linked keys share a location.
+#[derive(Default)]
+pub(crate) struct Pinned {
+    values: HashMap<Key, (Value, usize)>, // location to (value, number of pins)
+    bytes: usize,
+}
+
+impl Pinned {
+    pub(crate) fn get(&self, key: &Key) -> Option<Value> {
+        // Keeps lookups free when nothing is pinned
+        if self.values.is_empty() {
+            return None;
+        }
+        self.values.get(key).map(|(value, _)| value.clone())
+    }
+
+    pub(crate) fn pin(&mut self, key: Key, value: Value) {
+        let length = value.len();
+        let (_, pins) = self.values.entry(key).or_insert_with(|| {
+            self.bytes += length;
+            (value, 0)
+        });
+        *pins += 1;
+    }
+
+    /// Returns the value once its last pin is removed.
+    pub(crate) fn unpin(&mut self, key: &Key) -> Option<Value> {
+        let (_, pins) = self.values.get_mut(key)?;
+        *pins -= 1;
+        if *pins > 0 {
+            return None;
+        }
+
+        let (value, _) = self.values.remove(key).unwrap();
+        self.bytes -= value.len();
+        Some(value)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub(crate) fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.values.clear();
+        self.bytes = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pinned;
+
+    #[test]
+    fn pins_are_counted() {
+        let mut pinned = Pinned::default();
+        assert_eq!(pinned.get(&(0, 1)), None);
+
+        pinned.pin((0, 1), vec![1]);
+        pinned.pin((0, 1), vec![1]);
+        assert_eq!(pinned.bytes(), 1);
+
+        assert_eq!(pinned.unpin(&(0, 1)), None);
+        assert_eq!(pinned.get(&(0, 1)), Some(vec![1]));
+        assert_eq!(pinned.unpin(&(0, 1)), Some(vec![1]));
+        assert_eq!(pinned.get(&(0, 1)), None);
+        assert_eq!(pinned.len(), 0);
+    }
+}
@@ -0,0 +1,299 @@
+use crate::cache::{Cache, CacheLimits, CacheStats, Key, Value, DEFAULT_CAPACITY};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+
+/// Splits a cache into shards with a lock each, so that it can be used through `&self` by many
+/// threads at once.
+///
+/// Keys are distributed over the shards by hash, and the limits are split between them, so the
+/// cache as a whole never holds more entries or bytes than allowed. There are never more shards
+/// than entries, and each shard has room for the largest value that may be cached. A cache
+/// limited in bytes therefore only has a single shard, unless `max_entry_bytes` is set as well.
+/// Share it between database handles as an `Arc<ShardedCache<C>>`, which implements [`Cache`]
+/// itself, see [`LLDatabase::with_cache`](crate::LLDatabase::with_cache).
+pub struct ShardedCache<C: Cache> {
+    shards: Vec<Mutex<C>>,
+}
+
+impl<C: Cache> ShardedCache<C> {
+    /// Creates a cache with a shard count based on the available parallelism.
+    pub fn new(capacity: usize) -> Self {
+        Self::with_limits(CacheLimits {
+            entries: Some(capacity),
+            ..Default::default()
+        })
+    }
+
+    pub fn with_limits(limits: CacheLimits) -> Self {
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Self::with_shards(limits, threads * 4)
+    }
+
+    /// Creates a cache with `shards` shards, rounded up to the next power of two. Fewer shards
+    /// are used if the limits can't be split between that many.
+    pub fn with_shards(limits: CacheLimits, shards: usize) -> Self {
+        let mut most = usize::MAX;
+        if let Some(entries) = limits.entries {
+            most = most.min(entries);
+        }
+        if let Some(bytes) = limits.bytes {
+            let largest = limits.max_entry_bytes.map_or(bytes, |max| max.min(bytes));
+            most = most.min(bytes / largest.max(1));
+        }
+        // Keys are assigned to shards by masking their hash
+        let most = most.max(1);
+        let shards = shards.max(1).next_power_of_two().min(1 << most.ilog2());
+
+        // The first shards take the remainder, so the limits add up exactly
+        let share = |limit: Option<usize>, shard: usize| {
+            limit.map(|l| l / shards + usize::from(shard < l % shards))
+        };
+        ShardedCache {
+            shards: (0..shards)
+                .map(|shard| {
+                    Mutex::new(C::with_limits(CacheLimits {
+                        entries: share(limits.entries, shard),
+                        bytes: share(limits.bytes, shard),
+                        max_entry_bytes: limits.max_entry_bytes,
+                    }))
+                })
+                .collect(),
+        }
+    }
+
+    fn shard(&self, key: &Key) -> &Mutex<C> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize & (self.shards.len() - 1)]
+    }
+
+    pub fn get(&self, key: &Key) -> Option<Value> {
+        self.shard(key).lock().unwrap().get(key)
+    }
+
+    pub fn put(&self, key: Key, value: Value) {
+        self.shard(&key).lock().unwrap().put(key, value)
+    }
+
+    pub fn invalidate(&self) {
+        for shard in &self.shards {
+            shard.lock().unwrap().invalidate();
+        }
+    }
+
+    pub fn remove(&self, key: &Key) -> Option<Value> {
+        self.shard(key).lock().unwrap().remove(key)
+    }
+
+    pub fn pin(&self, key: Key, value: Value) {
+        self.shard(&key).lock().unwrap().pin(key, value)
+    }
+
+    pub fn unpin(&self, key: &Key) {
+        self.shard(key).lock().unwrap().unpin(key)
+    }
+
+    /// Collects the hot keys of all shards, shard by shard.
+    pub fn hot_keys(&self) -> Vec<Key> {
+        self.shards
+            .iter()
+            .flat_map(|shard| shard.lock().unwrap().hot_keys())
+            .collect()
+    }
+
+    /// Sums up the stats of all shards.
+    pub fn stats(&self) -> CacheStats {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().stats())
+            .fold(CacheStats::default(), |total, stats| total + stats)
+    }
+}
+
+impl<C: Cache> Cache for ShardedCache<C> {
+    fn new(size: usize) -> Self {
+        Self::new(size)
+    }
+
+    fn new_default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+
+    fn with_limits(limits: CacheLimits) -> Self {
+        Self::with_limits(limits)
+    }
+
+    fn get(&mut self, key: &Key) -> Option<Value> {
+        ShardedCache::get(self, key)
+    }
+
+    fn put(&mut self, key: Key, value: Value) {
+        ShardedCache::put(self, key, value)
+    }
+
+    fn invalidate(&mut self) {
+        ShardedCache::invalidate(self)
+    }
+
+    fn remove(&mut self, key: &Key) -> Option<Value> {
+        ShardedCache::remove(self, key)
+    }
+
+    fn pin(&mut self, key: Key, value: Value) {
+        ShardedCache::pin(self, key, value)
+    }
+
+    fn unpin(&mut self, key: &Key) {
+        ShardedCache::unpin(self, key)
+    }
+
+    fn hot_keys(&self) -> Vec<Key> {
+        ShardedCache::hot_keys(self)
+    }
+
+    fn stats(&self) -> CacheStats {
+        ShardedCache::stats(self)
+    }
+}
+
+impl<C: Cache> Cache for Arc<ShardedCache<C>> {
+    fn new(size: usize) -> Self {
+        Arc::new(ShardedCache::new(size))
+    }
+
+    fn new_default() -> Self {
+        Arc::new(ShardedCache::new(DEFAULT_CAPACITY))
+    }
+
+    fn with_limits(limits: CacheLimits) -> Self {
+        Arc::new(ShardedCache::with_limits(limits))
+    }
+
+    fn get(&mut self, key: &Key) -> Option<Value> {
+        ShardedCache::get(self, key)
+    }
+
+    fn put(&mut self, key: Key, value: Value) {
+        ShardedCache::put(self, key, value)
+    }
+
+    fn invalidate(&mut self) {
+        ShardedCache::invalidate(self)
+    }
+
+    fn remove(&mut self, key: &Key) -> Option<Value> {
+        ShardedCache::remove(self, key)
+    }
+
+    fn pin(&mut self, key: Key, value: Value) {
+        ShardedCache::pin(self, key, value)
+    }
+
+    fn unpin(&mut self, key: &Key) {
+        ShardedCache::unpin(self, key)
+    }
+
+    fn hot_keys(&self) -> Vec<Key> {
+        ShardedCache::hot_keys(self)
+    }
+
+    fn stats(&self) -> CacheStats {
+        ShardedCache::stats(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::sharded::ShardedCache;
+    use crate::cache::{CacheLimits, LfuCache};
+    use std::sync::Arc;
+
+    #[test]
+    fn divide_limits() {
+        let limits = CacheLimits {
+            entries: Some(10),
+            bytes: Some(100),
+            max_entry_bytes: Some(8),
+        };
+        let cache: ShardedCache<LfuCache> = ShardedCache::with_shards(limits, 3);
+        assert_eq!(cache.shards.len(), 4);
+
+        for i in 0..100 {
+            cache.put((i, 8), vec![i as u8; 8]);
+        }
+        let cached = (0..100).filter(|&i| cache.get(&(i, 8)).is_some()).count();
+        assert_eq!(cached, 10);
+        assert_eq!(cache.stats().entries, 10);
+
+        cache.put((100, 9), vec![0; 9]);
+        assert_eq!(cache.get(&(100, 9)), None);
+    }
+
+    #[test]
+    fn entry_limit() {
+        // Never more shards than entries, which add up exactly
+        let limits = CacheLimits {
+            entries: Some(5),
+            ..Default::default()
+        };
+        let cache: ShardedCache<LfuCache> = ShardedCache::with_shards(limits, 64);
+        assert_eq!(cache.shards.len(), 4);
+        for i in 0..100 {
+            cache.put((i, 1), vec![1]);
+        }
+        assert_eq!(cache.stats().entries, 5);
+    }
+
+    #[test]
+    fn byte_limit() {
+        // Values up to the whole budget are cached
+        let limits = CacheLimits {
+            bytes: Some(1000),
+            ..Default::default()
+        };
+        let cache: ShardedCache<LfuCache> = ShardedCache::with_shards(limits, 64);
+        assert_eq!(cache.shards.len(), 1);
+        cache.put((0, 900), vec![0; 900]);
+        assert!(cache.get(&(0, 900)).is_some());
+        cache.put((1, 1001), vec![0; 1001]);
+        assert_eq!(cache.get(&(1, 1001)), None);
+
+        // Each shard has room for the largest value that may be cached
+        let limits = CacheLimits {
+            bytes: Some(1000),
+            max_entry_bytes: Some(100),
+            ..Default::default()
+        };
+        let cache: ShardedCache<LfuCache> = ShardedCache::with_shards(limits, 64);
+        assert_eq!(cache.shards.len(), 8);
+        for i in 0..100 {
+            cache.put((i * 100, 100), vec![0; 100]);
+            assert!(cache.get(&(i * 100, 100)).is_some());
+            assert!(cache.stats().bytes <= 1000);
+        }
+    }
+
+    #[test]
+    fn concurrent_access() {
+        let cache: Arc<ShardedCache<LfuCache>> = Arc::new(ShardedCache::new(1024));
+        std::thread::scope(|s| {
+            for t in 0..4u64 {
+                let cache = &cache;
+                s.spawn(move || {
+                    for i in 0..1000 {
+                        let key = (t * 1000 + i, 1);
+                        cache.put(key, vec![t as u8]);
+                        // Other threads may have evicted the value already
+                        if let Some(value) = cache.get(&key) {
+                            assert_eq!(value, vec![t as u8]);
+                        }
+                    }
+                });
+            }
+        });
+
+        cache.invalidate();
+        assert_eq!(cache.get(&(0, 1)), None);
+    }
+}
@@ -0,0 +1,396 @@
+use crate::cache::list::List;
+use crate::cache::pinned::Pinned;
+use crate::cache::{Cache, CacheLimits, CacheStats, Key, Value, DEFAULT_CAPACITY};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+const SKETCH_DEPTH: usize = 4;
+const MAX_COUNT: u8 = 15;
+
+/// Approximate access frequencies in a count-min sketch with 4-bit counters.
+///
+/// All counters are halved once the number of recorded accesses reaches the sample size, so old
+/// popularity fades over time.
+struct FrequencySketch {
+    counters: Vec<u8>,
+    mask: usize,
+    additions: usize,
+    sample_size: usize,
+}
+
+impl FrequencySketch {
+    fn new(entries: usize) -> Self {
+        let width = entries.max(16).next_power_of_two();
+        FrequencySketch {
+            counters: vec![0; width * SKETCH_DEPTH],
+            mask: width - 1,
+            additions: 0,
+            sample_size: width * 10,
+        }
+    }
+
+    fn indices(&self, key: &Key) -> [usize; SKETCH_DEPTH] {
+        let mut indices = [0; SKETCH_DEPTH];
+        for (row, index) in indices.iter_mut().enumerate() {
+            let mut hasher = DefaultHasher::new();
+            (row, key).hash(&mut hasher);
+            *index = row * (self.mask + 1) + (hasher.finish() as usize & self.mask);
+        }
+        indices
+    }
+
+    fn frequency(&self, key: &Key) -> u8 {
+        self.indices(key)
+            .iter()
+            .map(|&i| self.counters[i])
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn increment(&mut self, key: &Key) {
+        for i in self.indices(key) {
+            self.counters[i] = (self.counters[i] + 1).min(MAX_COUNT);
+        }
+
+        self.additions += 1;
+        if self.additions >= self.sample_size {
+            self.counters.iter_mut().for_each(|c| *c /= 2);
+            self.additions /= 2;
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Slot {
+    Window(usize),
+    Probation(usize),
+    Protected(usize),
+}
+
+/// Window TinyLFU: new entries enter a small LRU window, and only move into the main cache if
+/// they are estimated to be accessed more often than the entry they would replace.
+///
+/// The main cache is a segmented LRU, entries are promoted from probation to protected on a hit.
+/// With a byte limit the regions are sized in bytes, otherwise in entries.
+pub struct TinyLfuCache {
+    limits: CacheLimits,
+    counters: CacheStats,
+    pinned: Pinned,
+    sketch: FrequencySketch,
+    map: HashMap<Key, Slot>,
+    window: List<(Key, Value)>,
+    probation: List<(Key, Value)>,
+    protected: List<(Key, Value)>,
+    // Weights of the regions in the unit the cache is sized in
+    window_weight: usize,
+    main_weight: usize,
+    protected_weight: usize,
+    bytes: usize, // total size of the cached values
+}
+
+impl TinyLfuCache {
+    pub fn new(capacity: usize) -> TinyLfuCache {
+        Self::with_limits(CacheLimits {
+            entries: Some(capacity),
+            ..Default::default()
+        })
+    }
+
+    pub fn with_limits(limits: CacheLimits) -> TinyLfuCache {
+        TinyLfuCache {
+            limits,
+            counters: CacheStats::default(),
+            pinned: Pinned::default(),
+            sketch: FrequencySketch::new(limits.entries.unwrap_or(DEFAULT_CAPACITY)),
+            map: HashMap::new(),
+            window: List::new(),
+            probation: List::new(),
+            protected: List::new(),
+            window_weight: 0,
+            main_weight: 0,
+            protected_weight: 0,
+            bytes: 0,
+        }
+    }
+
+    fn weight(&self, value: &Value) -> usize {
+        match self.limits.bytes {
+            Some(_) => value.len(),
+            None => 1,
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.limits
+            .bytes
+            .or(self.limits.entries)
+            .unwrap_or(DEFAULT_CAPACITY)
+    }
+
+    // The window holds 1% of the capacity, protected 80% of the rest
+    fn window_capacity(&self) -> usize {
+        (self.capacity() / 100).max(1)
+    }
+
+    fn main_capacity(&self) -> usize {
+        self.capacity().saturating_sub(self.window_capacity())
+    }
+
+    fn protected_capacity(&self) -> usize {
+        self.main_capacity() * 4 / 5
+    }
+
+    fn remove_entry(&mut self, slot: Slot) -> (Key, Value) {
+        let (key, value) = match slot {
+            Slot::Window(index) => self.window.remove(index),
+            Slot::Probation(index) => self.probation.remove(index),
+            Slot::Protected(index) => self.protected.remove(index),
+        };
+
+        let weight = self.weight(&value);
+        match slot {
+            Slot::Window(_) => self.window_weight -= weight,
+            Slot::Protected(_) => {
+                self.protected_weight -= weight;
+                self.main_weight -= weight;
+            }
+            Slot::Probation(_) => self.main_weight -= weight,
+        }
+        self.bytes -= value.len();
+        self.map.remove(&key);
+        (key, value)
+    }
+
+    fn insert_probation(&mut self, key: Key, value: Value) {
+        self.main_weight += self.weight(&value);
+        self.bytes += value.len();
+        let index = self.probation.push_front((key, value));
+        self.map.insert(key, Slot::Probation(index));
+    }
+
+    // The main cache entry that would be evicted next
+    fn victim(&self) -> Option<Slot> {
+        let key = self.probation.back().or(self.protected.back())?.0;
+        self.map.get(&key).copied()
+    }
+
+    // Moves entries that overflow the window into the main cache if they are popular enough
+    fn admit_from_window(&mut self) {
+        while self.window_weight > self.window_capacity() {
+            let Some(&(candidate, _)) = self.window.back() else {
+                break;
+            };
+            let (key, value) = self.remove_entry(self.map[&candidate]);
+            let weight = self.weight(&value);
+
+            let mut admit = true;
+            while self.main_weight + weight > self.main_capacity() {
+                let Some(victim) = self.victim() else {
+                    break;
+                };
+                let victim_key = match victim {
+                    Slot::Probation(index) => self.probation.get(index).0,
+                    Slot::Protected(index) => self.protected.get(index).0,
+                    Slot::Window(_) => unreachable!(),
+                };
+                // Either the candidate or the victim leaves the cache
+                self.counters.evictions += 1;
+                if self.sketch.frequency(&key) <= self.sketch.frequency(&victim_key) {
+                    admit = false;
+                    break;
+                }
+                self.remove_entry(victim);
+            }
+
+            if admit {
+                self.insert_probation(key, value);
+            }
+        }
+    }
+
+    fn demote_protected(&mut self) {
+        while self.protected_weight > self.protected_capacity() {
+            let Some(&(key, _)) = self.protected.back() else {
+                break;
+            };
+            let (key, value) = self.remove_entry(self.map[&key]);
+            self.insert_probation(key, value);
+        }
+    }
+}
+
+impl Cache for TinyLfuCache {
+    fn new(size: usize) -> Self {
+        Self::new(size)
+    }
+
+    fn new_default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+
+    fn with_limits(limits: CacheLimits) -> Self {
+        Self::with_limits(limits)
+    }
+
+    fn get(&mut self, key: &Key) -> Option<Value> {
+        if let Some(value) = self.pinned.get(key) {
+            self.counters.hits += 1;
+            return Some(value);
+        }
+
+        // Misses are recorded as well, so that a key is already known when it is put
+        self.sketch.increment(key);
+
+        let Some(&slot) = self.map.get(key) else {
+            self.counters.misses += 1;
+            return None;
+        };
+        self.counters.hits += 1;
+
+        match slot {
+            Slot::Window(index) => {
+                self.window.move_to_front(index);
+                Some(self.window.get(index).1.clone())
+            }
+            Slot::Probation(index) => {
+                let (key, value) = self.remove_entry(Slot::Probation(index));
+                let cached = value.clone();
+                let weight = self.weight(&value);
+                self.main_weight += weight;
+                self.protected_weight += weight;
+                self.bytes += value.len();
+                let index = self.protected.push_front((key, value));
+                self.map.insert(key, Slot::Protected(index));
+                self.demote_protected();
+                Some(cached)
+            }
+            Slot::Protected(index) => {
+                self.protected.move_to_front(index);
+                Some(self.protected.get(index).1.clone())
+            }
+        }
+    }
+
+    fn put(&mut self, key: Key, value: Value) {
+        if !self.limits.admits(value.len()) {
+            return;
+        }
+        self.remove(&key);
+
+        self.window_weight += self.weight(&value);
+        self.bytes += value.len();
+        let index = self.window.push_front((key, value));
+        self.map.insert(key, Slot::Window(index));
+        self.admit_from_window();
+
+        // Regions are sized in one unit, the other limit is enforced on top
+        while self.limits.exceeded_by(self.map.len(), self.bytes) {
+            let Some(slot) = self.victim().or(self
+                .window
+                .back()
+                .and_then(|(key, _)| self.map.get(key).copied()))
+            else {
+                break;
+            };
+            self.remove_entry(slot);
+            self.counters.evictions += 1;
+        }
+    }
+
+    fn invalidate(&mut self) {
+        *self = TinyLfuCache {
+            counters: self.counters,
+            ..Self::with_limits(self.limits)
+        };
+    }
+
+    fn remove(&mut self, key: &Key) -> Option<Value> {
+        let slot = *self.map.get(key)?;
+        Some(self.remove_entry(slot).1)
+    }
+
+    fn pin(&mut self, key: Key, value: Value) {
+        self.pinned.pin(key, value);
+    }
+
+    fn unpin(&mut self, key: &Key) {
+        if let Some(value) = self.pinned.unpin(key) {
+            self.put(*key, value);
+        }
+    }
+
+    fn hot_keys(&self) -> Vec<Key> {
+        let protected = self.protected.iter().map(|(key, _)| *key);
+        let probation = self.probation.iter().map(|(key, _)| *key);
+        let window = self.window.iter().map(|(key, _)| *key);
+        protected.chain(probation).chain(window).collect()
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.map.len() + self.pinned.len(),
+            bytes: self.bytes + self.pinned.bytes(),
+            ..self.counters
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::tiny_lfu::TinyLfuCache;
+    use crate::cache::{Cache, CacheLimits};
+
+    #[test]
+    fn popular_entries_are_admitted() {
+        let mut cache = TinyLfuCache::new(100);
+        for _ in 0..10 {
+            cache.get(&(1, 1));
+        }
+        cache.put((1, 1), vec![1]);
+
+        // Keys that are only seen once can't displace the popular key
+        for i in 10..300 {
+            cache.get(&(i, 1));
+            cache.put((i, 1), vec![i as u8]);
+        }
+
+        assert_eq!(cache.get(&(1, 1)), Some(vec![1]));
+        assert!(cache.map.len() <= 100);
+    }
+
+    #[test]
+    fn frequency_sketch_ages() {
+        let mut cache = TinyLfuCache::new(16);
+        for _ in 0..10 {
+            cache.sketch.increment(&(1, 1));
+        }
+        assert_eq!(cache.sketch.frequency(&(1, 1)), 10);
+
+        // Reaching the sample size halves all counters
+        for _ in 10..cache.sketch.sample_size {
+            cache.sketch.increment(&(2, 1));
+        }
+        assert_eq!(cache.sketch.frequency(&(1, 1)), 5);
+        assert_eq!(cache.sketch.frequency(&(2, 1)), 7);
+    }
+
+    #[test]
+    fn evict_by_bytes() {
+        let mut cache = TinyLfuCache::with_limits(CacheLimits {
+            bytes: Some(100),
+            ..Default::default()
+        });
+        for i in 0..4 {
+            cache.put((i, 30), vec![i as u8; 30]);
+        }
+        assert_eq!(cache.bytes, 90);
+
+        for _ in 0..3 {
+            cache.get(&(9, 30));
+        }
+        cache.put((9, 30), vec![9; 30]);
+        assert!(cache.bytes <= 100);
+        assert_eq!(cache.get(&(9, 30)), Some(vec![9; 30]));
+    }
+}
@@ -1,23 +1,105 @@
+use crate::cache::CacheStats;
+#[cfg(feature = "write")]
 use crate::cache::Key;
-use crate::{IndexTable, IndexType};
+use crate::databases::clock::{Clock, SystemClock};
+use crate::databases::merge::MergeOperator;
+use crate::databases::secondary::Extractor;
+use crate::databases::tree::Tree;
+use crate::databases::watch::Subscriber;
+#[cfg(feature = "write")]
+use crate::IndexTable;
+use crate::{IndexFormat, IndexType};
+use std::ops::Bound;
 use std::path::PathBuf;
+use std::sync::Arc;
+#[cfg(feature = "write")]
+use std::time::Duration;
+
+#[cfg(feature = "garbage-collection")]
+use crate::garbage_collection::{AllocationMode, GcMode, GcPolicy, GcReport, SpaceStats};
 
 #[cfg(feature = "write")]
-use crate::transactions::Transaction;
+use crate::transactions::{batch::Batch, Transaction};
+
+/// Which values to load into the cache when a database is opened.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Preload {
+    /// Start with an empty cache.
+    #[default]
+    None,
+    /// Load values in file order until the cache starts evicting.
+    All,
+    /// Load the values of these keys, in order, until the cache starts evicting.
+    Keys(Vec<String>),
+    /// Write the keys held by the cache to a hot set file on every `persist`, and load them again
+    /// on the next open. Caches that don't report hot keys, see
+    /// [`Cache::hot_keys`](crate::cache::Cache::hot_keys), never write a hot set.
+    HotSet,
+}
 
 /// Configuration settings required to initialize a [`Database`].
 ///
 /// It provides customizable settings like the storage path, cache size, and the type of index.
 pub struct DatabaseSettings {
-    /// Path to the database's directory.
+    /// Path to the database's directory, ignored by databases in memory.
     pub path: Option<PathBuf>,
     /// Size of the cache.
     pub cache_size: Option<usize>,
+    /// Maximum total size of the cached values in bytes.
+    ///
+    /// Once this is set, `cache_size` only bounds the number of entries if it is set as well.
+    /// Without either of them the cache holds the default number of entries.
+    pub cache_bytes: Option<usize>,
+    /// Values larger than this many bytes are always read from disk and never cached.
+    pub cache_max_entry_bytes: Option<usize>,
     /// Type of the index table.
     pub index_type: IndexType,
+    /// Format of HashMap and BTreeMap index tables on disk, both formats are read.
+    ///
+    /// [`IndexFormat::Compact`] files are a fraction of the size, but readb 0.4.1 and earlier
+    /// open a database written in it with an empty index, and overwrite the index on their next
+    /// `persist`. Switch to it once no older version opens the database. To go back, open the
+    /// database with [`IndexFormat::Bincode`] and `persist` it before an older version opens it.
+    pub index_format: IndexFormat,
 
     /// Whether or not to create the path if it doesn't exist
     pub create_path: bool,
+
+    /// Which values to load into the cache on open.
+    pub preload: Preload,
+
+    /// False positive rate of a Bloom filter that is checked before the index, e.g. `0.01`, so
+    /// most lookups of missing keys don't need to search the index. `None` disables it.
+    ///
+    /// The filter is persisted next to the index and built from it if it is missing or out of
+    /// date, e.g. on the first open with a filter.
+    pub bloom_filter: Option<f64>,
+
+    /// Maximum size of a data segment in bytes, `None` keeps all values in a single data file.
+    ///
+    /// Once a segment is full, it is sealed and never written to again. Garbage collection then
+    /// works on individual segments, moving live data out of a sealed segment and deleting it.
+    ///
+    /// A segment holds at most 2^40 bytes (1 TiB), larger segment sizes are rejected. Writes that
+    /// don't fit into a segment, or into the single data file, fail.
+    pub segment_size: Option<u64>,
+
+    /// The time keys written with `put_with_ttl` expire by, the system time by default.
+    pub clock: Arc<dyn Clock>,
+
+    /// When to run garbage collection automatically, `None` disables it.
+    ///
+    /// An automatic collection persists the index table, committing all writes made before it.
+    #[cfg(feature = "garbage-collection")]
+    pub gc_policy: Option<GcPolicy>,
+    /// How garbage collection reclaims space, both when triggered manually and by the policy.
+    #[cfg(feature = "garbage-collection")]
+    pub gc_mode: GcMode,
+    /// Where `put` writes new values. Anything but [`AllocationMode::Append`] reuses dead regions
+    /// of the data file, tracked in a free list that is persisted next to the index. Segmented
+    /// databases always append, as sealed segments are immutable.
+    #[cfg(feature = "garbage-collection")]
+    pub allocation_mode: AllocationMode,
 }
 
 impl Default for DatabaseSettings {
@@ -25,12 +107,59 @@ impl Default for DatabaseSettings {
         DatabaseSettings {
             path: None,
             cache_size: None,
+            cache_bytes: None,
+            cache_max_entry_bytes: None,
             index_type: IndexType::HashMap,
+            index_format: IndexFormat::Bincode,
             create_path: false,
+            preload: Preload::None,
+            bloom_filter: None,
+            segment_size: None,
+            clock: Arc::new(SystemClock),
+            #[cfg(feature = "garbage-collection")]
+            gc_policy: None,
+            #[cfg(feature = "garbage-collection")]
+            gc_mode: GcMode::Compact,
+            #[cfg(feature = "garbage-collection")]
+            allocation_mode: AllocationMode::Append,
         }
     }
 }
 
+/// Counters and sizes of a [`Database`], see [`Database::stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DatabaseStats {
+    /// Hits, misses, evictions and size of the cache. A cache shared between handles reports the
+    /// totals of all handles.
+    pub cache: CacheStats,
+    /// Number of values loaded from the data files.
+    pub loader_reads: u64,
+    /// Total size of the values loaded from the data files in bytes.
+    pub bytes_read: u64,
+    /// Number of keys in the index. Expired keys are counted until `persist` or garbage
+    /// collection removes them.
+    pub index_entries: usize,
+    /// Total size of the data files in bytes.
+    pub data_file_size: u64,
+}
+
+/// The value found by a failed [`Database::compare_and_swap`], which left the key unchanged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompareAndSwapError {
+    /// The value of the key, `None` if it doesn't exist.
+    pub current: Option<Vec<u8>>,
+    /// The value that would have been written, `None` for a delete.
+    pub proposed: Option<Vec<u8>>,
+}
+
+impl std::fmt::Display for CompareAndSwapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Compare and swap failed, the current value differs")
+    }
+}
+
+impl std::error::Error for CompareAndSwapError {}
+
 pub trait Database: Send + Sync {
     /// Constructs a new `Database` instance with the specified settings.
     ///
@@ -62,6 +191,31 @@ pub trait Database: Send + Sync {
     /// - `None` if the key doesn't exist or data loading fails.
     fn get(&mut self, key: &str) -> anyhow::Result<Option<Vec<u8>>>;
 
+    /// Retrieves the keys starting with `prefix` and their values, sorted by key.
+    ///
+    /// Sorted index tables, like [`IndexType::BTreeMap`], only visit the matching keys, the
+    /// others scan the whole index. Values that fail to load are skipped.
+    fn scan_prefix(&mut self, prefix: &str) -> anyhow::Result<Vec<(String, Vec<u8>)>>;
+
+    /// Retrieves the keys between `start` and `end` and their values, sorted by key.
+    ///
+    /// Like [`Database::scan_prefix`], this only visits the keys in range if the index table is
+    /// sorted.
+    ///
+    /// # Example
+    /// ```
+    /// use readb::{Database, DefaultDatabase};
+    /// use std::ops::Bound;
+    ///
+    /// let mut db = DefaultDatabase::new_default("./test_db".into());
+    /// let users = db.range(Bound::Included("user:"), Bound::Excluded("user;")).unwrap();
+    /// ```
+    fn range(
+        &mut self,
+        start: Bound<&str>,
+        end: Bound<&str>,
+    ) -> anyhow::Result<Vec<(String, Vec<u8>)>>;
+
     /// Associates an existing key with a new key.
     ///
     /// This effectively creates an alias for the old key. Note, that removing the old key, will **not**
@@ -84,8 +238,128 @@ pub trait Database: Send + Sync {
     /// - `key`: The key to be removed.
     fn delete(&mut self, key: &str) -> anyhow::Result<()>;
 
+    /// Opens the tree `name`, a keyspace with an index table of its own, creating it if needed.
+    /// New trees get the index type of the database, existing ones keep theirs.
+    ///
+    /// Trees share the data files, the cache and `persist` with the database, so a single
+    /// directory can hold several datasets. Names may contain letters, digits, `_` and `-`.
+    /// Remote cloning only copies the default keyspace.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use readb::{Database, DefaultDatabase};
+    ///
+    /// let mut db = DefaultDatabase::new_default("./test_db".into());
+    /// let mut users = db.open_tree("users").unwrap();
+    /// let alice = users.get("alice").unwrap();
+    /// ```
+    ///
+    /// # Errors
+    /// Returns an error if the name is invalid or the tree can't be created.
+    fn open_tree(&mut self, name: &str) -> anyhow::Result<Box<dyn Tree + '_>>;
+
+    /// Opens the tree `name` like [`Database::open_tree`], creating it with `index_type` if
+    /// needed.
+    ///
+    /// # Errors
+    /// Returns an error if the tree exists with another index type.
+    fn open_tree_with_type(
+        &mut self,
+        name: &str,
+        index_type: IndexType,
+    ) -> anyhow::Result<Box<dyn Tree + '_>>;
+
+    /// Returns the names of all trees, sorted.
+    fn tree_names(&self) -> Vec<String>;
+
+    /// Registers the secondary index `name`, which indexes the values of the default keyspace by
+    /// the key `extractor` returns for them, for example a field of the value. The index is
+    /// updated by `put`, `link`, `delete`, batches and committed transactions, which then read the
+    /// previous value of a key from disk as well.
+    ///
+    /// Indexes are persisted along with the database, but extractors aren't, so register them
+    /// again after opening the database. An index is rebuilt if it is registered for the first
+    /// time, or if it wasn't persisted along with the latest changes of the database, e.g. as it
+    /// changed in between. Use a new name when the extractor changes.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use readb::{Database, DefaultDatabase};
+    ///
+    /// let mut db = DefaultDatabase::new_default("./test_db".into());
+    /// db.register_index(
+    ///     "email",
+    ///     Box::new(|value| {
+    ///         let value = std::str::from_utf8(value).ok()?;
+    ///         value.split(',').next().map(str::to_string)
+    ///     }),
+    /// )
+    /// .unwrap();
+    /// let users = db.get_by_index("email", "alice@example.com").unwrap();
+    /// ```
+    ///
+    /// # Errors
+    /// Returns an error if the name is invalid, or the index can't be built.
+    fn register_index(&mut self, name: &str, extractor: Extractor) -> anyhow::Result<()>;
+
+    /// Retrieves the keys and values of the records the secondary index `name` maps `key` to,
+    /// sorted by key.
+    ///
+    /// # Errors
+    /// Returns an error if no index named `name` was registered.
+    fn get_by_index(&mut self, name: &str, key: &str) -> anyhow::Result<Vec<(String, Vec<u8>)>>;
+
+    /// Registers the operator [`Database::merge`] combines values with, replacing the previous
+    /// one. Operators aren't persisted, register it again after opening a database with merge
+    /// operands that weren't folded yet.
+    fn register_merge_operator(&mut self, operator: MergeOperator);
+
+    /// Subscribes to the changes of the keys starting with `prefix` in the default keyspace, an
+    /// empty prefix watches all keys. Events are sent after `put`, `delete`, `link`, `merge`,
+    /// batches, committed transactions and the removal of expired keys, in the order the changes
+    /// were made. Changes of other handles aren't seen.
+    ///
+    /// The subscriber is an iterator that blocks until the next event, and a `Stream` with the
+    /// `async-watch` feature. Both end once the database is dropped.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use readb::{Database, DefaultDatabase, Event};
+    ///
+    /// let mut db = DefaultDatabase::new_default("./test_db".into());
+    /// let subscriber = db.watch_prefix("user:");
+    /// std::thread::spawn(move || {
+    ///     for event in subscriber {
+    ///         if let Event::Delete { key } = event {
+    ///             println!("{} was deleted", key);
+    ///         }
+    ///     }
+    /// });
+    /// ```
+    fn watch_prefix(&mut self, prefix: &str) -> Subscriber;
+
+    /// Keeps the value of `key` in the cache regardless of the eviction policy, so reading it
+    /// never touches the disk. The pin follows the key when it is overwritten or linked, and is
+    /// dropped when the key is deleted.
+    ///
+    /// # Errors
+    /// Returns an error if the key doesn't exist or its value cannot be loaded.
+    fn pin(&mut self, key: &str) -> anyhow::Result<()>;
+
+    /// Releases the pin of `key`, its value is then evicted like any other. Unpinning a key that
+    /// isn't pinned does nothing.
+    fn unpin(&mut self, key: &str) -> anyhow::Result<()>;
+
+    /// Reports cache counters, disk reads and the size of the index and data files.
+    ///
+    /// With the `metrics` feature the same values are also exported through the `metrics` crate.
+    fn stats(&self) -> DatabaseStats;
+
     /// Commits the current state of the database, ensuring data persistence.
     /// Note, this only commits the index table.
+    ///
+    /// With a `gc_policy` set, writes may be committed earlier: garbage collection triggered by
+    /// a write persists the index table as well.
     fn persist(&mut self) -> anyhow::Result<()>;
 
     /// Adds a new key-value pair to the database.
@@ -93,14 +367,146 @@ pub trait Database: Send + Sync {
     #[cfg(feature = "write")]
     fn put(&mut self, key: &str, value: &[u8]) -> anyhow::Result<()>;
 
+    /// Applies all operations of `batch`, across the default keyspace and any trees, or none of
+    /// them if one fails.
+    /// Note: This method is only available if the "write" feature is enabled.
+    ///
+    /// The values are written in one go, like those of a committed transaction.
+    #[cfg(feature = "write")]
+    fn apply_batch(&mut self, batch: Batch) -> anyhow::Result<()>;
+
+    /// Combines the value of `key` with `operand` using the registered merge operator.
+    /// Note: This method is only available if the "write" feature is enabled.
+    ///
+    /// Only the operand is written, the value isn't read. Reads fold the operands of a key into
+    /// its value, and garbage collection writes the folded value to reclaim the space of the
+    /// operands. Writing or deleting the key drops its operands. Two exceptions read the value
+    /// right away: merging into a missing key writes the folded value, and registered secondary
+    /// indexes need the new value. Trees don't support merging.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use readb::{Database, DefaultDatabase};
+    ///
+    /// let mut db = DefaultDatabase::new_default("./test_db".into());
+    /// db.register_merge_operator(Box::new(|_key, value, operand| {
+    ///     let mut value = value.map(<[u8]>::to_vec).unwrap_or_default();
+    ///     value.extend_from_slice(operand);
+    ///     Some(value)
+    /// }));
+    /// db.merge("events:alice", b"login;").unwrap();
+    /// db.merge("events:alice", b"logout;").unwrap();
+    /// ```
+    ///
+    /// # Errors
+    /// Returns an error if no merge operator is registered.
+    #[cfg(feature = "write")]
+    fn merge(&mut self, key: &str, operand: &[u8]) -> anyhow::Result<()>;
+
+    /// Adds a new key-value pair that expires once `ttl` has passed, according to
+    /// [`DatabaseSettings::clock`]. Expired keys read as missing and are deleted on the next
+    /// `persist` or garbage collection, which then reclaims their space.
+    /// Note: This method is only available if the "write" feature is enabled.
+    ///
+    /// Writing the key again without a time to live, or deleting it, removes its expiry. A link
+    /// to the key expires along with it. Trees don't support expiry.
+    #[cfg(feature = "write")]
+    fn put_with_ttl(&mut self, key: &str, value: &[u8], ttl: Duration) -> anyhow::Result<()>;
+
+    /// Replaces the value of `key` with `new` if it currently is `expected`. `None` stands for a
+    /// missing key, so `expected` `None` only creates the key and `new` `None` deletes it.
+    /// Note: This method is only available if the "write" feature is enabled.
+    ///
+    /// Read-modify-write methods are atomic, as they borrow the database mutably. To update it
+    /// from several threads, share it behind a `Mutex` or `RwLock`. The new value is written like
+    /// a `put`, so it doesn't expire.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use readb::{Database, DefaultDatabase};
+    ///
+    /// let mut db = DefaultDatabase::new_default("./test_db".into());
+    /// match db.compare_and_swap("leader", None, Some(b"node-1")).unwrap() {
+    ///     Ok(()) => println!("Elected"),
+    ///     Err(e) => println!("{:?} is the leader", e.current),
+    /// }
+    /// ```
+    ///
+    /// # Returns
+    /// - `Ok(())` if the value was replaced.
+    /// - `Err(CompareAndSwapError)` with the current value if it isn't `expected`.
+    #[cfg(feature = "write")]
+    fn compare_and_swap(
+        &mut self,
+        key: &str,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> anyhow::Result<Result<(), CompareAndSwapError>>;
+
+    /// Replaces the value of `key` with the value `f` computes from the current one, and returns
+    /// it. `None` stands for a missing key, so returning `None` deletes the key.
+    /// Note: This method is only available if the "write" feature is enabled.
+    ///
+    /// `f` is called exactly once, see [`Database::compare_and_swap`] for atomicity.
+    #[cfg(feature = "write")]
+    fn update_and_fetch<F>(&mut self, key: &str, f: F) -> anyhow::Result<Option<Vec<u8>>>
+    where
+        F: FnMut(Option<&[u8]>) -> Option<Vec<u8>>,
+        Self: Sized;
+
+    /// Adds `delta` to the counter stored at `key` and returns its previous value. A missing key
+    /// counts as 0, overflows wrap around.
+    /// Note: This method is only available if the "write" feature is enabled.
+    ///
+    /// Counters are stored as 8 byte little-endian integers, see [`Database::compare_and_swap`]
+    /// for atomicity.
+    ///
+    /// # Errors
+    /// Returns an error if the value of `key` isn't 8 bytes long.
+    #[cfg(feature = "write")]
+    fn fetch_add(&mut self, key: &str, delta: i64) -> anyhow::Result<i64>;
+
     /// Performs garbage collection on the database.
     /// Note: This method is only available if the "garbage-collection" feature is enabled.
     ///
     /// This method requires a full scan of the index table, and is therefore very slow. It is
     /// also not thread-safe.
+    ///
+    /// With [`GcMode::PunchHoles`], dead regions are deallocated in place instead, falling back
+    /// to compaction if the file system doesn't support it. Segmented databases always compact
+    /// segment by segment.
     #[cfg(feature = "garbage-collection")]
     fn gc(&mut self) -> anyhow::Result<()>;
 
+    /// Performs a single step of incremental garbage collection.
+    /// Note: This method is only available if the "garbage-collection" feature is enabled.
+    ///
+    /// Each step compacts a region of roughly `max_bytes` of live data, moving it towards the
+    /// start of the data file. Once a full pass over the file is completed, the file is truncated
+    /// and the returned report is marked as finished. In segmented databases, each step instead
+    /// moves the live data out of the sealed segment with the most dead bytes, and deletes it.
+    ///
+    /// Reads and writes can be freely interleaved with steps, which makes it suitable to run in
+    /// the background, e.g. between requests.
+    ///
+    /// The index table is persisted after every step.
+    #[cfg(feature = "garbage-collection")]
+    fn gc_step(&mut self, max_bytes: usize) -> anyhow::Result<GcReport>;
+
+    /// Returns the amount of live and dead bytes in the data file.
+    /// Note: This method is only available if the "garbage-collection" feature is enabled.
+    ///
+    /// Dead bytes are tracked as keys are overwritten or deleted, so this method is cheap.
+    #[cfg(feature = "garbage-collection")]
+    fn space_stats(&self) -> SpaceStats;
+
+    /// Returns the report of the last completed garbage collection, if any.
+    /// Note: This method is only available if the "garbage-collection" feature is enabled.
+    ///
+    /// This includes collections triggered automatically by the [`GcPolicy`].
+    #[cfg(feature = "garbage-collection")]
+    fn last_gc_report(&self) -> Option<GcReport>;
+
     /// Create a new transaction.
     /// Note: This method is only available if the "write" feature is enabled.
     ///
@@ -125,6 +531,7 @@ pub trait Database: Send + Sync {
     fn tx(&mut self) -> anyhow::Result<Box<dyn Transaction + '_>>;
 }
 
+#[cfg(feature = "write")]
 pub(crate) trait DatabaseTransactionsIO: Database {
     // Perform a snapshot of the index table
     fn snapshot(&self) -> Box<dyn IndexTable>;
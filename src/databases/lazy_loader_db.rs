@@ -1,23 +1,163 @@
-use crate::cache::Cache;
-use crate::databases::db_trait::{Database, DatabaseSettings};
-use crate::index_table::factory::IndexFactory;
+use crate::cache::{Cache, CacheLimits, DEFAULT_CAPACITY};
+use crate::databases::clock::{millis, Clock};
+#[cfg(feature = "write")]
+use crate::databases::db_trait::CompareAndSwapError;
+use crate::databases::db_trait::{Database, DatabaseSettings, DatabaseStats, Preload};
+use crate::databases::merge::{MergeOperator, Operands};
+use crate::databases::observability;
+use crate::databases::secondary::{Extractor, SecondaryIndex};
+use crate::databases::tree::{Tree, TreeHandle};
+use crate::databases::watch::{Event, Subscriber, Subscribers};
+use crate::index_table::bloom::BloomIndexTable;
+use crate::index_table::btree::BTreeMapIndexTable;
+use crate::index_table::factory::{IndexFactory, IndexFormat, IndexType};
+use crate::index_table::hash_map::HashMapIndexTable;
+#[cfg(feature = "garbage-collection")]
+use crate::io::loader::segment_of;
 use crate::io::loader::LazyLoader;
 use crate::io::Loader;
 use crate::IndexTable;
 use std::fs;
-use std::path::PathBuf;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-#[cfg(feature = "write")]
 use crate::cache::Key;
 #[cfg(feature = "write")]
 use crate::databases::db_trait::DatabaseTransactionsIO;
 #[cfg(feature = "write")]
-use crate::transactions::{simple::SimpleTransaction, Transaction};
-#[cfg(feature = "write")]
+use crate::transactions::{batch::Batch, simple::SimpleTransaction, Transaction};
 use anyhow::bail;
 
 #[cfg(feature = "garbage-collection")]
-use crate::garbage_collection::compact_file;
+use crate::garbage_collection::{
+    collect_runs, compact_file, AllocationMode, CompactionCursor, FreeList, GcMode, GcPolicy,
+    GcReport, Reference, SpaceStats, SpaceTracker, Table,
+};
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "garbage-collection")]
+use std::io::ErrorKind;
+#[cfg(feature = "write")]
+use std::time::Duration;
+#[cfg(feature = "garbage-collection")]
+use std::time::Instant;
+
+// Amount of live data moved per step when garbage collection is triggered by the policy
+#[cfg(feature = "garbage-collection")]
+const AUTO_GC_STEP_SIZE: usize = 4 * 1024 * 1024;
+#[cfg(feature = "garbage-collection")]
+const FREE_LIST_NAME: &str = ".rdb.free";
+const HOT_SET_NAME: &str = ".rdb.hot";
+const BLOOM_FILTER_NAME: &str = ".rdb.bloom";
+const TREES_NAME: &str = ".rdb.trees";
+const INDEXES_NAME: &str = ".rdb.indexes";
+const GENERATION_NAME: &str = ".rdb.generation";
+const EXPIRY_NAME: &str = ".rdb.expiry";
+const OPERANDS_NAME: &str = ".rdb.operands";
+
+// Creates the cache described by the cache settings
+fn cache_from_settings<C: Cache>(settings: &DatabaseSettings) -> C {
+    match (
+        settings.cache_size,
+        settings.cache_bytes,
+        settings.cache_max_entry_bytes,
+    ) {
+        (Some(size), None, None) => C::new(size),
+        (None, None, None) => C::new_default(),
+        (entries, bytes, max_entry_bytes) => C::with_limits(CacheLimits {
+            // A bound on single values alone keeps the default number of entries
+            entries: entries.or(bytes.is_none().then_some(DEFAULT_CAPACITY)),
+            bytes,
+            max_entry_bytes,
+        }),
+    }
+}
+
+// A keyspace opened with `open_tree`, it shares the data files and the cache with the database
+struct Keyspace {
+    index_table: Box<dyn IndexTable>,
+    index_type: IndexType,
+}
+
+// The index tables a database is opened with, besides the secondary indexes
+struct Tables {
+    keyspace: Keyspace,
+    trees: HashMap<String, Keyspace>,
+    expiry: HashMapIndexTable,
+    operands: BTreeMapIndexTable,
+}
+
+// Every key of any keyspace and of the merge operands, and the location it references
+#[cfg(feature = "garbage-collection")]
+fn references(
+    index_table: &dyn IndexTable,
+    trees: &HashMap<String, Keyspace>,
+    operands: &dyn IndexTable,
+) -> Vec<(Reference, Key)> {
+    let keyspaces = std::iter::once((None, index_table)).chain(
+        trees
+            .iter()
+            .map(|(name, tree)| (Some(name.as_str()), tree.index_table.as_ref())),
+    );
+    let mut references = Vec::new();
+    for (tree, table) in keyspaces {
+        references.extend(
+            table
+                .all_key_values()
+                .into_iter()
+                .map(|(key, location)| (Reference::keyspace(tree, &key), location)),
+        );
+    }
+    references.extend(
+        operands
+            .all_key_values()
+            .into_iter()
+            .map(|(entry, location)| (Reference::operand(&entry), location)),
+    );
+    references
+}
+
+// The new locations of `extents` after the data at `from` was copied to `to`
+#[cfg(feature = "garbage-collection")]
+fn shift(extents: &[Key], from: u64, to: u64) -> Vec<(Key, Key)> {
+    extents
+        .iter()
+        .map(|&(offset, length)| ((offset, length), (to + offset - from, length)))
+        .collect()
+}
+
+// The generation stored at `path`, if there is a valid one
+fn read_generation(path: &Path) -> Option<u64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+// Tree and index names are used as directory names
+fn check_name(name: &str) -> anyhow::Result<()> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    if name.is_empty() || !name.chars().all(valid) {
+        bail!("Invalid name {:?}, use letters, digits, '_' and '-'", name);
+    }
+    Ok(())
+}
+
+// Opens the keyspaces stored in `path`, each in a directory of its own
+fn load_trees(path: &Path, format: IndexFormat) -> anyhow::Result<HashMap<String, Keyspace>> {
+    let mut trees = HashMap::new();
+    let Ok(entries) = fs::read_dir(path) else {
+        return Ok(trees);
+    };
+    for entry in entries {
+        let entry = entry?;
+        let mut index_factory = IndexFactory::new(IndexType::Auto).with_format(format);
+        let index_table = index_factory.load(entry.path())?;
+        let keyspace = Keyspace {
+            index_table,
+            index_type: index_factory.index_type(),
+        };
+        trees.insert(entry.file_name().to_string_lossy().into_owned(), keyspace);
+    }
+    Ok(trees)
+}
 
 /// The main database structure.
 ///
@@ -30,6 +170,58 @@ pub struct LLDatabase<C: Cache> {
     index_table: Box<dyn IndexTable>,
     cache: C,
     loader: LazyLoader,
+    // Used to write the index table when saving the database to another directory
+    index_type: IndexType,
+    // How the index tables are written, see `DatabaseSettings::index_format`
+    index_format: IndexFormat,
+    // Pinned keys and the location their pin is held for
+    pinned: HashMap<String, Key>,
+    // Where the keys held by the cache are written on persist, if the hot set is preloaded
+    hot_set_path: Option<PathBuf>,
+    // Keyspaces opened with `open_tree`, and the directory they are stored in
+    trees: HashMap<String, Keyspace>,
+    trees_path: Option<PathBuf>,
+    // Secondary indexes registered since the database was opened, and the directory they are
+    // stored in. Indexes on disk that weren't registered yet are dropped on the first change, as
+    // they can't be kept up to date
+    indexes: HashMap<String, SecondaryIndex>,
+    indexes_path: Option<PathBuf>,
+    unregistered_indexes: Vec<String>,
+    // Counts how often changes of the default keyspace were persisted. Every index on disk is
+    // stored with the generation it was built for, and only trusted if it's the current one.
+    // `None` if it isn't known, as for databases written before generations were stored
+    generation: Option<u64>,
+    generation_path: Option<PathBuf>,
+    // Whether the default keyspace changed since it was last persisted
+    changed: bool,
+    // The deadline of each key of the default keyspace that expires, in milliseconds since the
+    // Unix epoch, stored as `(deadline, 0)`
+    expiry: HashMapIndexTable,
+    clock: Arc<dyn Clock>,
+    // Merge operands of the default keyspace, folded into the values on reads and garbage
+    // collection, and the operator folding them
+    operands: Operands,
+    merge_operator: Option<MergeOperator>,
+    // Subscribers to the changes of the default keyspace
+    subscribers: Subscribers,
+
+    #[cfg(feature = "garbage-collection")]
+    space: SpaceTracker,
+    #[cfg(feature = "garbage-collection")]
+    compaction: Option<CompactionCursor>,
+    #[cfg(feature = "garbage-collection")]
+    gc_policy: Option<GcPolicy>,
+    #[cfg(feature = "garbage-collection")]
+    gc_mode: GcMode,
+    // Dead bytes that were already deallocated by punching holes
+    #[cfg(feature = "garbage-collection")]
+    punched_bytes: u64,
+    #[cfg(feature = "garbage-collection")]
+    last_gc: Option<(Instant, GcReport)>,
+    #[cfg(feature = "garbage-collection")]
+    allocation_mode: AllocationMode,
+    #[cfg(feature = "garbage-collection")]
+    free_list: FreeList,
 }
 
 impl<C: Cache> LLDatabase<C> {
@@ -40,152 +232,1749 @@ impl<C: Cache> LLDatabase<C> {
     pub fn unwrap(self) -> LLDatabase<C> {
         self
     }
-}
 
-impl<C: Cache + Send + Sync> Database for LLDatabase<C> {
-    fn new(settings: DatabaseSettings) -> Self {
-        if settings.path.is_none() {
-            panic!("Path is required");
+    // The index table of the keyspace `tree`, `None` is the default keyspace
+    fn table(&self, tree: Option<&str>) -> &dyn IndexTable {
+        match tree {
+            Some(name) => self.trees[name].index_table.as_ref(),
+            None => self.index_table.as_ref(),
         }
+    }
 
-        let path = settings.path.unwrap();
-        if !path.exists() && settings.create_path {
-            fs::create_dir(path.clone()).unwrap();
+    fn table_mut(&mut self, tree: Option<&str>) -> &mut Box<dyn IndexTable> {
+        match tree {
+            Some(name) => &mut self.trees.get_mut(name).unwrap().index_table,
+            None => &mut self.index_table,
         }
+    }
 
-        // path has to be a dictionary
-        #[cfg(not(feature = "ignore-path-check"))]
-        if !path.is_dir() {
-            panic!("Path must be a directory");
+    // Every location referenced by a key of any keyspace, once per key
+    fn locations(&self) -> Vec<Key> {
+        std::iter::once(&self.index_table)
+            .chain(self.trees.values().map(|tree| &tree.index_table))
+            .chain(std::iter::once(&self.operands.index_table))
+            .flat_map(|table| table.all_key_values())
+            .map(|(_, key)| key)
+            .collect()
+    }
+
+    // Reads a value through the cache, `None` if it can't be loaded
+    fn value_at(&mut self, index: Key) -> Option<Vec<u8>> {
+        let cached = self.cache.get(&index);
+        observability::record_get(cached.is_some());
+        if cached.is_some() {
+            return cached;
         }
 
-        let index_table = IndexFactory::new(settings.index_type)
-            .load_or_create(path.clone())
-            .unwrap();
-        let cache = if settings.cache_size.is_some() {
-            C::new(settings.cache_size.unwrap())
-        } else {
-            C::new_default()
+        let (offset, length) = index;
+        let d = match self.loader.load(offset, length) {
+            Ok(d) => d,
+            Err(_error) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(offset, length, error = ?_error, "Error loading data");
+                return None;
+            }
         };
+        observability::record_load(d.len());
 
-        Self {
-            index_table,
-            cache,
-            loader: LazyLoader::new(path.join("./.rdb.data")),
+        self.cache.put(index, d.clone());
+        Some(d)
+    }
+
+    // Reads the value of `key` of the default keyspace to update it. Unlike `get`, a value that
+    // can't be loaded is an error rather than missing
+    #[cfg(feature = "write")]
+    fn current_value(&mut self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let Some(index) = self.index_table.try_get(key)? else {
+            return Ok(None);
+        };
+        if self.is_expired(key) {
+            return Ok(None);
         }
+        let value = match self.cache.get(&index) {
+            Some(value) => value,
+            None => self.loader.load(index.0, index.1)?,
+        };
+        let operands = self.operands.of(key);
+        self.fold(key, Some(value), &operands)
     }
 
-    fn new_default(location: PathBuf) -> Self {
-        Self::new(DatabaseSettings {
-            path: Some(location),
-            ..Default::default()
-        })
+    fn values_of(&mut self, key_values: Vec<(String, Key)>) -> Vec<(String, Vec<u8>)> {
+        key_values
+            .into_iter()
+            .filter_map(|(key, index)| Some((key, self.value_at(index)?)))
+            .collect()
     }
 
-    fn get(&mut self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
-        let index = self.index_table.get(key);
-        if index.is_none() {
-            return Ok(None);
+    // Applies the merge operands at `operands` to the value of `key`, oldest first
+    fn fold(
+        &mut self,
+        key: &str,
+        mut value: Option<Vec<u8>>,
+        operands: &[Key],
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        if operands.is_empty() {
+            return Ok(value);
         }
-        let index = index.unwrap();
 
-        let cached = self.cache.get(&index);
-        if cached.is_some() {
-            return Ok(cached);
+        let mut loaded = Vec::with_capacity(operands.len());
+        for &operand in operands {
+            let Some(operand) = self.value_at(operand) else {
+                bail!("Merge operand of {} can't be loaded", key);
+            };
+            loaded.push(operand);
         }
+        let Some(operator) = &self.merge_operator else {
+            bail!(
+                "Key {} has merge operands, but no merge operator is registered",
+                key
+            );
+        };
+        for operand in loaded {
+            value = operator(key, value.as_deref(), &operand);
+        }
+        Ok(value)
+    }
 
-        let (offset, length) = index;
-        let d = self.loader.load(offset, length);
-        if d.is_err() {
-            println!("Error loading data: {:?}", d.err());
-            return Ok(None);
+    // Folds the merge operands into the values of a scan of the default keyspace
+    fn fold_all(
+        &mut self,
+        values: Vec<(String, Vec<u8>)>,
+    ) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+        if self.operands.is_empty() {
+            return Ok(values);
+        }
+        let mut folded = Vec::with_capacity(values.len());
+        for (key, value) in values {
+            let operands = self.operands.of(&key);
+            if let Some(value) = self.fold(&key, Some(value), &operands)? {
+                folded.push((key, value));
+            }
         }
-        let d = d.unwrap();
+        Ok(folded)
+    }
 
-        self.cache.put(index, d.clone());
-        Ok(Some(d))
+    // Whether `key` of the default keyspace expired, it still takes space until it is removed
+    fn is_expired(&self, key: &str) -> bool {
+        match self.expiry.get(key) {
+            Some((deadline, _)) => deadline <= millis(self.clock.now()),
+            None => false,
+        }
     }
 
-    fn link(&mut self, old: &str, new: &str) -> anyhow::Result<()> {
-        let index = self.index_table.get(old);
-        if index.is_none() {
-            return Err(anyhow::anyhow!("Key not found"));
+    // Drops the expired keys from a scan of the default keyspace
+    fn unexpired(&self, key_values: Vec<(String, Key)>) -> Vec<(String, Key)> {
+        if self.expiry.is_empty() {
+            return key_values;
         }
-        let index = index.unwrap();
-        self.index_table.insert(new, index)
+        key_values
+            .into_iter()
+            .filter(|(key, _)| !self.is_expired(key))
+            .collect()
     }
 
-    fn delete(&mut self, key: &str) -> anyhow::Result<()> {
-        self.index_table.delete(key)
+    // Moves the pin of `key` to its current value, or drops it if the key was deleted
+    fn refresh_pin(&mut self, key: &str) -> anyhow::Result<()> {
+        let Some(&previous) = self.pinned.get(key) else {
+            return Ok(());
+        };
+        let current = self.index_table.get(key);
+        if current == Some(previous) {
+            return Ok(());
+        }
+
+        self.unpin_location(previous);
+        self.repin(key.to_string(), current)
     }
 
-    fn persist(&mut self) -> anyhow::Result<()> {
+    // Unpinning caches the value again, but the location may hold other data by now
+    fn unpin_location(&mut self, location: Key) {
+        self.cache.unpin(&location);
+        self.cache.remove(&location);
+    }
+
+    // Pins the value of `key` at its new location, `None` if it was deleted
+    fn repin(&mut self, key: String, current: Option<Key>) -> anyhow::Result<()> {
+        match current {
+            Some((offset, length)) => {
+                let value = self.loader.load(offset, length)?;
+                self.cache.pin((offset, length), value);
+                self.pinned.insert(key, (offset, length));
+            }
+            None => {
+                self.pinned.remove(&key);
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(any(feature = "write", feature = "garbage-collection"))]
+    fn refresh_pins(&mut self) -> anyhow::Result<()> {
+        // All outdated pins are dropped before any is added again, as a key may have moved to the
+        // previous location of another one
+        let moved: Vec<(String, Key, Option<Key>)> = self
+            .pinned
+            .iter()
+            .filter_map(|(key, &previous)| {
+                let current = self.index_table.get(key);
+                (current != Some(previous)).then(|| (key.clone(), previous, current))
+            })
+            .collect();
+        for (_, previous, _) in &moved {
+            self.unpin_location(*previous);
+        }
+        for (key, _, current) in moved {
+            self.repin(key, current)?;
+        }
+        Ok(())
+    }
+
+    // Remembers the keys held by the cache, so the next open can preload them. An empty cache
+    // keeps the previous hot set, keys that moved since are skipped when preloading
+    fn persist_hot_set(&self) -> anyhow::Result<()> {
+        if let Some(path) = &self.hot_set_path {
+            let keys = self.cache.hot_keys();
+            if !keys.is_empty() {
+                fs::write(path, bincode::serialize(&keys)?)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Indexes on disk that weren't registered can't be kept up to date once the default keyspace
+    // changes
+    fn drop_unregistered_indexes(&mut self) -> anyhow::Result<()> {
+        if let Some(directory) = &self.indexes_path {
+            for name in self.unregistered_indexes.drain(..) {
+                fs::remove_dir_all(directory.join(name))?;
+            }
+        }
+        Ok(())
+    }
+
+    // Flushes the data and the index table, without evaluating the garbage collection policy
+    fn persist_files(&mut self) -> anyhow::Result<()> {
+        // The secondary indexes are written first, with the generation the default keyspace is
+        // written as. They're rebuilt if it doesn't make it to disk. Indexes on disk can't be
+        // checked in a database without a generation, so they're dropped before it gets one
+        let next = match (self.generation, self.changed) {
+            (Some(generation), false) => generation,
+            (Some(generation), true) => generation + 1,
+            (None, _) => {
+                self.drop_unregistered_indexes()?;
+                1
+            }
+        };
+        for (name, index) in &self.indexes {
+            index.index_table.persist()?;
+            if let Some(directory) = &self.indexes_path {
+                fs::write(directory.join(name).join(GENERATION_NAME), next.to_string())?;
+            }
+        }
+
         self.index_table.persist()?;
+        for tree in self.trees.values() {
+            tree.index_table.persist()?;
+        }
+        self.expiry.persist()?;
+        self.operands.index_table.persist()?;
 
-        #[cfg(feature = "write")]
+        #[cfg(any(feature = "write", feature = "garbage-collection"))]
         self.loader.persist()?;
 
+        if self.generation != Some(next) {
+            if let Some(path) = &self.generation_path {
+                fs::write(path, next.to_string())?;
+            }
+            self.generation = Some(next);
+        }
+        self.changed = false;
+
+        // Now that the index on disk no longer references them, released regions can be reused.
+        // Their cached values were already dropped when they were released
+        #[cfg(feature = "garbage-collection")]
+        if self.allocation_mode != AllocationMode::Append {
+            self.free_list.promote(self.compaction.map(|c| c.write));
+            self.free_list.persist()?;
+        }
+
         Ok(())
     }
 
+    // Fails before any data is written if the keys of `tree` can't be changed
     #[cfg(feature = "write")]
-    fn put(&mut self, key: &str, value: &[u8]) -> anyhow::Result<()> {
-        let index = self.loader.add(value)?;
-        self.index_table.insert(key, index)
+    fn check_writable(&self, tree: Option<&str>) -> anyhow::Result<()> {
+        if !self.table(tree).is_writable() {
+            bail!("The index table is read-only");
+        }
+        Ok(())
+    }
+
+    // Writes a value into a free region if possible, appends it otherwise
+    #[cfg(feature = "write")]
+    fn write_value(&mut self, value: &[u8]) -> anyhow::Result<Key> {
+        #[cfg(feature = "garbage-collection")]
+        if let Some(offset) = self.free_list.allocate(value.len(), self.allocation_mode) {
+            self.loader.write_at(offset, value)?;
+            return Ok((offset, value.len()));
+        }
+
+        self.loader.add(value)
     }
 
+    // Updates the space accounting after the index entry `previous` was replaced by `current`
     #[cfg(feature = "garbage-collection")]
-    fn gc(&mut self) -> anyhow::Result<()> {
-        self.loader.read_and_replace(|data| {
-            let keys = self.index_table.all_key_values();
-            let (new_keys, new_data) = compact_file(keys, data);
-            self.index_table.replace_all(new_keys)?;
+    fn track(&mut self, reference: Reference, previous: Option<Key>, current: Option<Key>) {
+        if let Some(dead) = self.space.replace(reference, previous, current) {
+            // Cached values are keyed by location, which will be overwritten by other data
+            self.cache.remove(&dead);
+            if self.allocation_mode != AllocationMode::Append {
+                self.free_list.release(dead);
+            }
+        }
+    }
 
-            Ok(new_data)
-        })
+    #[cfg(feature = "garbage-collection")]
+    fn rebuild_space_tracker(&mut self) {
+        self.space = SpaceTracker::from_keys(references(
+            self.index_table.as_ref(),
+            &self.trees,
+            self.operands.index_table.as_ref(),
+        ));
     }
 
-    #[cfg(feature = "write")]
-    fn tx(&mut self) -> anyhow::Result<Box<dyn Transaction + '_>> {
-        Ok(Box::new(SimpleTransaction::new(self)))
+    // Points every key referencing a moved extent to its new location
+    #[cfg(feature = "garbage-collection")]
+    fn apply_moves(&mut self, moves: Vec<(Key, Key)>) -> anyhow::Result<()> {
+        if moves.is_empty() {
+            return Ok(());
+        }
+
+        // The space tracker still knows the keys of the old locations
+        for (from, to) in &moves {
+            for reference in self.space.references(*from).to_vec() {
+                let table = match &reference.table {
+                    Table::Default => &mut self.index_table,
+                    Table::Tree(name) => &mut self.trees.get_mut(name).unwrap().index_table,
+                    Table::Operands => &mut self.operands.index_table,
+                };
+                table.insert(&reference.key, *to)?;
+            }
+        }
+        self.space.relocate(&moves);
+        self.relocate_cached(&moves)
+    }
+
+    // Moves cached values along with their data. All of them are taken out first, as the new
+    // location of one value can be the old location of another
+    #[cfg(feature = "garbage-collection")]
+    fn relocate_cached(&mut self, moves: &[(Key, Key)]) -> anyhow::Result<()> {
+        let values: Vec<(Key, Option<Vec<u8>>)> = moves
+            .iter()
+            .map(|(from, to)| (*to, self.cache.remove(from)))
+            .collect();
+        for (to, value) in values {
+            if let Some(value) = value {
+                self.cache.put(to, value);
+            }
+        }
+        self.refresh_pins()
     }
 }
 
-#[cfg(feature = "write")]
-impl<C: Cache + Send + Sync> DatabaseTransactionsIO for LLDatabase<C> {
-    fn snapshot(&self) -> Box<dyn IndexTable> {
-        self.index_table.snapshot()
+impl<C: Cache + Send + Sync> LLDatabase<C> {
+    /// Constructs a database that uses the given cache instead of creating one from the
+    /// cache settings.
+    ///
+    /// This allows handles of the same database, for example one per thread, to share a cache
+    /// like an `Arc<ShardedCache<_>>`. Only share a cache between handles that read the same
+    /// data files.
+    pub fn with_cache(settings: DatabaseSettings, cache: C) -> Self {
+        if settings.path.is_none() {
+            panic!("Path is required");
+        }
+
+        let path = settings.path.clone().unwrap();
+        if !path.exists() && settings.create_path {
+            fs::create_dir(path.clone()).unwrap();
+        }
+
+        // path has to be a dictionary
+        #[cfg(not(feature = "ignore-path-check"))]
+        if !path.is_dir() {
+            panic!("Path must be a directory");
+        }
+
+        let format = settings.index_format;
+        let mut index_factory = IndexFactory::new(settings.index_type).with_format(format);
+        let index_table = index_factory.load_or_create(path.clone()).unwrap();
+        let trees = load_trees(&path.join(TREES_NAME), format).unwrap();
+        let expiry_path = path.join(EXPIRY_NAME);
+        let expiry = match expiry_path.exists() {
+            true => HashMapIndexTable::new(expiry_path).unwrap(),
+            false => HashMapIndexTable::new_default(expiry_path).unwrap(),
+        }
+        .with_format(format);
+        let operands_path = path.join(OPERANDS_NAME);
+        let operands = match operands_path.exists() {
+            true => BTreeMapIndexTable::new(operands_path).unwrap(),
+            false => BTreeMapIndexTable::new_default(operands_path).unwrap(),
+        }
+        .with_format(format);
+        let loader = LazyLoader::new(path.clone(), settings.segment_size);
+
+        let keyspace = Keyspace {
+            index_table,
+            index_type: index_factory.index_type(),
+        };
+        let tables = Tables {
+            keyspace,
+            trees,
+            expiry,
+            operands,
+        };
+        Self::open(settings, cache, tables, loader, Some(path))
     }
 
-    fn merge_file(&mut self, new_content: &[u8]) -> anyhow::Result<u64> {
-        Ok(self.loader.add(new_content)?.0)
+    /// Constructs an empty database that keeps its data and index table in memory, for tests
+    /// and ephemeral data. `path` is ignored, everything else behaves like a database on disk.
+    ///
+    /// Use [`LLDatabase::save_to`] to write it to a directory.
+    pub fn in_memory(settings: DatabaseSettings) -> Self {
+        let cache = cache_from_settings(&settings);
+        let mut index_factory = IndexFactory::new(settings.index_type);
+        let index_table = index_factory.in_memory();
+        let loader = LazyLoader::in_memory(settings.segment_size);
+
+        let keyspace = Keyspace {
+            index_table,
+            index_type: index_factory.index_type(),
+        };
+        let tables = Tables {
+            keyspace,
+            trees: HashMap::new(),
+            expiry: HashMapIndexTable::in_memory(),
+            operands: BTreeMapIndexTable::in_memory(),
+        };
+        Self::open(settings, cache, tables, loader, None)
     }
 
-    fn merge_index_table(
-        &mut self,
-        backup: Box<dyn IndexTable>,
-        new_key_values: Vec<(String, Key)>,
-    ) -> anyhow::Result<()> {
-        for (k, v) in new_key_values {
-            match self.index_table.insert(k.as_str(), v) {
-                Ok(_) => (),
-                Err(e) => {
-                    self.rollback(backup).unwrap_or_else(|e2| {
-                        panic!(
-                            "[FATAL] Failed to apply rollback: {}\nOriginal Error: {}",
-                            e2, e
-                        )
-                    });
-                    bail!("Failed to merge index tables: {}", e);
+    /// Reads the database in the directory at `path` into memory, changes are never written
+    /// back. `settings.path` is ignored.
+    ///
+    /// # Errors
+    /// Returns an error if there is no database at `path`, or its index type doesn't match.
+    pub fn load_from<P: AsRef<Path>>(path: P, settings: DatabaseSettings) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let mut index_factory = IndexFactory::new(settings.index_type);
+        let on_disk = index_factory.load(path.to_path_buf())?;
+        let mut index_table = index_factory.in_memory();
+        index_table.replace_all(on_disk.all_key_values())?;
+        let keyspace = Keyspace {
+            index_table,
+            index_type: index_factory.index_type(),
+        };
+
+        let mut trees = load_trees(&path.join(TREES_NAME), settings.index_format)?;
+        for tree in trees.values_mut() {
+            let mut index_factory = IndexFactory::new(tree.index_type);
+            let mut index_table = index_factory.in_memory();
+            index_table.replace_all(tree.index_table.all_key_values())?;
+            tree.index_table = index_table;
+            tree.index_type = index_factory.index_type();
+        }
+        let mut expiry = HashMapIndexTable::in_memory();
+        if path.join(EXPIRY_NAME).exists() {
+            let on_disk = HashMapIndexTable::new(path.join(EXPIRY_NAME))?;
+            expiry.replace_all(on_disk.all_key_values())?;
+        }
+        let mut operands = BTreeMapIndexTable::in_memory();
+        if path.join(OPERANDS_NAME).exists() {
+            let on_disk = BTreeMapIndexTable::new(path.join(OPERANDS_NAME))?;
+            operands.replace_all(on_disk.all_key_values())?;
+        }
+        let loader = LazyLoader::load_into_memory(path, settings.segment_size)?;
+
+        let cache = cache_from_settings(&settings);
+        let tables = Tables {
+            keyspace,
+            trees,
+            expiry,
+            operands,
+        };
+        Ok(Self::open(settings, cache, tables, loader, None))
+    }
+
+    /// Writes the database in the on-disk format to the directory at `path`, which is created
+    /// if needed. The copy can be opened like any database, or read with
+    /// [`LLDatabase::load_from`]. Databases on disk are copied as well, including changes that
+    /// weren't persisted yet.
+    ///
+    /// # Errors
+    /// Returns an error if the directory isn't empty or can't be written.
+    pub fn save_to<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        fs::create_dir_all(path)?;
+        if fs::read_dir(path)?.next().is_some() {
+            bail!("Directory {} is not empty", path.display());
+        }
+
+        self.loader.save_to(path)?;
+        for (name, tree) in &self.trees {
+            let tree_path = path.join(TREES_NAME).join(name);
+            fs::create_dir_all(&tree_path)?;
+            IndexFactory::new(tree.index_type)
+                .with_format(self.index_format)
+                .write(tree_path, tree.index_table.all_key_values())?;
+        }
+        if !self.expiry.is_empty() {
+            let mut expiry = HashMapIndexTable::new_default(path.join(EXPIRY_NAME))?
+                .with_format(self.index_format);
+            expiry.replace_all(self.expiry.all_key_values())?;
+            expiry.persist()?;
+        }
+        if !self.operands.is_empty() {
+            let mut operands = BTreeMapIndexTable::new_default(path.join(OPERANDS_NAME))?
+                .with_format(self.index_format);
+            operands.replace_all(self.operands.index_table.all_key_values())?;
+            operands.persist()?;
+        }
+        IndexFactory::new(self.index_type)
+            .with_format(self.index_format)
+            .write(path.to_path_buf(), self.index_table.all_key_values())
+    }
+
+    // Assembles a database, `directory` is `None` for databases in memory
+    fn open(
+        settings: DatabaseSettings,
+        cache: C,
+        tables: Tables,
+        loader: LazyLoader,
+        directory: Option<PathBuf>,
+    ) -> Self {
+        let Tables {
+            keyspace,
+            trees,
+            expiry,
+            operands,
+        } = tables;
+        let operands = Operands::new(Box::new(operands));
+        let Keyspace {
+            index_table,
+            index_type,
+        } = keyspace;
+        let bloom_path = directory
+            .as_ref()
+            .map(|directory| directory.join(BLOOM_FILTER_NAME));
+        let index_table: Box<dyn IndexTable> = match settings.bloom_filter {
+            Some(rate) => Box::new(BloomIndexTable::open(index_table, rate, bloom_path)),
+            None => {
+                // The index might change without the filter, which must be rebuilt then
+                if let Some(path) = bloom_path.filter(|path| path.exists()) {
+                    let _ = fs::remove_file(path);
                 }
+                index_table
+            }
+        };
+
+        #[cfg(feature = "garbage-collection")]
+        let space = SpaceTracker::from_keys(references(
+            index_table.as_ref(),
+            &trees,
+            operands.index_table.as_ref(),
+        ));
+
+        #[cfg(feature = "garbage-collection")]
+        let allocation_mode = match loader.is_segmented() {
+            true => AllocationMode::Append,
+            false => settings.allocation_mode,
+        };
+        #[cfg(feature = "garbage-collection")]
+        let free_list_path = directory
+            .as_ref()
+            .map(|directory| directory.join(FREE_LIST_NAME));
+        #[cfg(feature = "garbage-collection")]
+        let free_list = match allocation_mode {
+            AllocationMode::Append => FreeList::new(free_list_path),
+            _ => FreeList::load(free_list_path, &space.dead_zones(&loader.segments())),
+        };
+
+        let hot_set_path = match settings.preload {
+            Preload::HotSet => directory
+                .as_ref()
+                .map(|directory| directory.join(HOT_SET_NAME)),
+            _ => None,
+        };
+
+        let mut database = Self {
+            index_table,
+            cache,
+            loader,
+            index_type,
+            index_format: settings.index_format,
+            pinned: HashMap::new(),
+            hot_set_path,
+            trees,
+            trees_path: directory
+                .as_ref()
+                .map(|directory| directory.join(TREES_NAME)),
+            indexes: HashMap::new(),
+            unregistered_indexes: directory
+                .as_ref()
+                .and_then(|directory| fs::read_dir(directory.join(INDEXES_NAME)).ok())
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| Some(entry.ok()?.file_name().to_string_lossy().into_owned()))
+                .collect(),
+            indexes_path: directory
+                .as_ref()
+                .map(|directory| directory.join(INDEXES_NAME)),
+            generation: directory
+                .as_ref()
+                .and_then(|directory| read_generation(&directory.join(GENERATION_NAME))),
+            generation_path: directory.map(|directory| directory.join(GENERATION_NAME)),
+            changed: false,
+            expiry,
+            clock: settings.clock.clone(),
+            operands,
+            merge_operator: None,
+            subscribers: Subscribers::default(),
+            #[cfg(feature = "garbage-collection")]
+            space,
+            #[cfg(feature = "garbage-collection")]
+            compaction: None,
+            #[cfg(feature = "garbage-collection")]
+            gc_policy: settings.gc_policy,
+            #[cfg(feature = "garbage-collection")]
+            gc_mode: settings.gc_mode,
+            #[cfg(feature = "garbage-collection")]
+            punched_bytes: 0,
+            #[cfg(feature = "garbage-collection")]
+            last_gc: None,
+            #[cfg(feature = "garbage-collection")]
+            allocation_mode,
+            #[cfg(feature = "garbage-collection")]
+            free_list,
+        };
+        database.preload(settings.preload);
+        database
+    }
+
+    // Fills the cache, stopping once it starts evicting unless all keys are known to fit
+    fn preload(&mut self, preload: Preload) {
+        let (keys, stop_on_eviction) = match preload {
+            Preload::None => return,
+            Preload::All => {
+                let mut keys = self.locations();
+                keys.sort();
+                keys.dedup();
+                (keys, true)
+            }
+            Preload::Keys(keys) => {
+                let keys = keys.iter().filter_map(|key| self.index_table.get(key));
+                (keys.collect(), true)
+            }
+            // The hot set was written by a cache of the same size, load the hottest keys last.
+            // Locations the index doesn't reference anymore may hold other data by now
+            Preload::HotSet => {
+                let keys: Vec<Key> = self
+                    .hot_set_path
+                    .as_ref()
+                    .and_then(|path| fs::read(path).ok())
+                    .and_then(|bytes| bincode::deserialize(&bytes).ok())
+                    .unwrap_or_default();
+                let live: HashSet<Key> = self.locations().into_iter().collect();
+                let keys = keys.into_iter().rev().filter(|key| live.contains(key));
+                (keys.collect(), false)
+            }
+        };
+
+        let evictions = self.cache.stats().evictions;
+        for (offset, length) in keys {
+            // Hot keys may point past the data files after a compaction
+            let Ok(value) = self.loader.load(offset, length) else {
+                continue;
+            };
+            self.cache.put((offset, length), value);
+            if stop_on_eviction && self.cache.stats().evictions > evictions {
+                break;
             }
         }
+    }
+}
+
+#[cfg(feature = "garbage-collection")]
+impl<C: Cache + Send + Sync> LLDatabase<C> {
+    // Runs a full garbage collection if the policy asks for it
+    fn collect_if_needed(&mut self) -> anyhow::Result<()> {
+        let Some(policy) = &self.gc_policy else {
+            return Ok(());
+        };
+
+        // Holes that were already punched don't need to be collected again
+        let mut stats = self.space_stats();
+        let file_size = stats.live_bytes + stats.dead_bytes;
+        self.punched_bytes = self.punched_bytes.min(stats.dead_bytes);
+        stats.dead_bytes -= self.punched_bytes;
+        if file_size > 0 {
+            stats.fragmentation = stats.dead_bytes as f64 / file_size as f64;
+        }
 
-        Ok(())
+        let last_run = self.last_gc.map(|(at, _)| at);
+        if !policy.should_collect(&stats, last_run) {
+            return Ok(());
+        }
+
+        if self.gc_mode == GcMode::Compact && !self.loader.is_segmented() {
+            while !self.gc_step(AUTO_GC_STEP_SIZE)?.finished {}
+            return Ok(());
+        }
+        self.gc()
     }
 
-    fn rollback(&mut self, index_table: Box<dyn IndexTable>) -> anyhow::Result<()> {
-        self.index_table = index_table;
-        Ok(())
+    // The sealed segment with the most dead bytes, along with the amount of dead bytes
+    fn segment_to_compact(&self) -> Option<((u64, u64), u64)> {
+        let segments = self.loader.segments();
+        let sealed = &segments[..segments.len() - 1];
+        let dead_zones = self.space.dead_zones(sealed);
+
+        sealed
+            .iter()
+            .map(|(start, size)| {
+                let dead = dead_zones
+                    .iter()
+                    .filter(|zone| zone.0 >= *start && zone.0 < start + size)
+                    .map(|zone| zone.1 as u64)
+                    .sum::<u64>();
+                ((*start, *size), dead)
+            })
+            .filter(|(_, dead)| *dead > 0)
+            .max_by_key(|(_, dead)| *dead)
+    }
+
+    // Moves the live data of the sealed segment with the most dead bytes to the active segment,
+    // then deletes the sealed segment
+    fn compact_segment(&mut self) -> anyhow::Result<GcReport> {
+        self.persist_files()?;
+
+        let Some(((start, size), _)) = self.segment_to_compact() else {
+            return Ok(GcReport {
+                finished: true,
+                ..Default::default()
+            });
+        };
+
+        // Empty values appended last are located right at the end of the segment
+        let extents = self
+            .space
+            .extents_from(start)
+            .take_while(|key| segment_of(key.0) == segment_of(start))
+            .collect::<Vec<_>>();
+
+        let mut report = GcReport::default();
+        let mut moves = Vec::new();
+        for run in collect_runs(extents.into_iter(), usize::MAX) {
+            let data = self.loader.load(run.offset, run.length)?;
+            let (new_offset, _) = self.loader.add(&data)?;
+            report.moved_bytes += run.length as u64;
+
+            for (offset, length) in run.extents {
+                moves.push(((offset, length), (new_offset + offset - run.offset, length)));
+            }
+        }
+        self.apply_moves(moves)?;
+
+        // The index on disk must not reference the segment anymore, before it is deleted
+        self.persist_files()?;
+        self.loader.remove_segment(start)?;
+
+        report.reclaimed_bytes = size;
+        report.finished = self.segment_to_compact().is_none();
+        Ok(report)
+    }
+
+    fn compact_segments(&mut self) -> anyhow::Result<GcReport> {
+        let mut total = GcReport::default();
+        while !total.finished {
+            let report = self.compact_segment()?;
+            total.moved_bytes += report.moved_bytes;
+            total.reclaimed_bytes += report.reclaimed_bytes;
+            total.finished = report.finished;
+        }
+
+        self.last_gc = Some((Instant::now(), total));
+        Ok(total)
+    }
+
+    // Deallocates all dead regions in place, returns None if the file system doesn't support it
+    fn punch_holes(&mut self) -> anyhow::Result<Option<GcReport>> {
+        // Nothing the index on disk references may be deallocated
+        self.persist_files()?;
+
+        let allocated_before = self.loader.allocated_size()?;
+        for (offset, length) in self.space.dead_zones(&self.loader.segments()) {
+            match self.loader.punch_hole(offset, length as u64) {
+                Ok(()) => (),
+                Err(e) if e.kind() == ErrorKind::Unsupported => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.punched_bytes = self.space_stats().dead_bytes;
+
+        let allocated_after = self.loader.allocated_size()?;
+        Ok(Some(GcReport {
+            moved_bytes: 0,
+            reclaimed_bytes: allocated_before.saturating_sub(allocated_after),
+            finished: true,
+        }))
+    }
+}
+
+// The operations of the database work on any keyspace, `None` is the default keyspace. Only keys
+// of the default keyspace can be pinned
+impl<C: Cache + Send + Sync> LLDatabase<C> {
+    pub(crate) fn get_in(
+        &mut self,
+        tree: Option<&str>,
+        key: &str,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        if tree.is_none() && self.is_expired(key) {
+            return Ok(None);
+        }
+        let Some(index) = self.table(tree).get(key) else {
+            return Ok(None);
+        };
+        let value = self.value_at(index);
+        match (tree, value) {
+            (None, Some(value)) => {
+                let operands = self.operands.of(key);
+                self.fold(key, Some(value), &operands)
+            }
+            (_, value) => Ok(value),
+        }
+    }
+
+    pub(crate) fn scan_prefix_in(
+        &mut self,
+        tree: Option<&str>,
+        prefix: &str,
+    ) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+        let key_values = self.table(tree).scan_prefix(prefix);
+        match tree {
+            Some(_) => Ok(self.values_of(key_values)),
+            None => {
+                let values = self.values_of(self.unexpired(key_values));
+                self.fold_all(values)
+            }
+        }
+    }
+
+    pub(crate) fn range_in(
+        &mut self,
+        tree: Option<&str>,
+        start: Bound<&str>,
+        end: Bound<&str>,
+    ) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+        let key_values = self.table(tree).range(start, end);
+        match tree {
+            Some(_) => Ok(self.values_of(key_values)),
+            None => {
+                let values = self.values_of(self.unexpired(key_values));
+                self.fold_all(values)
+            }
+        }
+    }
+
+    pub(crate) fn link_in(
+        &mut self,
+        tree: Option<&str>,
+        old: &str,
+        new: &str,
+    ) -> anyhow::Result<()> {
+        let Some(index) = self.table(tree).get(old) else {
+            bail!("Key not found");
+        };
+        if tree.is_none() && self.is_expired(old) {
+            bail!("Key not found");
+        }
+        if old == new {
+            return Ok(());
+        }
+        self.set_in(tree, new, Some(index))?;
+        if tree.is_some() {
+            return Ok(());
+        }
+
+        // The new key expires along with the old one, and shares its merge operands
+        self.set_expiry(new, self.expiry.get(old).map(|(deadline, _)| deadline))?;
+        let operands = self.operands.of(old);
+        for &operand in &operands {
+            self.push_operand(new, operand)?;
+        }
+        if !operands.is_empty() && !self.indexes.is_empty() {
+            let base = self.loader.load(index.0, index.1)?;
+            let value = self.fold(new, Some(base.clone()), &operands)?;
+            self.update_index_values(new, Some(&base), value.as_deref())?;
+        }
+
+        if self.subscribers.watches(new) {
+            self.subscribers.publish(Event::Link {
+                old: old.to_string(),
+                new: new.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    pub(crate) fn delete_in(&mut self, tree: Option<&str>, key: &str) -> anyhow::Result<()> {
+        self.set_in(tree, key, None)?;
+        if tree.is_none() {
+            self.set_expiry(key, None)?;
+            self.publish_delete(key);
+        }
+        Ok(())
+    }
+
+    /// Writes `value` to `key`. A key of the default keyspace expires at `deadline`, which is set
+    /// before the insert is published or garbage is collected.
+    #[cfg(feature = "write")]
+    pub(crate) fn put_in(
+        &mut self,
+        tree: Option<&str>,
+        key: &str,
+        value: &[u8],
+        deadline: Option<u64>,
+    ) -> anyhow::Result<()> {
+        self.check_writable(tree)?;
+        let index = self.write_value(value)?;
+        self.set_in(tree, key, Some(index))?;
+        if tree.is_none() {
+            self.set_expiry(key, deadline)?;
+            self.publish_insert(key)?;
+        }
+
+        #[cfg(feature = "garbage-collection")]
+        self.collect_if_needed()?;
+        Ok(())
+    }
+
+    pub(crate) fn len_in(&self, tree: Option<&str>) -> usize {
+        self.table(tree).len()
+    }
+
+    // Adds a merge operand of `key`, stored at `location`
+    fn push_operand(&mut self, key: &str, location: Key) -> anyhow::Result<()> {
+        #[cfg_attr(not(feature = "garbage-collection"), allow(unused_variables))]
+        let entry = self.operands.push(key, location)?;
+        #[cfg(feature = "garbage-collection")]
+        self.track(Reference::operand(&entry), None, Some(location));
+        Ok(())
+    }
+
+    // Points `key` to `index`, or deletes it for `None`. Merge operands of the previous value are
+    // dropped
+    fn set_in(&mut self, tree: Option<&str>, key: &str, index: Option<Key>) -> anyhow::Result<()> {
+        let previous = self.table(tree).try_get(key)?;
+        match index {
+            Some(index) => self.table_mut(tree).insert(key, index)?,
+            None => self.table_mut(tree).delete(key)?,
+        }
+        let operands = match tree {
+            Some(_) => Vec::new(),
+            None => self.operands.clear(key)?,
+        };
+        if tree.is_none() {
+            let locations: Vec<Key> = operands.iter().map(|(_, location)| *location).collect();
+            self.update_indexes(key, previous, &locations, index)?;
+        }
+        #[cfg(feature = "garbage-collection")]
+        {
+            self.track(Reference::keyspace(tree, key), previous, index);
+            for (entry, operand) in operands {
+                self.track(Reference::operand(&entry), Some(operand), None);
+            }
+        }
+
+        match tree {
+            Some(_) => Ok(()),
+            None => self.refresh_pin(key),
+        }
+    }
+
+    // Sends the current value of `key` of the default keyspace to its subscribers
+    #[cfg(feature = "write")]
+    fn publish_insert(&mut self, key: &str) -> anyhow::Result<()> {
+        if !self.subscribers.watches(key) {
+            return Ok(());
+        }
+        match self.current_value(key)? {
+            Some(value) => {
+                let key = key.to_string();
+                self.subscribers.publish(Event::Insert { key, value });
+            }
+            None => self.publish_delete(key),
+        }
+        Ok(())
+    }
+
+    fn publish_delete(&mut self, key: &str) {
+        if self.subscribers.watches(key) {
+            let key = key.to_string();
+            self.subscribers.publish(Event::Delete { key });
+        }
+    }
+
+    // Sets the deadline of `key` of the default keyspace, `None` keeps it from expiring
+    fn set_expiry(&mut self, key: &str, deadline: Option<u64>) -> anyhow::Result<()> {
+        match deadline {
+            Some(deadline) => self.expiry.insert(key, (deadline, 0)),
+            None => self.expiry.delete(key),
+        }
+    }
+
+    // Deletes the keys that expired, their space is reclaimed like the space of any deleted key
+    fn remove_expired(&mut self) -> anyhow::Result<()> {
+        let now = millis(self.clock.now());
+        let expired: Vec<String> = self
+            .expiry
+            .all_key_values()
+            .into_iter()
+            .filter(|(_, (deadline, _))| *deadline <= now)
+            .map(|(key, _)| key)
+            .collect();
+        for key in expired {
+            self.delete_in(None, &key)?;
+        }
+        Ok(())
+    }
+
+    // Writes the values of all keys with merge operands, folding the operands into them, so the
+    // space of the operands can be reclaimed
+    #[cfg(all(feature = "write", feature = "garbage-collection"))]
+    fn fold_operands(&mut self) -> anyhow::Result<()> {
+        if self.merge_operator.is_none() {
+            return Ok(());
+        }
+        for key in self.operands.keys() {
+            let index = match self.current_value(&key)? {
+                Some(value) => Some(self.write_value(&value)?),
+                None => None,
+            };
+            self.set_in(None, &key, index)?;
+        }
+        Ok(())
+    }
+
+    // Updates the secondary indexes after the value of `key` moved from `previous`, with the merge
+    // operands `operands`, to `current`
+    fn update_indexes(
+        &mut self,
+        key: &str,
+        previous: Option<Key>,
+        operands: &[Key],
+        current: Option<Key>,
+    ) -> anyhow::Result<()> {
+        self.mark_changed()?;
+        if self.indexes.is_empty() || (previous == current && operands.is_empty()) {
+            return Ok(());
+        }
+
+        let previous = previous
+            .map(|(offset, length)| self.loader.load(offset, length))
+            .transpose()?;
+        let previous = self.fold(key, previous, operands)?;
+        let current = current
+            .map(|(offset, length)| self.loader.load(offset, length))
+            .transpose()?;
+        self.update_index_values(key, previous.as_deref(), current.as_deref())
+    }
+
+    fn update_index_values(
+        &mut self,
+        key: &str,
+        previous: Option<&[u8]>,
+        current: Option<&[u8]>,
+    ) -> anyhow::Result<()> {
+        for index in self.indexes.values_mut() {
+            index.update(key, previous, current)?;
+        }
+        Ok(())
+    }
+
+    // Called before the default keyspace changes
+    fn mark_changed(&mut self) -> anyhow::Result<()> {
+        self.changed = true;
+        self.drop_unregistered_indexes()
+    }
+
+    // Fills the index table of `index` from all records of the default keyspace
+    fn build_index(&mut self, index: &mut SecondaryIndex) -> anyhow::Result<()> {
+        let mut entries = Vec::new();
+        for (key, (offset, length)) in self.index_table.all_key_values() {
+            let value = self.loader.load(offset, length)?;
+            let operands = self.operands.of(&key);
+            let Some(value) = self.fold(&key, Some(value), &operands)? else {
+                continue;
+            };
+            entries.extend(index.entry(&key, &value).map(|entry| (entry, (0, 0))));
+        }
+        index.index_table.replace_all(entries)
+    }
+
+    // Rebuilds all secondary indexes, after the default keyspace was restored from a snapshot
+    #[cfg(feature = "write")]
+    fn rebuild_indexes(&mut self) -> anyhow::Result<()> {
+        let mut indexes = std::mem::take(&mut self.indexes);
+        let result = indexes
+            .values_mut()
+            .try_for_each(|index| self.build_index(index));
+        self.indexes = indexes;
+        result
+    }
+
+    // Opens the keyspace `name` unless it is open already. Keyspaces are created with the index
+    // type of the default keyspace if `index_type` is `Auto`
+    fn open_keyspace(&mut self, name: &str, index_type: IndexType) -> anyhow::Result<()> {
+        if let Some(tree) = self.trees.get(name) {
+            if index_type != IndexType::Auto && index_type != tree.index_type {
+                bail!("Tree {} has the index type {:?}", name, tree.index_type);
+            }
+            return Ok(());
+        }
+
+        check_name(name)?;
+        let index_type = match index_type {
+            IndexType::Auto => self.index_type,
+            index_type => index_type,
+        };
+        let mut index_factory = IndexFactory::new(index_type).with_format(self.index_format);
+        let index_table = match &self.trees_path {
+            Some(path) => {
+                let path = path.join(name);
+                fs::create_dir_all(&path)?;
+                index_factory.create(path)?
+            }
+            None => index_factory.in_memory(),
+        };
+        let keyspace = Keyspace {
+            index_table,
+            index_type: index_factory.index_type(),
+        };
+        self.trees.insert(name.to_string(), keyspace);
+        Ok(())
+    }
+}
+
+impl<C: Cache + Send + Sync> Database for LLDatabase<C> {
+    fn new(settings: DatabaseSettings) -> Self {
+        let cache = cache_from_settings(&settings);
+        Self::with_cache(settings, cache)
+    }
+
+    fn new_default(location: PathBuf) -> Self {
+        Self::new(DatabaseSettings {
+            path: Some(location),
+            ..Default::default()
+        })
+    }
+
+    fn get(&mut self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        self.get_in(None, key)
+    }
+
+    fn scan_prefix(&mut self, prefix: &str) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+        self.scan_prefix_in(None, prefix)
+    }
+
+    fn range(
+        &mut self,
+        start: Bound<&str>,
+        end: Bound<&str>,
+    ) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+        self.range_in(None, start, end)
+    }
+
+    fn link(&mut self, old: &str, new: &str) -> anyhow::Result<()> {
+        self.link_in(None, old, new)
+    }
+
+    fn delete(&mut self, key: &str) -> anyhow::Result<()> {
+        self.delete_in(None, key)
+    }
+
+    fn open_tree(&mut self, name: &str) -> anyhow::Result<Box<dyn Tree + '_>> {
+        self.open_tree_with_type(name, IndexType::Auto)
+    }
+
+    fn open_tree_with_type(
+        &mut self,
+        name: &str,
+        index_type: IndexType,
+    ) -> anyhow::Result<Box<dyn Tree + '_>> {
+        self.open_keyspace(name, index_type)?;
+        Ok(Box::new(TreeHandle::new(self, name)))
+    }
+
+    fn tree_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.trees.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    fn register_index(&mut self, name: &str, extractor: Extractor) -> anyhow::Result<()> {
+        check_name(name)?;
+        self.indexes.remove(name);
+        let unregistered = self.unregistered_indexes.iter().position(|n| n == name);
+
+        if let Some(position) = unregistered {
+            self.unregistered_indexes.swap_remove(position);
+        }
+
+        let mut index = match &self.indexes_path {
+            // The index on disk is up to date if it was built for the default keyspace on disk,
+            // which didn't change since the database was opened
+            Some(directory)
+                if unregistered.is_some()
+                    && self.generation.is_some()
+                    && read_generation(&directory.join(name).join(GENERATION_NAME))
+                        == self.generation =>
+            {
+                let index_table = IndexFactory::new(IndexType::Auto)
+                    .with_format(self.index_format)
+                    .load(directory.join(name))?;
+                let index = SecondaryIndex::new(extractor, index_table);
+                self.indexes.insert(name.to_string(), index);
+                return Ok(());
+            }
+            Some(directory) => {
+                let path = directory.join(name);
+                if path.exists() {
+                    fs::remove_dir_all(&path)?;
+                }
+                fs::create_dir_all(&path)?;
+                let index_table = IndexFactory::new(IndexType::BTreeMap)
+                    .with_format(self.index_format)
+                    .create(path)?;
+                SecondaryIndex::new(extractor, index_table)
+            }
+            None => {
+                let index_table = IndexFactory::new(IndexType::BTreeMap).in_memory();
+                SecondaryIndex::new(extractor, index_table)
+            }
+        };
+
+        // A new index is persisted right away. Without unpersisted changes it matches the default
+        // keyspace on disk, otherwise it gets its generation once they're persisted
+        self.build_index(&mut index)?;
+        index.index_table.persist()?;
+        if let (Some(directory), Some(generation), false) =
+            (&self.indexes_path, self.generation, self.changed)
+        {
+            fs::write(
+                directory.join(name).join(GENERATION_NAME),
+                generation.to_string(),
+            )?;
+        }
+        self.indexes.insert(name.to_string(), index);
+        Ok(())
+    }
+
+    fn get_by_index(&mut self, name: &str, key: &str) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+        let Some(index) = self.indexes.get(name) else {
+            bail!("Index {} is not registered", name);
+        };
+
+        let mut records = Vec::new();
+        for primary in index.primary_keys(key) {
+            let Some(location) = self.index_table.get(&primary) else {
+                continue;
+            };
+            if self.is_expired(&primary) {
+                continue;
+            }
+            let Some(value) = self.value_at(location) else {
+                continue;
+            };
+            let operands = self.operands.of(&primary);
+            let Some(value) = self.fold(&primary, Some(value), &operands)? else {
+                continue;
+            };
+            if self.indexes[name].matches(key, &value) {
+                records.push((primary, value));
+            }
+        }
+        Ok(records)
+    }
+
+    fn watch_prefix(&mut self, prefix: &str) -> Subscriber {
+        self.subscribers.subscribe(prefix)
+    }
+
+    fn register_merge_operator(&mut self, operator: MergeOperator) {
+        self.merge_operator = Some(operator);
+    }
+
+    fn pin(&mut self, key: &str) -> anyhow::Result<()> {
+        let Some((offset, length)) = self.index_table.get(key) else {
+            bail!("Key not found");
+        };
+        self.unpin(key)?;
+
+        let value = self.loader.load(offset, length)?;
+        self.cache.pin((offset, length), value);
+        self.pinned.insert(key.to_string(), (offset, length));
+        Ok(())
+    }
+
+    fn unpin(&mut self, key: &str) -> anyhow::Result<()> {
+        if let Some(location) = self.pinned.remove(key) {
+            self.cache.unpin(&location);
+        }
+        Ok(())
+    }
+
+    fn stats(&self) -> DatabaseStats {
+        let (loader_reads, bytes_read) = self.loader.read_stats();
+        DatabaseStats {
+            cache: self.cache.stats(),
+            loader_reads,
+            bytes_read,
+            index_entries: self.index_table.len(),
+            data_file_size: self.loader.size(),
+        }
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    fn persist(&mut self) -> anyhow::Result<()> {
+        self.remove_expired()?;
+        self.persist_files()?;
+        self.persist_hot_set()?;
+        observability::record_stats(&self.stats());
+
+        #[cfg(feature = "garbage-collection")]
+        self.collect_if_needed()?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "write")]
+    fn put(&mut self, key: &str, value: &[u8]) -> anyhow::Result<()> {
+        self.put_in(None, key, value, None)
+    }
+
+    #[cfg(feature = "write")]
+    fn merge(&mut self, key: &str, operand: &[u8]) -> anyhow::Result<()> {
+        let Some(operator) = &self.merge_operator else {
+            bail!("No merge operator is registered");
+        };
+        self.check_writable(None)?;
+
+        // Without a value to defer the operand to, it is folded right away
+        if self.index_table.try_get(key)?.is_none() || self.is_expired(key) {
+            return match operator(key, None, operand) {
+                Some(value) => self.put_in(None, key, &value, None),
+                None => self.delete_in(None, key),
+            };
+        }
+
+        self.mark_changed()?;
+        let previous = match self.indexes.is_empty() {
+            true => None,
+            false => self.current_value(key)?,
+        };
+        let location = self.write_value(operand)?;
+        self.push_operand(key, location)?;
+
+        if !self.indexes.is_empty() {
+            let operator = self.merge_operator.as_ref().unwrap();
+            let current = operator(key, previous.as_deref(), operand);
+            self.update_index_values(key, previous.as_deref(), current.as_deref())?;
+        }
+        self.publish_insert(key)?;
+
+        #[cfg(feature = "garbage-collection")]
+        self.collect_if_needed()?;
+        Ok(())
+    }
+
+    #[cfg(feature = "write")]
+    fn put_with_ttl(&mut self, key: &str, value: &[u8], ttl: Duration) -> anyhow::Result<()> {
+        let deadline = millis(self.clock.now() + ttl);
+        self.put_in(None, key, value, Some(deadline))
+    }
+
+    #[cfg(feature = "write")]
+    fn compare_and_swap(
+        &mut self,
+        key: &str,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> anyhow::Result<Result<(), CompareAndSwapError>> {
+        let current = self.current_value(key)?;
+        if current.as_deref() != expected {
+            return Ok(Err(CompareAndSwapError {
+                current,
+                proposed: new.map(<[u8]>::to_vec),
+            }));
+        }
+
+        match new {
+            Some(value) => self.put(key, value)?,
+            None => self.delete(key)?,
+        }
+        Ok(Ok(()))
+    }
+
+    #[cfg(feature = "write")]
+    fn update_and_fetch<F>(&mut self, key: &str, mut f: F) -> anyhow::Result<Option<Vec<u8>>>
+    where
+        F: FnMut(Option<&[u8]>) -> Option<Vec<u8>>,
+    {
+        let current = self.current_value(key)?;
+        let new = f(current.as_deref());
+        match &new {
+            Some(value) => self.put(key, value)?,
+            None => self.delete(key)?,
+        }
+        Ok(new)
+    }
+
+    #[cfg(feature = "write")]
+    fn fetch_add(&mut self, key: &str, delta: i64) -> anyhow::Result<i64> {
+        let previous = match self.current_value(key)? {
+            Some(value) => {
+                let Ok(bytes) = <[u8; 8]>::try_from(value.as_slice()) else {
+                    bail!("Value of {} is not a counter", key);
+                };
+                i64::from_le_bytes(bytes)
+            }
+            None => 0,
+        };
+        self.put(key, &previous.wrapping_add(delta).to_le_bytes())?;
+        Ok(previous)
+    }
+
+    #[cfg(feature = "write")]
+    fn apply_batch(&mut self, batch: Batch) -> anyhow::Result<()> {
+        // Opening the keyspaces first means a batch that can't be applied writes nothing
+        let mut trees: Vec<Option<&str>> = batch.operations.iter().map(|op| op.tree()).collect();
+        trees.sort();
+        trees.dedup();
+        for name in trees.iter().flatten() {
+            self.open_keyspace(name, IndexType::Auto)?;
+        }
+        for tree in &trees {
+            self.check_writable(*tree)?;
+        }
+        let backups: Vec<(Option<&str>, Box<dyn IndexTable>)> = trees
+            .iter()
+            .map(|tree| (*tree, self.table(*tree).snapshot()))
+            .collect();
+        let operands = self.operands.index_table.snapshot();
+        #[cfg(feature = "garbage-collection")]
+        let pending = self.free_list.pending();
+
+        // All values are written at once, right behind each other
+        let data: Vec<u8> = batch
+            .operations
+            .iter()
+            .filter_map(|op| op.value.as_deref())
+            .flatten()
+            .copied()
+            .collect();
+        let mut offset = match data.is_empty() {
+            true => 0,
+            false => self.loader.add(&data)?.0,
+        };
+
+        for op in &batch.operations {
+            let index = op.value.as_ref().map(|value| {
+                let index = (offset, value.len());
+                offset += value.len() as u64;
+                index
+            });
+            if let Err(e) = self.set_in(op.tree(), &op.key, index) {
+                let restores_default = trees.contains(&None);
+                for (tree, backup) in backups {
+                    *self.table_mut(tree) = backup;
+                }
+                self.operands.index_table = operands;
+                if restores_default {
+                    self.rebuild_indexes()?;
+                }
+                // Regions released before the batch stay released
+                #[cfg(feature = "garbage-collection")]
+                {
+                    self.rebuild_space_tracker();
+                    self.free_list.restore_pending(pending);
+                }
+                self.refresh_pins()?;
+                bail!("Failed to apply batch: {}", e);
+            }
+        }
+        for op in batch.operations.iter().filter(|op| op.tree().is_none()) {
+            self.set_expiry(&op.key, None)?;
+            if self.subscribers.watches(&op.key) {
+                let key = op.key.clone();
+                self.subscribers.publish(match &op.value {
+                    Some(value) => Event::Insert {
+                        key,
+                        value: value.clone(),
+                    },
+                    None => Event::Delete { key },
+                });
+            }
+        }
+
+        #[cfg(feature = "garbage-collection")]
+        self.collect_if_needed()?;
+        Ok(())
+    }
+
+    #[cfg(feature = "garbage-collection")]
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    fn gc(&mut self) -> anyhow::Result<()> {
+        self.remove_expired()?;
+        #[cfg(feature = "write")]
+        self.fold_operands()?;
+        if self.loader.is_segmented() {
+            self.compact_segments()?;
+            return Ok(());
+        }
+
+        if self.gc_mode == GcMode::PunchHoles {
+            if let Some(report) = self.punch_holes()? {
+                self.last_gc = Some((Instant::now(), report));
+                return Ok(());
+            }
+        }
+
+        let size_before = self.loader.size();
+        let mut moves = Vec::new();
+        self.loader.read_and_replace(|data| {
+            // The keys of all keyspaces are compacted at once, prefixed with their keyspace
+            let mut tables: Vec<&mut Box<dyn IndexTable>> = std::iter::once(&mut self.index_table)
+                .chain(self.trees.values_mut().map(|tree| &mut tree.index_table))
+                .chain(std::iter::once(&mut self.operands.index_table))
+                .collect();
+            let keys: Vec<(String, Key)> = tables
+                .iter()
+                .enumerate()
+                .flat_map(|(i, table)| {
+                    let key_values = table.all_key_values().into_iter();
+                    key_values.map(move |(key, index)| (format!("{}\0{}", i, key), index))
+                })
+                .collect();
+            let locations: HashMap<String, Key> = keys.iter().cloned().collect();
+            let (new_keys, new_data) = compact_file(keys, data);
+            moves = new_keys
+                .iter()
+                .map(|(key, new_location)| (locations[key], *new_location))
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect();
+
+            let mut key_values = vec![Vec::new(); tables.len()];
+            for (key, index) in new_keys {
+                let (i, key) = key.split_once('\0').unwrap();
+                key_values[i.parse::<usize>()?].push((key.to_string(), index));
+            }
+            for (table, key_values) in tables.iter_mut().zip(key_values) {
+                table.replace_all(key_values)?;
+            }
+
+            Ok(new_data)
+        })?;
+
+        self.rebuild_space_tracker();
+        self.compaction = None;
+        self.free_list.clear();
+        self.punched_bytes = 0;
+        self.relocate_cached(&moves)?;
+
+        let size_after = self.loader.size();
+        let report = GcReport {
+            moved_bytes: moves
+                .iter()
+                .filter(|(from, to)| from.0 != to.0)
+                .map(|(from, _)| from.1 as u64)
+                .sum(),
+            reclaimed_bytes: size_before.saturating_sub(size_after),
+            finished: true,
+        };
+        self.last_gc = Some((Instant::now(), report));
+        Ok(())
+    }
+
+    #[cfg(feature = "garbage-collection")]
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    fn gc_step(&mut self, max_bytes: usize) -> anyhow::Result<GcReport> {
+        self.remove_expired()?;
+        #[cfg(feature = "write")]
+        self.fold_operands()?;
+        if self.loader.is_segmented() {
+            let report = self.compact_segment()?;
+            self.last_gc = Some((Instant::now(), report));
+            return Ok(report);
+        }
+
+        // Make sure the data is flushed and the index on disk doesn't point into dead zones, which
+        // are about to be overwritten
+        self.persist_files()?;
+
+        let mut cursor = match self.compaction.take() {
+            Some(cursor) => cursor,
+            None => match self.space.first_dead_offset(&self.loader.segments()) {
+                Some(offset) => {
+                    // Free regions are about to be overwritten by the compaction
+                    self.free_list.clear();
+                    CompactionCursor::new(offset)
+                }
+                None => {
+                    let report = GcReport {
+                        finished: true,
+                        ..Default::default()
+                    };
+                    self.last_gc = Some((Instant::now(), report));
+                    return Ok(report);
+                }
+            },
+        };
+
+        let mut report = GcReport::default();
+        let mut copies = Vec::new();
+        for run in collect_runs(self.space.extents_from(cursor.read), max_bytes) {
+            let write = cursor.write;
+            cursor.read = run.offset + run.length as u64;
+            cursor.write += run.length as u64;
+            if run.offset != write {
+                let data = self.loader.load(run.offset, run.length)?;
+                report.moved_bytes += run.length as u64;
+                copies.push((run, write, data));
+            }
+        }
+        cursor.moved_bytes += report.moved_bytes;
+
+        // The index on disk references the runs until it's persisted again. If a run would be
+        // copied over one of them, they're all staged at the end of the file first, so they're
+        // only ever overwritten once the index on disk points to their staged copies
+        let size = self.loader.size();
+        let overlapping = match (copies.first(), copies.last()) {
+            (Some((first, ..)), Some((last, write, _))) => {
+                write + last.length as u64 > first.offset
+            }
+            _ => false,
+        };
+        if overlapping {
+            let mut moves = Vec::new();
+            for (run, _, data) in &mut copies {
+                let (offset, _) = self.loader.add(data)?;
+                let staged = shift(&run.extents, run.offset, offset);
+                run.offset = offset;
+                run.extents = staged.iter().map(|(_, to)| *to).collect();
+                moves.extend(staged);
+            }
+            self.apply_moves(moves)?;
+            self.persist_files()?;
+        }
+
+        let mut moves = Vec::new();
+        for (run, write, data) in copies {
+            self.loader.write_at(write, &data)?;
+            moves.extend(shift(&run.extents, run.offset, write));
+        }
+        self.apply_moves(moves)?;
+
+        let finished = self.space.extents_from(cursor.read).next().is_none();
+        if finished {
+            report.reclaimed_bytes = size - cursor.write;
+            report.finished = true;
+            self.punched_bytes = 0;
+
+            let cycle = GcReport {
+                moved_bytes: cursor.moved_bytes,
+                ..report
+            };
+            self.last_gc = Some((Instant::now(), cycle));
+        } else {
+            self.compaction = Some(cursor);
+        }
+
+        // Every table may point into the moved values, not only the default keyspace. The space
+        // after the compacted data is only released once none of them does anymore
+        self.persist_files()?;
+        if finished {
+            self.loader.truncate(cursor.write)?;
+        } else if overlapping {
+            self.loader.truncate(size)?;
+        }
+        Ok(report)
+    }
+
+    #[cfg(feature = "garbage-collection")]
+    fn space_stats(&self) -> SpaceStats {
+        self.space.stats(self.loader.size())
+    }
+
+    #[cfg(feature = "garbage-collection")]
+    fn last_gc_report(&self) -> Option<GcReport> {
+        self.last_gc.map(|(_, report)| report)
+    }
+
+    #[cfg(feature = "write")]
+    fn tx(&mut self) -> anyhow::Result<Box<dyn Transaction + '_>> {
+        Ok(Box::new(SimpleTransaction::new(self)))
+    }
+}
+
+#[cfg(feature = "write")]
+impl<C: Cache + Send + Sync> DatabaseTransactionsIO for LLDatabase<C> {
+    fn snapshot(&self) -> Box<dyn IndexTable> {
+        self.index_table.snapshot()
+    }
+
+    fn merge_file(&mut self, new_content: &[u8]) -> anyhow::Result<u64> {
+        self.check_writable(None)?;
+        Ok(self.loader.add(new_content)?.0)
+    }
+
+    fn merge_index_table(
+        &mut self,
+        backup: Box<dyn IndexTable>,
+        new_key_values: Vec<(String, Key)>,
+    ) -> anyhow::Result<()> {
+        let operands = self.operands.index_table.snapshot();
+        for (k, v) in &new_key_values {
+            if let Err(e) = self.set_in(None, k.as_str(), Some(*v)) {
+                self.operands.index_table = operands;
+                self.rollback(backup).unwrap_or_else(|e2| {
+                    panic!(
+                        "[FATAL] Failed to apply rollback: {}\nOriginal Error: {}",
+                        e2, e
+                    )
+                });
+                bail!("Failed to merge index tables: {}", e);
+            }
+        }
+        for (k, _) in &new_key_values {
+            self.set_expiry(k, None)?;
+            self.publish_insert(k)?;
+        }
+
+        self.refresh_pins()
+    }
+
+    fn rollback(&mut self, index_table: Box<dyn IndexTable>) -> anyhow::Result<()> {
+        self.index_table = index_table;
+        self.changed = true;
+        self.rebuild_indexes()?;
+
+        // Regions released since the snapshot might be referenced again
+        #[cfg(feature = "garbage-collection")]
+        {
+            self.rebuild_space_tracker();
+            self.free_list.clear_pending();
+        }
+        self.refresh_pins()
     }
 }
@@ -0,0 +1,114 @@
+use crate::cache::Key;
+use crate::IndexTable;
+
+/// Combines the value of a key with an operand written by
+/// [`Database::merge`](crate::Database::merge), see
+/// [`Database::register_merge_operator`](crate::Database::register_merge_operator). It gets the
+/// key, its current value and the operand, and returns the new value or `None` to delete the key.
+pub type MergeOperator = Box<dyn Fn(&str, Option<&[u8]>, &[u8]) -> Option<Vec<u8>> + Send + Sync>;
+
+// The merge operands of the default keyspace that weren't folded into their values yet. Its index
+// table holds an entry per operand, the key and a sequence number separated by a NUL byte. The
+// sequence number is fixed width hex, so the operands of a key are sorted in the order they were
+// written
+pub(crate) struct Operands {
+    pub index_table: Box<dyn IndexTable>,
+    next: u64,
+}
+
+impl Operands {
+    pub fn new(index_table: Box<dyn IndexTable>) -> Self {
+        let next = index_table
+            .all_key_values()
+            .iter()
+            .filter_map(|(entry, _)| u64::from_str_radix(entry.rsplit('\0').next()?, 16).ok())
+            .max()
+            .map_or(0, |last| last + 1);
+        Self { index_table, next }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index_table.is_empty()
+    }
+
+    // The entries and locations of the operands of `key`, oldest first. A key containing a NUL
+    // byte shares its prefix with others, their entries are told apart by the length of the rest
+    fn entries(&self, key: &str) -> Vec<(String, Key)> {
+        let prefix = format!("{}\0", key);
+        self.index_table
+            .scan_prefix(&prefix)
+            .into_iter()
+            .filter(|(entry, _)| entry.len() == prefix.len() + 16)
+            .collect()
+    }
+
+    // The locations of the operands of `key`, oldest first
+    pub fn of(&self, key: &str) -> Vec<Key> {
+        self.entries(key)
+            .into_iter()
+            .map(|(_, location)| location)
+            .collect()
+    }
+
+    // The keys with operands, each once
+    #[cfg(all(feature = "write", feature = "garbage-collection"))]
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self
+            .index_table
+            .all_key_values()
+            .into_iter()
+            .filter_map(|(entry, _)| Some(entry.rsplit_once('\0')?.0.to_string()))
+            .collect();
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+
+    // Adds an operand of `key` and returns its entry
+    pub fn push(&mut self, key: &str, location: Key) -> anyhow::Result<String> {
+        let entry = format!("{}\0{:016x}", key, self.next);
+        self.next += 1;
+        self.index_table.insert(&entry, location)?;
+        Ok(entry)
+    }
+
+    // Removes the operands of `key` and returns their entries and locations
+    pub fn clear(&mut self, key: &str) -> anyhow::Result<Vec<(String, Key)>> {
+        let entries = self.entries(key);
+        for (entry, _) in &entries {
+            self.index_table.delete(entry)?;
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index_table::btree::BTreeMapIndexTable;
+
+    #[test]
+    fn test_operands() -> anyhow::Result<()> {
+        let mut operands = Operands::new(Box::new(BTreeMapIndexTable::in_memory()));
+        assert_eq!(operands.push("a", (0, 1))?, format!("a\0{:016x}", 0));
+        operands.push("a\0b", (1, 1))?;
+        operands.push("a", (2, 1))?;
+        assert_eq!(operands.of("a"), vec![(0, 1), (2, 1)]);
+
+        // Sequence numbers continue after the operands on disk
+        let mut operands = Operands::new(operands.index_table);
+        operands.push("a", (3, 1))?;
+        let cleared = operands.clear("a")?;
+        assert_eq!(
+            cleared
+                .iter()
+                .map(|(_, location)| *location)
+                .collect::<Vec<_>>(),
+            vec![(0, 1), (2, 1), (3, 1)]
+        );
+        assert_eq!(cleared[2].0, format!("a\0{:016x}", 3));
+        assert_eq!(operands.of("a\0b"), vec![(1, 1)]);
+        assert!(operands.of("a").is_empty());
+        Ok(())
+    }
+}
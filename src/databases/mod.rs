@@ -1,3 +1,9 @@
+pub(crate) mod clock;
 pub mod db_trait;
 
 pub(crate) mod lazy_loader_db;
+pub(crate) mod merge;
+mod observability;
+pub(crate) mod secondary;
+pub(crate) mod tree;
+pub(crate) mod watch;
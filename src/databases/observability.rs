@@ -0,0 +1,31 @@
+//! Exports database activity through the `metrics` crate. Without the `metrics` feature all of
+//! this compiles to nothing.
+
+use crate::DatabaseStats;
+
+pub(crate) fn record_get(_cache_hit: bool) {
+    #[cfg(feature = "metrics")]
+    match _cache_hit {
+        true => metrics::counter!("readb_cache_hits_total").increment(1),
+        false => metrics::counter!("readb_cache_misses_total").increment(1),
+    }
+}
+
+pub(crate) fn record_load(_bytes: usize) {
+    #[cfg(feature = "metrics")]
+    {
+        metrics::counter!("readb_loader_reads_total").increment(1);
+        metrics::counter!("readb_loader_read_bytes_total").increment(_bytes as u64);
+    }
+}
+
+pub(crate) fn record_stats(_stats: &DatabaseStats) {
+    #[cfg(feature = "metrics")]
+    {
+        metrics::gauge!("readb_cache_entries").set(_stats.cache.entries as f64);
+        metrics::gauge!("readb_cache_bytes").set(_stats.cache.bytes as f64);
+        metrics::gauge!("readb_cache_evictions").set(_stats.cache.evictions as f64);
+        metrics::gauge!("readb_index_entries").set(_stats.index_entries as f64);
+        metrics::gauge!("readb_data_file_bytes").set(_stats.data_file_size as f64);
+    }
+}
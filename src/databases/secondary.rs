@@ -0,0 +1,101 @@
+use crate::IndexTable;
+
+/// Extracts the key a value is indexed by, see
+/// [`Database::register_index`](crate::Database::register_index). Values it returns `None` for
+/// aren't indexed.
+pub type Extractor = Box<dyn Fn(&[u8]) -> Option<String> + Send + Sync>;
+
+// A secondary index over the default keyspace. Its index table holds an entry per indexed record,
+// the indexed key and the primary key separated by a NUL byte, so the entries of an indexed key
+// are a prefix scan away. Entries don't point to a location, so compaction never touches them
+pub(crate) struct SecondaryIndex {
+    extractor: Extractor,
+    pub index_table: Box<dyn IndexTable>,
+}
+
+impl SecondaryIndex {
+    pub fn new(extractor: Extractor, index_table: Box<dyn IndexTable>) -> Self {
+        Self {
+            extractor,
+            index_table,
+        }
+    }
+
+    // The entry of the record `primary`, if its value is indexed
+    pub fn entry(&self, primary: &str, value: &[u8]) -> Option<String> {
+        (self.extractor)(value).map(|key| format!("{}\0{}", key, primary))
+    }
+
+    // Replaces the entry of the record `primary` when its value changes, `None` if it has none
+    pub fn update(
+        &mut self,
+        primary: &str,
+        previous: Option<&[u8]>,
+        current: Option<&[u8]>,
+    ) -> anyhow::Result<()> {
+        let previous = previous.and_then(|value| self.entry(primary, value));
+        let current = current.and_then(|value| self.entry(primary, value));
+        if previous == current {
+            return Ok(());
+        }
+
+        if let Some(entry) = previous {
+            self.index_table.delete(&entry)?;
+        }
+        if let Some(entry) = current {
+            self.index_table.insert(&entry, (0, 0))?;
+        }
+        Ok(())
+    }
+
+    // The primary keys of the records indexed by `key`, sorted. An indexed key containing a NUL
+    // byte may match other entries as well, check the values with `matches`
+    pub fn primary_keys(&self, key: &str) -> Vec<String> {
+        let prefix = format!("{}\0", key);
+        self.index_table
+            .scan_prefix(&prefix)
+            .into_iter()
+            .map(|(entry, _)| entry[prefix.len()..].to_string())
+            .collect()
+    }
+
+    pub fn matches(&self, key: &str, value: &[u8]) -> bool {
+        (self.extractor)(value).as_deref() == Some(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index_table::btree::BTreeMapIndexTable;
+
+    #[test]
+    fn test_update() -> anyhow::Result<()> {
+        let extractor: Extractor = Box::new(|value| {
+            let value = std::str::from_utf8(value).ok()?;
+            value.split_once(',').map(|(email, _)| email.to_string())
+        });
+        let mut index = SecondaryIndex::new(extractor, Box::new(BTreeMapIndexTable::in_memory()));
+
+        index.update("user:1", None, Some(b"a@example.com,Alice"))?;
+        index.update("user:2", None, Some(b"b@example.com,Bob"))?;
+        index.update("user:3", None, Some(b"a@example.com,Anna"))?;
+        index.update("user:4", None, Some(b"not indexed"))?;
+        assert_eq!(
+            index.primary_keys("a@example.com"),
+            vec!["user:1", "user:3"]
+        );
+
+        index.update(
+            "user:3",
+            Some(b"a@example.com,Anna"),
+            Some(b"c@example.com,Anna"),
+        )?;
+        index.update("user:2", Some(b"b@example.com,Bob"), None)?;
+        assert_eq!(index.primary_keys("a@example.com"), vec!["user:1"]);
+        assert_eq!(index.primary_keys("c@example.com"), vec!["user:3"]);
+        assert!(index.primary_keys("b@example.com").is_empty());
+        assert_eq!(index.index_table.len(), 2);
+        Ok(())
+    }
+}
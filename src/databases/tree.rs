@@ -0,0 +1,102 @@
+use crate::cache::Cache;
+use crate::databases::lazy_loader_db::LLDatabase;
+use std::ops::Bound;
+
+/// A named keyspace of a database, opened with
+/// [`Database::open_tree`](crate::Database::open_tree).
+///
+/// Each tree has an index table of its own, so the same key can hold different values in
+/// different trees. The values are stored in the data files of the database and share its cache,
+/// and [`Database::persist`](crate::Database::persist) persists all trees at once.
+pub trait Tree: Send + Sync {
+    /// The name of the tree.
+    fn name(&self) -> &str;
+
+    /// Retrieves the value associated with `key` in this tree.
+    fn get(&mut self, key: &str) -> anyhow::Result<Option<Vec<u8>>>;
+
+    /// Retrieves the keys of this tree starting with `prefix` and their values, sorted by key.
+    fn scan_prefix(&mut self, prefix: &str) -> anyhow::Result<Vec<(String, Vec<u8>)>>;
+
+    /// Retrieves the keys of this tree between `start` and `end` and their values, sorted by key.
+    fn range(
+        &mut self,
+        start: Bound<&str>,
+        end: Bound<&str>,
+    ) -> anyhow::Result<Vec<(String, Vec<u8>)>>;
+
+    /// Associates an existing key of this tree with a new key of this tree.
+    ///
+    /// # Errors
+    /// Returns an error if the old key is not found in the tree.
+    fn link(&mut self, old: &str, new: &str) -> anyhow::Result<()>;
+
+    /// Deletes a key from the index table of this tree.
+    fn delete(&mut self, key: &str) -> anyhow::Result<()>;
+
+    /// Returns the number of keys in this tree.
+    fn len(&self) -> usize;
+
+    /// Returns whether this tree contains no keys.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Adds a new key-value pair to this tree.
+    /// Note: This method is only available if the "write" feature is enabled.
+    #[cfg(feature = "write")]
+    fn put(&mut self, key: &str, value: &[u8]) -> anyhow::Result<()>;
+}
+
+pub(crate) struct TreeHandle<'a, C: Cache> {
+    database: &'a mut LLDatabase<C>,
+    name: String,
+}
+
+impl<'a, C: Cache> TreeHandle<'a, C> {
+    pub(crate) fn new(database: &'a mut LLDatabase<C>, name: &str) -> Self {
+        Self {
+            database,
+            name: name.to_string(),
+        }
+    }
+}
+
+impl<C: Cache + Send + Sync> Tree for TreeHandle<'_, C> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn get(&mut self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        self.database.get_in(Some(&self.name), key)
+    }
+
+    fn scan_prefix(&mut self, prefix: &str) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+        self.database.scan_prefix_in(Some(&self.name), prefix)
+    }
+
+    fn range(
+        &mut self,
+        start: Bound<&str>,
+        end: Bound<&str>,
+    ) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+        self.database.range_in(Some(&self.name), start, end)
+    }
+
+    fn link(&mut self, old: &str, new: &str) -> anyhow::Result<()> {
+        self.database.link_in(Some(&self.name), old, new)
+    }
+
+    fn delete(&mut self, key: &str) -> anyhow::Result<()> {
+        self.database.delete_in(Some(&self.name), key)
+    }
+
+    fn len(&self) -> usize {
+        self.database.len_in(Some(&self.name))
+    }
+
+    #[cfg(feature = "write")]
+    fn put(&mut self, key: &str, value: &[u8]) -> anyhow::Result<()> {
+        self.database.put_in(Some(&self.name), key, value, None)
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+#[cfg(feature = "async-watch")]
+use std::task::Waker;
+use std::time::{Duration, Instant};
+
+/// A change of a key in the default keyspace, received by a [`Subscriber`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// `key` was written by `put`, `merge`, a batch or a committed transaction, and now holds
+    /// `value`.
+    Insert { key: String, value: Vec<u8> },
+    /// `key` was deleted, or it expired. Deleting a missing key sends an event as well.
+    Delete { key: String },
+    /// `new` was linked to `old`, so it holds the value of `old` now.
+    Link { old: String, new: String },
+}
+
+impl Event {
+    /// The key that changed, `new` for a link.
+    pub fn key(&self) -> &str {
+        match self {
+            Event::Insert { key, .. } | Event::Delete { key } => key,
+            Event::Link { new, .. } => new,
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    events: VecDeque<Event>,
+    // Set once the database is dropped, no events follow the queued ones then
+    closed: bool,
+    #[cfg(feature = "async-watch")]
+    waker: Option<Waker>,
+}
+
+#[derive(Default)]
+struct Channel {
+    state: Mutex<State>,
+    ready: Condvar,
+}
+
+impl Channel {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn push(&self, event: Option<Event>) {
+        let mut state = self.lock();
+        match event {
+            Some(event) => state.events.push_back(event),
+            None => state.closed = true,
+        }
+        #[cfg(feature = "async-watch")]
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        self.ready.notify_all();
+    }
+}
+
+/// Receives the changes of the keys starting with a prefix, see
+/// [`Database::watch_prefix`](crate::Database::watch_prefix).
+///
+/// Events are queued until they are received, in the order the changes were made. Iterating
+/// blocks until the next event, and ends once the database is dropped. With the `async-watch`
+/// feature the subscriber is a `Stream` as well.
+pub struct Subscriber {
+    channel: Arc<Channel>,
+}
+
+impl Subscriber {
+    /// Returns the next event if one is queued, without blocking.
+    pub fn try_next(&mut self) -> Option<Event> {
+        self.channel.lock().events.pop_front()
+    }
+
+    /// Waits up to `timeout` for the next event, `None` if there was none or the database was
+    /// dropped.
+    pub fn next_timeout(&mut self, timeout: Duration) -> Option<Event> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.channel.lock();
+        loop {
+            if let Some(event) = state.events.pop_front() {
+                return Some(event);
+            }
+            let now = Instant::now();
+            if state.closed || now >= deadline {
+                return None;
+            }
+            state = self
+                .channel
+                .ready
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+}
+
+impl Iterator for Subscriber {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        let mut state = self.channel.lock();
+        loop {
+            if let Some(event) = state.events.pop_front() {
+                return Some(event);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self
+                .channel
+                .ready
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+#[cfg(feature = "async-watch")]
+impl futures_core::Stream for Subscriber {
+    type Item = Event;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Event>> {
+        let mut state = self.channel.lock();
+        if let Some(event) = state.events.pop_front() {
+            return std::task::Poll::Ready(Some(event));
+        }
+        if state.closed {
+            return std::task::Poll::Ready(None);
+        }
+        state.waker = Some(cx.waker().clone());
+        std::task::Poll::Pending
+    }
+}
+
+// The subscribers of a database and the prefixes they watch. Subscribers that were dropped are
+// forgotten on the next event, and the remaining ones are closed along with the database
+#[derive(Default)]
+pub(crate) struct Subscribers {
+    channels: Vec<(String, Arc<Channel>)>,
+}
+
+impl Subscribers {
+    pub fn subscribe(&mut self, prefix: &str) -> Subscriber {
+        let channel = Arc::new(Channel::default());
+        self.channels.push((prefix.to_string(), channel.clone()));
+        Subscriber { channel }
+    }
+
+    // Whether a subscriber receives the changes of `key`, so its event is worth building
+    pub fn watches(&self, key: &str) -> bool {
+        self.channels
+            .iter()
+            .any(|(prefix, channel)| key.starts_with(prefix) && Arc::strong_count(channel) > 1)
+    }
+
+    pub fn publish(&mut self, event: Event) {
+        self.channels
+            .retain(|(_, channel)| Arc::strong_count(channel) > 1);
+        for (prefix, channel) in &self.channels {
+            if event.key().starts_with(prefix.as_str()) {
+                channel.push(Some(event.clone()));
+            }
+        }
+    }
+}
+
+impl Drop for Subscribers {
+    fn drop(&mut self) {
+        for (_, channel) in &self.channels {
+            channel.push(None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delete(key: &str) -> Event {
+        Event::Delete {
+            key: key.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_publish() {
+        let mut subscribers = Subscribers::default();
+        let mut users = subscribers.subscribe("user:");
+        let all = subscribers.subscribe("");
+        assert!(subscribers.watches("user:1"));
+
+        subscribers.publish(delete("user:1"));
+        subscribers.publish(delete("order:1"));
+        assert_eq!(users.try_next(), Some(delete("user:1")));
+        assert_eq!(users.try_next(), None);
+        assert_eq!(users.next_timeout(Duration::from_millis(10)), None);
+
+        // Dropped subscribers are forgotten, the others end with the database
+        drop(users);
+        assert!(subscribers.watches("user:1"));
+        subscribers.publish(delete("user:2"));
+        assert_eq!(subscribers.channels.len(), 1);
+        let handle = std::thread::spawn(move || all.collect::<Vec<_>>());
+        drop(subscribers);
+        assert_eq!(
+            handle.join().unwrap(),
+            vec![delete("user:1"), delete("order:1"), delete("user:2")]
+        );
+    }
+}
@@ -0,0 +1,267 @@
+use crate::cache::Key;
+use bincode::{deserialize_from, serialize_into};
+use fs2::FileExt;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter};
+use std::path::PathBuf;
+
+/// How `put` decides where new values are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AllocationMode {
+    /// Always append to the end of the data file.
+    #[default]
+    Append,
+    /// Use the first free region (by offset) that is large enough.
+    FirstFit,
+    /// Use the smallest free region that is large enough.
+    BestFit,
+}
+
+/// Free regions of the data file that new values can be written to.
+///
+/// Regions released by the database are kept pending until the next persist, so the index table on
+/// disk never points to data that was overwritten.
+pub(crate) struct FreeList {
+    by_offset: BTreeMap<u64, usize>,
+    by_size: BTreeSet<(usize, u64)>,
+    pending: Vec<Key>,
+    file_path: Option<PathBuf>, // `None` for databases in memory
+}
+
+impl FreeList {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self {
+            by_offset: BTreeMap::new(),
+            by_size: BTreeSet::new(),
+            pending: Vec::new(),
+            file_path: path,
+        }
+    }
+
+    /// Loads the persisted free list, as long as all of its regions are within `dead_zones`.
+    /// Otherwise, the free list is rebuilt from `dead_zones`.
+    pub fn load(path: Option<PathBuf>, dead_zones: &[Key]) -> Self {
+        let persisted: Option<Vec<Key>> = path
+            .as_ref()
+            .and_then(|path| File::open(path).ok())
+            .and_then(|file| deserialize_from(BufReader::new(file)).ok());
+
+        let is_dead = |(offset, length): &Key| {
+            let i = dead_zones.partition_point(|zone| zone.0 <= *offset);
+            i > 0 && {
+                let zone = dead_zones[i - 1];
+                offset + *length as u64 <= zone.0 + zone.1 as u64
+            }
+        };
+        let regions = match persisted {
+            Some(regions) if regions.iter().all(is_dead) => regions,
+            _ => dead_zones.to_vec(),
+        };
+
+        let mut free_list = Self::new(path);
+        for (offset, length) in regions {
+            free_list.insert(offset, length);
+        }
+        free_list
+    }
+
+    pub fn persist(&self) -> anyhow::Result<()> {
+        let Some(file_path) = &self.file_path else {
+            return Ok(());
+        };
+        let file = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(file_path)?;
+        file.lock_exclusive()?;
+
+        let regions = self.regions();
+        serialize_into(BufWriter::new(&file), &regions)?;
+
+        FileExt::unlock(&file)?;
+        Ok(())
+    }
+
+    pub fn regions(&self) -> Vec<Key> {
+        self.by_offset.iter().map(|(o, l)| (*o, *l)).collect()
+    }
+
+    /// Marks a region as free, once the database was persisted.
+    pub fn release(&mut self, key: Key) {
+        if key.1 > 0 {
+            self.pending.push(key);
+        }
+    }
+
+    /// Makes the pending regions available for allocation and returns them.
+    ///
+    /// Regions ending after `limit` are dropped, they are about to be compacted.
+    // `Option::is_none_or` would need Rust 1.82
+    #[allow(clippy::unnecessary_map_or)]
+    pub fn promote(&mut self, limit: Option<u64>) -> Vec<Key> {
+        let pending = std::mem::take(&mut self.pending);
+        let promoted = pending
+            .into_iter()
+            .filter(|(offset, length)| limit.map_or(true, |l| offset + *length as u64 <= l))
+            .collect::<Vec<_>>();
+
+        for (offset, length) in &promoted {
+            self.insert(*offset, *length);
+        }
+        promoted
+    }
+
+    pub fn clear(&mut self) {
+        self.by_offset.clear();
+        self.by_size.clear();
+        self.pending.clear();
+    }
+
+    #[cfg(feature = "write")]
+    pub fn clear_pending(&mut self) {
+        self.pending.clear();
+    }
+
+    /// The regions released since the last persist, to restore them with `restore_pending`.
+    #[cfg(feature = "write")]
+    pub fn pending(&self) -> Vec<Key> {
+        self.pending.clone()
+    }
+
+    #[cfg(feature = "write")]
+    pub fn restore_pending(&mut self, pending: Vec<Key>) {
+        self.pending = pending;
+    }
+
+    /// Takes `length` bytes out of a free region, returns the offset to write to.
+    #[cfg(any(feature = "write", test))]
+    pub fn allocate(&mut self, length: usize, mode: AllocationMode) -> Option<u64> {
+        if length == 0 {
+            return None;
+        }
+
+        let (offset, size) = match mode {
+            AllocationMode::Append => return None,
+            AllocationMode::FirstFit => self
+                .by_offset
+                .iter()
+                .find(|(_, size)| **size >= length)
+                .map(|(offset, size)| (*offset, *size))?,
+            AllocationMode::BestFit => self
+                .by_size
+                .range((length, 0)..)
+                .next()
+                .map(|(size, offset)| (*offset, *size))?,
+        };
+
+        self.remove(offset, size);
+        if size > length {
+            self.insert_exact(offset + length as u64, size - length);
+        }
+        Some(offset)
+    }
+
+    // Inserts a region, merging it with adjacent free regions
+    fn insert(&mut self, mut offset: u64, mut length: usize) {
+        if let Some((&prev_offset, &prev_length)) = self.by_offset.range(..offset).next_back() {
+            if prev_offset + prev_length as u64 >= offset {
+                let end = (offset + length as u64).max(prev_offset + prev_length as u64);
+                self.remove(prev_offset, prev_length);
+                offset = prev_offset;
+                length = (end - offset) as usize;
+            }
+        }
+
+        while let Some((&next_offset, &next_length)) = self.by_offset.range(offset..).next() {
+            if next_offset > offset + length as u64 {
+                break;
+            }
+            let end = (offset + length as u64).max(next_offset + next_length as u64);
+            self.remove(next_offset, next_length);
+            length = (end - offset) as usize;
+        }
+
+        self.insert_exact(offset, length);
+    }
+
+    fn insert_exact(&mut self, offset: u64, length: usize) {
+        self.by_offset.insert(offset, length);
+        self.by_size.insert((length, offset));
+    }
+
+    fn remove(&mut self, offset: u64, length: usize) {
+        self.by_offset.remove(&offset);
+        self.by_size.remove(&(length, offset));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn free_list(regions: &[Key]) -> FreeList {
+        let mut free_list = FreeList::new(None);
+        for key in regions {
+            free_list.release(*key);
+        }
+        free_list.promote(None);
+        free_list
+    }
+
+    #[test]
+    fn test_adjacent_regions_are_merged() {
+        let free_list = free_list(&[(10, 10), (30, 10), (20, 10), (50, 5)]);
+        assert_eq!(free_list.regions(), vec![(10, 30), (50, 5)]);
+    }
+
+    #[test]
+    fn test_first_fit() {
+        let mut free_list = free_list(&[(0, 10), (20, 4), (40, 6)]);
+        assert_eq!(free_list.allocate(5, AllocationMode::FirstFit), Some(0));
+        assert_eq!(free_list.allocate(5, AllocationMode::FirstFit), Some(5));
+        assert_eq!(free_list.allocate(5, AllocationMode::FirstFit), Some(40));
+        assert_eq!(free_list.allocate(5, AllocationMode::FirstFit), None);
+        assert_eq!(free_list.regions(), vec![(20, 4), (45, 1)]);
+    }
+
+    #[test]
+    fn test_best_fit() {
+        let mut free_list = free_list(&[(0, 10), (20, 4), (40, 6)]);
+        assert_eq!(free_list.allocate(5, AllocationMode::BestFit), Some(40));
+        assert_eq!(free_list.allocate(4, AllocationMode::BestFit), Some(20));
+        assert_eq!(free_list.allocate(1, AllocationMode::BestFit), Some(45));
+        assert_eq!(free_list.allocate(5, AllocationMode::Append), None);
+    }
+
+    #[test]
+    fn test_promote_respects_limit() {
+        let mut free_list = FreeList::new(None);
+        free_list.release((0, 10));
+        free_list.release((20, 10));
+        assert_eq!(free_list.allocate(5, AllocationMode::FirstFit), None);
+
+        assert_eq!(free_list.promote(Some(25)), vec![(0, 10)]);
+        assert_eq!(free_list.regions(), vec![(0, 10)]);
+    }
+
+    #[test]
+    fn test_load_validates_regions() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("free");
+
+        let mut free_list = free_list(&[(0, 10)]);
+        free_list.file_path = Some(path.clone());
+        free_list.persist()?;
+
+        let loaded = FreeList::load(Some(path.clone()), &[(0, 20), (40, 10)]);
+        assert_eq!(loaded.regions(), vec![(0, 10)]);
+
+        // The persisted region is not dead anymore
+        let loaded = FreeList::load(Some(path), &[(5, 20), (40, 10)]);
+        assert_eq!(loaded.regions(), vec![(5, 20), (40, 10)]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+use crate::cache::Key;
+
+/// The outcome of a garbage collection run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcReport {
+    /// Number of live bytes that were moved to a new location.
+    pub moved_bytes: u64,
+    /// Number of bytes the data file shrank by.
+    pub reclaimed_bytes: u64,
+    /// Whether the compaction cycle is complete. If false, more steps are required.
+    pub finished: bool,
+}
+
+/// Position of an in-progress incremental compaction.
+///
+/// Everything before `write` is compacted, everything between `write` and `read` is dead, and
+/// everything after `read` has not been visited yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CompactionCursor {
+    pub read: u64,
+    pub write: u64,
+    /// Bytes moved since the cycle started
+    pub moved_bytes: u64,
+}
+
+impl CompactionCursor {
+    pub fn new(offset: u64) -> Self {
+        Self {
+            read: offset,
+            write: offset,
+            moved_bytes: 0,
+        }
+    }
+}
+
+/// A contiguous range of live data, moved as a single unit.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Run {
+    pub offset: u64,
+    pub length: usize,
+    pub extents: Vec<Key>,
+}
+
+/// Groups `extents` (sorted by offset) into runs, until at least `max_bytes` are covered.
+///
+/// Overlapping extents always end up in the same run, adjacent ones are merged while the budget allows.
+pub(crate) fn collect_runs<I: Iterator<Item = Key>>(extents: I, max_bytes: usize) -> Vec<Run> {
+    let mut runs: Vec<Run> = Vec::new();
+    let mut total = 0;
+
+    for extent in extents {
+        let (offset, length) = extent;
+        if let Some(run) = runs.last_mut() {
+            let end = run.offset + run.length as u64;
+            let overlapping = offset < end;
+            let free = length == 0 || run.length == 0 || total + length <= max_bytes;
+            if overlapping || (offset == end && free) {
+                let new_end = end.max(offset + length as u64);
+                total += (new_end - end) as usize;
+                run.length = (new_end - run.offset) as usize;
+                run.extents.push(extent);
+                continue;
+            }
+        }
+
+        if total >= max_bytes && !runs.is_empty() {
+            break;
+        }
+        total += length;
+        runs.push(Run {
+            offset,
+            length,
+            extents: vec![extent],
+        });
+    }
+
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect_runs_respects_budget() {
+        let extents = vec![(10, 10), (20, 10), (40, 10), (60, 10)];
+        let runs = collect_runs(extents.into_iter(), 20);
+
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].offset, 10);
+        assert_eq!(runs[0].length, 20);
+        assert_eq!(runs[0].extents, vec![(10, 10), (20, 10)]);
+    }
+
+    #[test]
+    fn test_collect_runs_keeps_overlaps_together() {
+        let extents = vec![(0, 0), (0, 10), (5, 10), (30, 10)];
+        let runs = collect_runs(extents.into_iter(), 1);
+
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].length, 15);
+        assert_eq!(runs[0].extents.len(), 3);
+    }
+}
@@ -1,4 +1,15 @@
+mod free_list;
+mod incremental;
+mod policy;
 mod shrink;
+mod space;
 mod zones;
 
+pub use free_list::AllocationMode;
+pub(crate) use free_list::FreeList;
+pub use incremental::GcReport;
+pub(crate) use incremental::{collect_runs, CompactionCursor};
+pub use policy::{GcMode, GcPolicy};
 pub use shrink::compact_file;
+pub use space::SpaceStats;
+pub(crate) use space::{Reference, SpaceTracker, Table};
@@ -0,0 +1,87 @@
+use crate::garbage_collection::SpaceStats;
+use std::time::{Duration, Instant};
+
+/// How garbage collection reclaims dead space.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GcMode {
+    /// Moves live data to the start of the data file and truncates it.
+    #[default]
+    Compact,
+    /// Deallocates dead regions of the data file in place, without moving live data.
+    ///
+    /// This keeps offsets stable and only costs one call per dead region. It is only supported on
+    /// Linux, and on file systems supporting `FALLOC_FL_PUNCH_HOLE` (e.g. ext4, xfs, btrfs, tmpfs).
+    /// Everywhere else, garbage collection falls back to compaction.
+    PunchHoles,
+}
+
+/// Thresholds that trigger garbage collection automatically.
+///
+/// The policy is evaluated after every `put`, `merge`, `apply_batch` and `persist`, a collection
+/// only runs once all thresholds are crossed.
+///
+/// A collection persists the index table, like [`Database::persist`](crate::Database::persist).
+/// Writes made since the last `persist` are therefore committed as soon as a write triggers a
+/// collection, and survive a crash even if `persist` is never called.
+#[derive(Debug, Clone)]
+pub struct GcPolicy {
+    /// Share of dead bytes in the data file, between `0.0` and `1.0`, above which to collect.
+    pub dead_ratio: f64,
+    /// Data files smaller than this (in bytes) are never collected.
+    pub min_file_size: u64,
+    /// Minimum time between two automatic collections.
+    pub min_interval: Duration,
+}
+
+impl Default for GcPolicy {
+    fn default() -> Self {
+        GcPolicy {
+            dead_ratio: 0.5,
+            min_file_size: 1024 * 1024,
+            min_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+impl GcPolicy {
+    pub(crate) fn should_collect(&self, stats: &SpaceStats, last_run: Option<Instant>) -> bool {
+        if stats.dead_bytes == 0 || stats.live_bytes + stats.dead_bytes < self.min_file_size {
+            return false;
+        }
+        if stats.fragmentation < self.dead_ratio {
+            return false;
+        }
+
+        match last_run {
+            Some(last_run) => last_run.elapsed() >= self.min_interval,
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(live_bytes: u64, dead_bytes: u64) -> SpaceStats {
+        SpaceStats {
+            live_bytes,
+            dead_bytes,
+            fragmentation: dead_bytes as f64 / (live_bytes + dead_bytes) as f64,
+        }
+    }
+
+    #[test]
+    fn test_should_collect() {
+        let policy = GcPolicy {
+            dead_ratio: 0.5,
+            min_file_size: 100,
+            min_interval: Duration::from_secs(60),
+        };
+
+        assert!(policy.should_collect(&stats(50, 50), None));
+        assert!(!policy.should_collect(&stats(60, 40), None));
+        assert!(!policy.should_collect(&stats(10, 80), None));
+        assert!(!policy.should_collect(&stats(50, 50), Some(Instant::now())));
+    }
+}
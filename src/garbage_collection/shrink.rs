@@ -4,7 +4,7 @@ pub type Key2Key = (Key, Key); // old key to new key transformation
 
 pub fn compact_links(keys: &mut [(String, Key)]) -> Vec<(String, Key2Key)> {
     // Sort the keys based on their offsets for consistency.
-    keys.sort_by(|a, b| a.1 .0.cmp(&b.1 .0));
+    keys.sort_by_key(|a| a.1 .0);
 
     let mut transformations = Vec::new();
 
@@ -48,12 +48,13 @@ mod tests {
 
     #[test]
     fn test_compact_links() {
-        let mut keys = Vec::new();
-        keys.push(("abc".to_string(), (0, 10)));
-        keys.push(("def".to_string(), (20, 10)));
-        keys.push(("ghi".to_string(), (40, 10)));
-        keys.push(("jkl".to_string(), (60, 10)));
-        keys.push(("mno".to_string(), (80, 10)));
+        let mut keys = vec![
+            ("abc".to_string(), (0, 10)),
+            ("def".to_string(), (20, 10)),
+            ("ghi".to_string(), (40, 10)),
+            ("jkl".to_string(), (60, 10)),
+            ("mno".to_string(), (80, 10)),
+        ];
 
         let transformations = compact_links(&mut keys);
         assert_eq!(transformations.len(), 5);
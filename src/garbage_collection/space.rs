@@ -0,0 +1,249 @@
+use crate::cache::Key;
+use crate::garbage_collection::zones::compute_dead_zones;
+use std::collections::BTreeMap;
+
+/// A breakdown of how the data file is used.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SpaceStats {
+    /// Bytes referenced by at least one key.
+    pub live_bytes: u64,
+    /// Bytes no longer referenced by any key, reclaimable by garbage collection.
+    pub dead_bytes: u64,
+    /// Share of the data file occupied by dead bytes, between `0.0` and `1.0`.
+    pub fragmentation: f64,
+}
+
+/// The index table holding a key that references an extent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Table {
+    /// The default keyspace.
+    Default,
+    /// The keyspace of a tree.
+    Tree(String),
+    /// The merge operands of the default keyspace.
+    Operands,
+}
+
+/// A key referencing an extent, and the index table it is stored in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Reference {
+    pub table: Table,
+    pub key: String,
+}
+
+impl Reference {
+    /// A key of the keyspace `tree`, `None` is the default keyspace.
+    pub fn keyspace(tree: Option<&str>, key: &str) -> Self {
+        let table = match tree {
+            Some(name) => Table::Tree(name.to_string()),
+            None => Table::Default,
+        };
+        Self {
+            table,
+            key: key.to_string(),
+        }
+    }
+
+    /// An entry of the merge operands table.
+    pub fn operand(entry: &str) -> Self {
+        Self {
+            table: Table::Operands,
+            key: entry.to_string(),
+        }
+    }
+}
+
+/// Keeps track of every extent referenced by the index tables, and of the keys referencing it.
+///
+/// Links share an extent with the key they were created from, so an extent only becomes dead once
+/// its last reference is removed. The keys of an extent are known, so moving it only updates them.
+#[derive(Default)]
+pub(crate) struct SpaceTracker {
+    extents: BTreeMap<Key, Vec<Reference>>,
+    live_bytes: u64,
+}
+
+impl SpaceTracker {
+    pub fn from_keys<I: IntoIterator<Item = (Reference, Key)>>(keys: I) -> Self {
+        let mut tracker = Self::default();
+        for (reference, key) in keys {
+            tracker.reference(reference, key);
+        }
+        tracker
+    }
+
+    /// Registers a new reference to `key`.
+    pub fn reference(&mut self, reference: Reference, key: Key) {
+        let references = self.extents.entry(key).or_default();
+        if references.is_empty() {
+            self.live_bytes += key.1 as u64;
+        }
+        references.push(reference);
+    }
+
+    /// Drops a reference to `key`, returns true if the extent became dead.
+    pub fn release(&mut self, reference: &Reference, key: Key) -> bool {
+        let Some(references) = self.extents.get_mut(&key) else {
+            return false;
+        };
+        if let Some(i) = references.iter().position(|r| r == reference) {
+            references.swap_remove(i);
+        }
+        if !references.is_empty() {
+            return false;
+        }
+        self.extents.remove(&key);
+        self.live_bytes -= key.1 as u64;
+        true
+    }
+
+    /// Replaces the reference of `reference` to `previous` (if any) with one to `current` (if
+    /// any).
+    ///
+    /// Returns `previous` if it became dead.
+    pub fn replace(
+        &mut self,
+        reference: Reference,
+        previous: Option<Key>,
+        current: Option<Key>,
+    ) -> Option<Key> {
+        if let Some(current) = current {
+            self.reference(reference.clone(), current);
+        }
+        previous.filter(|previous| self.release(&reference, *previous))
+    }
+
+    /// The keys referencing `key`.
+    pub fn references(&self, key: Key) -> &[Reference] {
+        self.extents.get(&key).map_or(&[], Vec::as_slice)
+    }
+
+    /// Moves the references of extents that were relocated by the garbage collector.
+    pub fn relocate(&mut self, moves: &[(Key, Key)]) {
+        // Release everything first, a new location may equal the old location of a later move
+        let references = moves
+            .iter()
+            .map(|(old, _)| self.extents.remove(old).unwrap_or_default())
+            .collect::<Vec<_>>();
+        for ((old, new), references) in moves.iter().zip(references) {
+            if references.is_empty() {
+                continue;
+            }
+            self.live_bytes -= old.1 as u64;
+            let entry = self.extents.entry(*new).or_default();
+            if entry.is_empty() {
+                self.live_bytes += new.1 as u64;
+            }
+            entry.extend(references);
+        }
+    }
+
+    /// All extents starting at or after `offset`, in file order.
+    pub fn extents_from(&self, offset: u64) -> impl Iterator<Item = Key> + '_ {
+        self.extents.range((offset, 0)..).map(|(key, _)| *key)
+    }
+
+    /// Returns all regions not referenced by any key, in file order.
+    ///
+    /// `segments` holds the start offset and size of every data segment.
+    pub fn dead_zones(&self, segments: &[(u64, u64)]) -> Vec<Key> {
+        let mut dead_zones = Vec::new();
+        for (start, size) in segments {
+            let end = start + size;
+            let mut extents = self
+                .extents
+                .range((*start, 0)..(end, 0))
+                .map(|(key, _)| (key.0 - start, key.1))
+                .collect::<Vec<_>>();
+
+            let zones = compute_dead_zones(&mut extents, *size);
+            dead_zones.extend(zones.into_iter().map(|zone| (zone.0 + start, zone.1)));
+        }
+        dead_zones
+    }
+
+    /// Returns the offset of the first byte not referenced by any key, if there is one.
+    pub fn first_dead_offset(&self, segments: &[(u64, u64)]) -> Option<u64> {
+        self.dead_zones(segments).first().map(|zone| zone.0)
+    }
+
+    pub fn stats(&self, file_size: u64) -> SpaceStats {
+        let live_bytes = self.live_bytes.min(file_size);
+        let dead_bytes = file_size - live_bytes;
+        let fragmentation = if file_size == 0 {
+            0.0
+        } else {
+            dead_bytes as f64 / file_size as f64
+        };
+
+        SpaceStats {
+            live_bytes,
+            dead_bytes,
+            fragmentation,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn references(keys: &[(&str, Key)]) -> Vec<(Reference, Key)> {
+        keys.iter()
+            .map(|(name, key)| (Reference::keyspace(None, name), *key))
+            .collect()
+    }
+
+    #[test]
+    fn test_links_share_extents() {
+        let mut tracker = SpaceTracker::from_keys(references(&[
+            ("a", (0, 10)),
+            ("b", (10, 5)),
+            ("c", (0, 10)),
+        ]));
+        assert_eq!(tracker.stats(15).live_bytes, 15);
+        assert_eq!(
+            tracker.references((0, 10)),
+            [
+                Reference::keyspace(None, "a"),
+                Reference::keyspace(None, "c")
+            ]
+        );
+
+        assert!(!tracker.release(&Reference::keyspace(None, "a"), (0, 10)));
+        assert_eq!(tracker.stats(15).dead_bytes, 0);
+
+        assert!(tracker.release(&Reference::keyspace(None, "c"), (0, 10)));
+        let stats = tracker.stats(15);
+        assert_eq!(stats.live_bytes, 5);
+        assert_eq!(stats.dead_bytes, 10);
+        assert_eq!(tracker.first_dead_offset(&[(0, 15)]), Some(0));
+    }
+
+    #[test]
+    fn test_dead_zones_per_segment() {
+        let tracker = SpaceTracker::from_keys(references(&[
+            ("a", (0, 10)),
+            ("b", (100, 5)),
+            ("c", (110, 5)),
+        ]));
+        let zones = tracker.dead_zones(&[(0, 20), (100, 20)]);
+        assert_eq!(zones, vec![(10, 10), (105, 5), (115, 5)]);
+    }
+
+    #[test]
+    fn test_relocate_onto_previous_location() {
+        let mut tracker = SpaceTracker::from_keys(references(&[("a", (10, 5)), ("b", (15, 5))]));
+        tracker.relocate(&[((10, 5), (5, 5)), ((15, 5), (10, 5))]);
+        assert_eq!(
+            tracker.references((10, 5)),
+            [Reference::keyspace(None, "b")]
+        );
+
+        assert_eq!(
+            tracker.extents_from(0).collect::<Vec<_>>(),
+            [(5, 5), (10, 5)]
+        );
+        assert_eq!(tracker.stats(15).live_bytes, 10);
+    }
+}
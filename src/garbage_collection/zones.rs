@@ -1,10 +1,9 @@
 use crate::cache::Key;
 
 /// Returns a list of dead zones in the form of (offset, size) tuples.
-#[allow(unused)]
 pub fn compute_dead_zones(keys: &mut [Key], file_size: u64) -> Vec<Key> {
     // Sort the keys based on their offsets.
-    keys.sort_by(|a, b| a.0.cmp(&b.0));
+    keys.sort_by_key(|a| a.0);
 
     let mut dead_zones = Vec::new();
 
@@ -18,7 +17,8 @@ pub fn compute_dead_zones(keys: &mut [Key], file_size: u64) -> Vec<Key> {
             dead_zones.push((last_end, (offset - last_end) as usize));
         }
 
-        last_end = offset + size as u64;
+        // Extents may overlap (e.g. empty values), so never move the end backwards
+        last_end = last_end.max(offset + size as u64);
     }
 
     // Check for a dead zone after the last key until the end of the file.
@@ -35,12 +35,7 @@ mod tests {
 
     #[test]
     fn test_compute_dead_zones() {
-        let mut keys = Vec::new();
-        keys.push((0, 10));
-        keys.push((20, 10));
-        keys.push((40, 10));
-        keys.push((60, 10));
-        keys.push((80, 10));
+        let mut keys = vec![(0, 10), (20, 10), (40, 10), (60, 10), (80, 10)];
 
         let dead_zones = compute_dead_zones(&mut keys, 100);
         assert_eq!(dead_zones.len(), 5);
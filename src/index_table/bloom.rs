@@ -0,0 +1,316 @@
+use crate::cache::Key;
+use crate::index_table::{hash, mix, IndexTable};
+use anyhow::{bail, Result};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const MAGIC: &[u8; 8] = b"RDBBLM01";
+const HEADER_SIZE: usize = 48;
+// Filters are sized for at least this many keys, so small databases don't rebuild constantly
+const MIN_CAPACITY: usize = 1024;
+
+/// A Bloom filter over the keys of an index table. It never reports a key that was inserted as
+/// missing, and reports other keys as present at about the configured false positive rate, as
+/// long as no more keys than its capacity were inserted.
+#[derive(Clone)]
+pub(crate) struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+    capacity: usize,
+    false_positive_rate: f64,
+}
+
+impl BloomFilter {
+    pub fn new(capacity: usize, false_positive_rate: f64) -> Self {
+        let capacity = capacity.max(MIN_CAPACITY);
+        let false_positive_rate = false_positive_rate.clamp(1e-9, 0.5);
+        let bits_per_key = -false_positive_rate.ln() / std::f64::consts::LN_2.powi(2);
+        let bits = (capacity as f64 * bits_per_key).ceil() as usize;
+        let hashes = (bits_per_key * std::f64::consts::LN_2)
+            .round()
+            .clamp(1.0, 16.0) as u32;
+
+        Self {
+            bits: vec![0; bits.div_ceil(64)],
+            hashes,
+            capacity,
+            false_positive_rate,
+        }
+    }
+
+    pub fn insert(&mut self, key: &str) {
+        for bit in self.bit_indices(key) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    pub fn may_contain(&self, key: &str) -> bool {
+        self.bit_indices(key)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    // Double hashing, the second hash is odd so it never degenerates into a single bit
+    fn bit_indices(&self, key: &str) -> impl Iterator<Item = usize> {
+        let bits = self.bits.len() as u64 * 64;
+        let first = hash(key.as_bytes(), 0);
+        let second = mix(first) | 1;
+        (0..self.hashes as u64)
+            .map(move |i| (first.wrapping_add(i.wrapping_mul(second)) % bits) as usize)
+    }
+
+    // The file holds the key count of the index table, to detect filters that are out of date
+    fn write(&self, path: &Path, keys: usize) -> Result<()> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + self.bits.len() * 8);
+        bytes.extend_from_slice(MAGIC);
+        for value in [
+            self.hashes as u64,
+            self.capacity as u64,
+            self.false_positive_rate.to_bits(),
+            keys as u64,
+            self.bits.len() as u64,
+        ] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        for word in &self.bits {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+
+        // A torn filter could report persisted keys as missing, so it is replaced at once
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        std::fs::write(&temp_path, bytes)?;
+        std::fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    // Returns the filter and the key count it was written for
+    fn read(path: &Path) -> Result<(Self, usize)> {
+        let bytes = std::fs::read(path)?;
+        if bytes.len() < HEADER_SIZE || &bytes[..8] != MAGIC {
+            bail!("{} is not a Bloom filter", path.display());
+        }
+        let header: Vec<u64> = bytes[8..HEADER_SIZE]
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        let words = header[4] as usize;
+        if bytes.len() != HEADER_SIZE + words * 8 || words == 0 {
+            bail!("Bloom filter {} is truncated", path.display());
+        }
+
+        let filter = Self {
+            bits: bytes[HEADER_SIZE..]
+                .chunks_exact(8)
+                .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+                .collect(),
+            hashes: header[0] as u32,
+            capacity: header[1] as usize,
+            false_positive_rate: f64::from_bits(header[2]),
+        };
+        Ok((filter, header[3] as usize))
+    }
+}
+
+/// Wraps an index table with a [`BloomFilter`], so lookups of missing keys mostly don't reach
+/// the table. The filter is persisted before the table, which means the filter on disk always
+/// holds every key of the table on disk.
+///
+/// Deleted keys stay in the filter, it is rebuilt once they make up a third of its capacity,
+/// once the table outgrows it and whenever all keys are replaced, as by garbage collection.
+pub(crate) struct BloomIndexTable {
+    inner: Box<dyn IndexTable>,
+    filter: BloomFilter,
+    deleted: usize,
+    path: Option<PathBuf>, // `None` keeps the filter in memory only
+    changed: AtomicBool,
+}
+
+impl BloomIndexTable {
+    /// Loads the filter at `path` if it is up to date with `inner`, builds it otherwise.
+    pub fn open(
+        inner: Box<dyn IndexTable>,
+        false_positive_rate: f64,
+        path: Option<PathBuf>,
+    ) -> Self {
+        let loaded = path
+            .as_deref()
+            .and_then(|path| BloomFilter::read(path).ok())
+            .filter(|(filter, keys)| {
+                filter.false_positive_rate == false_positive_rate && *keys == inner.len()
+            });
+
+        let mut table = Self {
+            filter: BloomFilter::new(0, false_positive_rate),
+            inner,
+            deleted: 0,
+            path,
+            changed: AtomicBool::new(false),
+        };
+        match loaded {
+            Some((filter, _)) => table.filter = filter,
+            None => table.rebuild(table.inner.all_key_values().iter().map(|(key, _)| key)),
+        }
+        table
+    }
+
+    fn rebuild<'a>(&mut self, keys: impl Iterator<Item = &'a String>) {
+        let mut filter = BloomFilter::new(self.inner.len() * 2, self.filter.false_positive_rate);
+        for key in keys {
+            filter.insert(key);
+        }
+        self.filter = filter;
+        self.deleted = 0;
+        self.changed.store(true, Ordering::Relaxed);
+    }
+}
+
+impl IndexTable for BloomIndexTable {
+    fn get(&self, key: &str) -> Option<Key> {
+        if !self.filter.may_contain(key) {
+            return None;
+        }
+        self.inner.get(key)
+    }
+
+    fn try_get(&self, key: &str) -> Result<Option<Key>> {
+        if !self.filter.may_contain(key) {
+            return Ok(None);
+        }
+        self.inner.try_get(key)
+    }
+
+    fn insert(&mut self, key: &str, value: Key) -> Result<()> {
+        self.inner.insert(key, value)?;
+        if self.inner.len() > self.filter.capacity {
+            let key_values = self.inner.all_key_values();
+            self.rebuild(key_values.iter().map(|(key, _)| key));
+            return Ok(());
+        }
+
+        self.filter.insert(key);
+        self.changed.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &str) -> Result<()> {
+        let len = self.inner.len();
+        self.inner.delete(key)?;
+        self.deleted += len - self.inner.len();
+        if self.deleted > self.filter.capacity / 3 {
+            let key_values = self.inner.all_key_values();
+            self.rebuild(key_values.iter().map(|(key, _)| key));
+        }
+        Ok(())
+    }
+
+    fn is_writable(&self) -> bool {
+        self.inner.is_writable()
+    }
+
+    fn load(&mut self) -> Result<()> {
+        self.inner.load()?;
+        let key_values = self.inner.all_key_values();
+        self.rebuild(key_values.iter().map(|(key, _)| key));
+        Ok(())
+    }
+
+    fn persist(&self) -> Result<()> {
+        if let Some(path) = &self.path {
+            if self.changed.swap(false, Ordering::Relaxed) {
+                if let Err(e) = self.filter.write(path, self.inner.len()) {
+                    self.changed.store(true, Ordering::Relaxed);
+                    return Err(e);
+                }
+            }
+        }
+        self.inner.persist()
+    }
+
+    #[cfg(test)]
+    fn index_type(&self) -> &str {
+        self.inner.index_type()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn all_key_values(&self) -> Vec<(String, Key)> {
+        self.inner.all_key_values()
+    }
+
+    fn range(&self, start: Bound<&str>, end: Bound<&str>) -> Vec<(String, Key)> {
+        self.inner.range(start, end)
+    }
+
+    fn scan_prefix(&self, prefix: &str) -> Vec<(String, Key)> {
+        self.inner.scan_prefix(prefix)
+    }
+
+    fn replace_all(&mut self, key_values: Vec<(String, Key)>) -> Result<()> {
+        self.inner.replace_all(key_values)?;
+        let key_values = self.inner.all_key_values();
+        self.rebuild(key_values.iter().map(|(key, _)| key));
+        Ok(())
+    }
+
+    #[cfg(feature = "write")]
+    fn snapshot(&self) -> Box<dyn IndexTable> {
+        Box::new(Self {
+            inner: self.inner.snapshot(),
+            filter: self.filter.clone(),
+            deleted: self.deleted,
+            path: self.path.clone(),
+            changed: AtomicBool::new(self.changed.load(Ordering::Relaxed)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index_table::hash_map::HashMapIndexTable;
+
+    #[test]
+    fn test_false_positive_rate() {
+        let mut filter = BloomFilter::new(10_000, 0.01);
+        for i in 0..10_000 {
+            filter.insert(&format!("key{}", i));
+        }
+        assert!((0..10_000).all(|i| filter.may_contain(&format!("key{}", i))));
+
+        let false_positives = (0..10_000)
+            .filter(|i| filter.may_contain(&format!("missing{}", i)))
+            .count();
+        assert!(false_positives < 200, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn test_persisted_with_the_index() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let index_path = temp_dir.path().join("index");
+        let path = temp_dir.path().join("bloom");
+        let inner = Box::new(HashMapIndexTable::new(index_path.clone())?);
+        let mut table = BloomIndexTable::open(inner, 0.01, Some(path.clone()));
+        for i in 0..5000 {
+            table.insert(&format!("key{}", i), (i, 1))?;
+        }
+        assert!(table.filter.capacity >= 5000);
+        table.delete("key0")?;
+        assert_eq!(table.get("key0"), None);
+        assert_eq!(table.get("key1"), Some((1, 1)));
+        table.persist()?;
+
+        let (filter, keys) = BloomFilter::read(&path)?;
+        assert_eq!(keys, 4999);
+        assert!(filter.may_contain("key4999"));
+
+        // A filter that doesn't match the index is rebuilt
+        let mut inner = Box::new(HashMapIndexTable::new(index_path)?);
+        inner.insert("other", (0, 1))?;
+        let table = BloomIndexTable::open(inner, 0.01, Some(path));
+        assert_eq!(table.get("other"), Some((0, 1)));
+        Ok(())
+    }
+}
@@ -0,0 +1,970 @@
+use crate::cache::list::List;
+use crate::cache::Key;
+use crate::index_table::{is_empty_range, IndexTable};
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+const MAGIC: &[u8; 8] = b"RDBBPT01";
+const PAGE_SIZE: usize = 4096;
+// The first page holds two header slots, each commit overwrites the older one
+const HEADER_SIZE: usize = 56;
+const SLOT_SIZE: usize = PAGE_SIZE / 2;
+/// Longest key a paged index table accepts, so that every page holds at least a few keys.
+pub const MAX_KEY_SIZE: usize = 1024;
+// Clean pages kept in memory, 4MB
+const CACHED_PAGES: usize = 1024;
+// Smaller files are never compacted, as there is little to gain
+const MIN_COMPACT_PAGES: u64 = 64;
+const LEAF: u8 = 0;
+const INTERNAL: u8 = 1;
+
+/// An index table stored as a B+tree of fixed size pages, of which only the ones a lookup
+/// touches are read. Opening it reads a single page, regardless of the number of keys.
+///
+/// Pages are never modified in place. A changed page is written to a new location on `persist`,
+/// along with its parents up to the root, and a header pointing to the new root is written
+/// last, so a crash leaves the previous tree intact. Once more than half of the file is taken
+/// by replaced pages, `persist` rewrites it. Pages are not merged as keys are deleted, only
+/// empty ones are dropped, garbage collection rebuilds the tree compactly.
+///
+/// The first page holds two headers (magic, sequence number, root page, number of keys, number
+/// of pages and replaced pages, checksum), the one with the higher valid sequence number is
+/// current. Leaf pages hold keys and locations, internal pages keys and child pages.
+pub struct BPlusTreeIndexTable {
+    path: PathBuf,
+    store: Arc<Mutex<Store>>, // shared with snapshots, whose clean pages are the same
+    tree: Mutex<Tree>,
+}
+
+#[derive(Clone)]
+enum Node {
+    Leaf {
+        keys: Vec<String>,
+        values: Vec<Key>,
+    },
+    // `children[i]` holds the keys below `keys[i]` and at or above `keys[i - 1]`
+    Internal {
+        keys: Vec<String>,
+        children: Vec<u64>,
+    },
+}
+
+impl Node {
+    fn keys(&self) -> &[String] {
+        match self {
+            Node::Leaf { keys, .. } | Node::Internal { keys, .. } => keys,
+        }
+    }
+
+    // Bytes taken by an entry besides its key
+    fn entry_size(&self) -> usize {
+        match self {
+            Node::Leaf { .. } => 2 + 16,
+            Node::Internal { .. } => 2 + 8,
+        }
+    }
+
+    fn size(&self) -> usize {
+        let first_child = match self {
+            Node::Leaf { .. } => 0,
+            Node::Internal { .. } => 8,
+        };
+        let entries: usize = self.keys().iter().map(|key| key.len()).sum();
+        3 + first_child + entries + self.keys().len() * self.entry_size()
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Node::Leaf { keys, .. } => keys.is_empty(),
+            Node::Internal { children, .. } => children.is_empty(),
+        }
+    }
+
+    // Moves the upper half, by size, to a new node. Returns the key separating them and the node
+    fn split(&mut self) -> (String, Node) {
+        let half = self.size() / 2;
+        let entry_size = self.entry_size();
+        let keys = self.keys();
+        let (mut mid, mut size) = (0, 0);
+        while mid < keys.len() - 1 && size < half {
+            size += keys[mid].len() + entry_size;
+            mid += 1;
+        }
+        let mid = mid.max(1);
+
+        match self {
+            Node::Leaf { keys, values } => {
+                let right_keys = keys.split_off(mid);
+                let right_values = values.split_off(mid);
+                let separator = right_keys[0].clone();
+                let right = Node::Leaf {
+                    keys: right_keys,
+                    values: right_values,
+                };
+                (separator, right)
+            }
+            Node::Internal { keys, children } => {
+                let right_keys = keys.split_off(mid + 1);
+                let separator = keys.pop().unwrap();
+                let right = Node::Internal {
+                    keys: right_keys,
+                    children: children.split_off(mid + 1),
+                };
+                (separator, right)
+            }
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut page = Vec::with_capacity(PAGE_SIZE);
+        let put_key = |page: &mut Vec<u8>, key: &String| {
+            page.extend_from_slice(&(key.len() as u16).to_le_bytes());
+            page.extend_from_slice(key.as_bytes());
+        };
+        match self {
+            Node::Leaf { keys, values } => {
+                page.push(LEAF);
+                page.extend_from_slice(&(keys.len() as u16).to_le_bytes());
+                for (key, (offset, length)) in keys.iter().zip(values) {
+                    put_key(&mut page, key);
+                    page.extend_from_slice(&offset.to_le_bytes());
+                    page.extend_from_slice(&(*length as u64).to_le_bytes());
+                }
+            }
+            Node::Internal { keys, children } => {
+                page.push(INTERNAL);
+                page.extend_from_slice(&(keys.len() as u16).to_le_bytes());
+                page.extend_from_slice(&children[0].to_le_bytes());
+                for (key, child) in keys.iter().zip(&children[1..]) {
+                    put_key(&mut page, key);
+                    page.extend_from_slice(&child.to_le_bytes());
+                }
+            }
+        }
+        page.resize(PAGE_SIZE, 0);
+        page
+    }
+
+    fn decode(page: &[u8]) -> Result<Self> {
+        let mut reader = PageReader { page, at: 0 };
+        let kind = reader.take(1)?[0];
+        let count = reader.u16()? as usize;
+        match kind {
+            LEAF => {
+                let mut keys = Vec::with_capacity(count);
+                let mut values = Vec::with_capacity(count);
+                for _ in 0..count {
+                    keys.push(reader.key()?);
+                    values.push((reader.u64()?, reader.u64()? as usize));
+                }
+                Ok(Node::Leaf { keys, values })
+            }
+            INTERNAL => {
+                let mut keys = Vec::with_capacity(count);
+                let mut children = Vec::with_capacity(count + 1);
+                children.push(reader.u64()?);
+                for _ in 0..count {
+                    keys.push(reader.key()?);
+                    children.push(reader.u64()?);
+                }
+                Ok(Node::Internal { keys, children })
+            }
+            _ => bail!("Unknown page kind {}", kind),
+        }
+    }
+}
+
+struct PageReader<'a> {
+    page: &'a [u8],
+    at: usize,
+}
+
+impl<'a> PageReader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        let Some(bytes) = self.page.get(self.at..self.at + length) else {
+            bail!("Page is corrupted");
+        };
+        self.at += length;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn key(&mut self) -> Result<String> {
+        let length = self.u16()? as usize;
+        Ok(String::from_utf8(self.take(length)?.to_vec())?)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Header {
+    sequence: u64,
+    root: u64, // 0 for an empty tree, as the first page holds the headers
+    len: u64,
+    pages: u64,
+    dead_pages: u64,
+}
+
+impl Header {
+    fn empty() -> Self {
+        Self {
+            sequence: 0,
+            root: 0,
+            len: 0,
+            pages: 1,
+            dead_pages: 0,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        for value in [
+            self.sequence,
+            self.root,
+            self.len,
+            self.pages,
+            self.dead_pages,
+        ] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&checksum(&bytes).to_le_bytes());
+        bytes
+    }
+
+    // `None` if the slot was never written, or the write was torn
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        if &bytes[..8] != MAGIC || read_u64(bytes, 48) != checksum(&bytes[..48]) {
+            return None;
+        }
+        Some(Self {
+            sequence: read_u64(bytes, 8),
+            root: read_u64(bytes, 16),
+            len: read_u64(bytes, 24),
+            pages: read_u64(bytes, 32),
+            dead_pages: read_u64(bytes, 40),
+        })
+    }
+
+    fn read(file: &mut File, path: &Path) -> Result<Self> {
+        if file.metadata()?.len() == 0 {
+            return Ok(Self::empty());
+        }
+
+        let mut page = vec![0; PAGE_SIZE];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut page)?;
+        let first = Self::decode(&page[..HEADER_SIZE]);
+        let second = Self::decode(&page[SLOT_SIZE..SLOT_SIZE + HEADER_SIZE]);
+        match first.into_iter().chain(second).max_by_key(|h| h.sequence) {
+            Some(header) => Ok(header),
+            None => bail!("{} is not a paged index table", path.display()),
+        }
+    }
+
+    fn write(&self, file: &mut File) -> Result<()> {
+        let slot = (self.sequence % 2) as usize * SLOT_SIZE;
+        file.seek(SeekFrom::Start(slot as u64))?;
+        file.write_all(&self.encode())?;
+        file.sync_data()?;
+        Ok(())
+    }
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+// FNV-1a, which never changes between platforms or Rust versions, as it is persisted
+fn checksum(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+// The file and the least recently used pages read from it
+struct Store {
+    file: File,
+    pages: HashMap<u64, (Arc<Node>, usize)>,
+    recency: List<u64>, // most recently used first
+}
+
+impl Store {
+    fn read(&mut self, id: u64) -> Result<Arc<Node>> {
+        if let Some((node, index)) = self.pages.get(&id) {
+            self.recency.move_to_front(*index);
+            return Ok(node.clone());
+        }
+
+        let mut page = vec![0; PAGE_SIZE];
+        self.file.seek(SeekFrom::Start(id * PAGE_SIZE as u64))?;
+        self.file.read_exact(&mut page)?;
+        let node = Arc::new(Node::decode(&page)?);
+        self.cache(id, node.clone());
+        Ok(node)
+    }
+
+    fn cache(&mut self, id: u64, node: Arc<Node>) {
+        if let Some((_, index)) = self.pages.remove(&id) {
+            self.recency.remove(index);
+        }
+        if self.pages.len() >= CACHED_PAGES {
+            if let Some(evicted) = self.recency.pop_back() {
+                self.pages.remove(&evicted);
+            }
+        }
+        let index = self.recency.push_front(id);
+        self.pages.insert(id, (node, index));
+    }
+
+    fn clear(&mut self) {
+        self.pages.clear();
+        self.recency.clear();
+    }
+}
+
+// The key separating a page from the one split off it, and the id of the latter
+type Split = (String, u64);
+
+// The tree as seen by one table, changed pages are kept in memory until they are persisted
+#[derive(Clone)]
+struct Tree {
+    header: Header, // as last persisted, pages below `header.pages` are never changed
+    root: u64,
+    len: usize,
+    pages: u64,
+    dead_pages: u64,
+    dirty: HashMap<u64, Arc<Node>>,
+}
+
+impl Tree {
+    fn new(header: Header) -> Self {
+        Self {
+            header,
+            root: header.root,
+            len: header.len as usize,
+            pages: header.pages,
+            dead_pages: header.dead_pages,
+            dirty: HashMap::new(),
+        }
+    }
+
+    fn node(&self, store: &mut Store, id: u64) -> Result<Arc<Node>> {
+        match self.dirty.get(&id) {
+            Some(node) => Ok(node.clone()),
+            None => store.read(id),
+        }
+    }
+
+    fn node_mut(&mut self, id: u64) -> &mut Node {
+        Arc::make_mut(self.dirty.get_mut(&id).unwrap())
+    }
+
+    fn allocate(&mut self, node: Node) -> u64 {
+        let id = self.pages;
+        self.pages += 1;
+        self.dirty.insert(id, Arc::new(node));
+        id
+    }
+
+    fn free(&mut self, id: u64) {
+        self.dirty.remove(&id);
+        self.dead_pages += 1;
+    }
+
+    // Returns the id of a copy of the page that can be changed, which is the page itself if it
+    // was already changed since the last persist
+    fn make_dirty(&mut self, store: &mut Store, id: u64) -> Result<u64> {
+        if id >= self.header.pages {
+            return Ok(id);
+        }
+        let node = store.read(id)?;
+        self.dead_pages += 1;
+        let id = self.pages;
+        self.pages += 1;
+        self.dirty.insert(id, node);
+        Ok(id)
+    }
+
+    fn get(&self, store: &mut Store, key: &str) -> Result<Option<Key>> {
+        let mut id = self.root;
+        while id != 0 {
+            match &*self.node(store, id)? {
+                Node::Leaf { keys, values } => {
+                    let found = keys.binary_search_by(|k| k.as_str().cmp(key));
+                    return Ok(found.ok().map(|i| values[i]));
+                }
+                Node::Internal { keys, children } => {
+                    id = children[keys.partition_point(|k| k.as_str() <= key)];
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn insert(&mut self, store: &mut Store, key: &str, value: Key) -> Result<()> {
+        if key.len() > MAX_KEY_SIZE {
+            bail!(
+                "Keys of paged index tables are limited to {} bytes",
+                MAX_KEY_SIZE
+            );
+        }
+        if self.root == 0 {
+            self.root = self.allocate(Node::Leaf {
+                keys: Vec::new(),
+                values: Vec::new(),
+            });
+        }
+
+        let (root, split, added) = self.insert_into(store, self.root, key, value)?;
+        self.root = root;
+        if let Some((separator, right)) = split {
+            self.root = self.allocate(Node::Internal {
+                keys: vec![separator],
+                children: vec![root, right],
+            });
+        }
+        if added {
+            self.len += 1;
+        }
+        Ok(())
+    }
+
+    // Returns the new id of the page, the separator and id of the page split off if it became
+    // too large, and whether the key is new
+    fn insert_into(
+        &mut self,
+        store: &mut Store,
+        id: u64,
+        key: &str,
+        value: Key,
+    ) -> Result<(u64, Option<Split>, bool)> {
+        let id = self.make_dirty(store, id)?;
+        let mut added = false;
+        let child = match self.node_mut(id) {
+            Node::Leaf { keys, values } => {
+                match keys.binary_search_by(|k| k.as_str().cmp(key)) {
+                    Ok(i) => values[i] = value,
+                    Err(i) => {
+                        keys.insert(i, key.to_string());
+                        values.insert(i, value);
+                        added = true;
+                    }
+                }
+                None
+            }
+            Node::Internal { keys, children } => {
+                let i = keys.partition_point(|k| k.as_str() <= key);
+                Some((i, children[i]))
+            }
+        };
+
+        if let Some((i, child)) = child {
+            let (child, split, child_added) = self.insert_into(store, child, key, value)?;
+            added = child_added;
+            if let Node::Internal { keys, children } = self.node_mut(id) {
+                children[i] = child;
+                if let Some((separator, right)) = split {
+                    keys.insert(i, separator);
+                    children.insert(i + 1, right);
+                }
+            }
+        }
+
+        if self.dirty[&id].size() <= PAGE_SIZE {
+            return Ok((id, None, added));
+        }
+        let (separator, right) = self.node_mut(id).split();
+        let right = self.allocate(right);
+        Ok((id, Some((separator, right)), added))
+    }
+
+    fn delete(&mut self, store: &mut Store, key: &str) -> Result<()> {
+        if self.get(store, key)?.is_none() {
+            return Ok(());
+        }
+        self.root = self.delete_from(store, self.root, key)?.unwrap_or(0);
+        self.len -= 1;
+
+        // Internal roots with a single child are dropped, so the tree only gets as deep as needed
+        while self.root != 0 {
+            let Node::Internal { children, .. } = &*self.node(store, self.root)? else {
+                break;
+            };
+            if children.len() > 1 {
+                break;
+            }
+            let child = children[0];
+            self.free(self.root);
+            self.root = child;
+        }
+        Ok(())
+    }
+
+    // Returns the new id of the page, `None` if it became empty and was dropped
+    fn delete_from(&mut self, store: &mut Store, id: u64, key: &str) -> Result<Option<u64>> {
+        let id = self.make_dirty(store, id)?;
+        let child = match self.node_mut(id) {
+            Node::Leaf { keys, values } => {
+                if let Ok(i) = keys.binary_search_by(|k| k.as_str().cmp(key)) {
+                    keys.remove(i);
+                    values.remove(i);
+                }
+                None
+            }
+            Node::Internal { keys, children } => {
+                let i = keys.partition_point(|k| k.as_str() <= key);
+                Some((i, children[i]))
+            }
+        };
+
+        if let Some((i, child)) = child {
+            let child = self.delete_from(store, child, key)?;
+            if let Node::Internal { keys, children } = self.node_mut(id) {
+                match child {
+                    Some(child) => children[i] = child,
+                    None => {
+                        children.remove(i);
+                        if !keys.is_empty() {
+                            keys.remove(i.saturating_sub(1));
+                        }
+                    }
+                }
+            }
+        }
+
+        if self.dirty[&id].is_empty() {
+            self.free(id);
+            return Ok(None);
+        }
+        Ok(Some(id))
+    }
+
+    // Collects the keys between `start` and `end` below `id`, in order
+    fn range(
+        &self,
+        store: &mut Store,
+        id: u64,
+        bounds: (Bound<&str>, Bound<&str>),
+        key_values: &mut Vec<(String, Key)>,
+    ) -> Result<()> {
+        match &*self.node(store, id)? {
+            Node::Leaf { keys, values } => {
+                for (key, value) in keys.iter().zip(values) {
+                    if RangeBounds::<str>::contains(&bounds, key.as_str()) {
+                        key_values.push((key.clone(), *value));
+                    }
+                }
+            }
+            Node::Internal { keys, children } => {
+                let first = match bounds.0 {
+                    Bound::Included(start) | Bound::Excluded(start) => {
+                        keys.partition_point(|k| k.as_str() <= start)
+                    }
+                    Bound::Unbounded => 0,
+                };
+                let last = match bounds.1 {
+                    Bound::Included(end) => keys.partition_point(|k| k.as_str() <= end),
+                    Bound::Excluded(end) => keys.partition_point(|k| k.as_str() < end),
+                    Bound::Unbounded => keys.len(),
+                };
+                for child in &children[first..=last] {
+                    self.range(store, *child, bounds, key_values)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Builds a tree of full pages from keys in strictly ascending order, replacing all pages
+    fn build(&mut self, key_values: Vec<(String, Key)>) {
+        self.dirty.clear();
+        self.dead_pages = self.pages - 1;
+        self.len = key_values.len();
+
+        // Each level holds the first key below every page
+        let mut level = Vec::new();
+        let mut leaf: (Vec<String>, Vec<Key>) = (Vec::new(), Vec::new());
+        let mut size = 3;
+        for (key, value) in key_values {
+            if size + key.len() + 2 + 16 > PAGE_SIZE {
+                let (keys, values) = std::mem::take(&mut leaf);
+                let first = keys[0].clone();
+                level.push((first, self.allocate(Node::Leaf { keys, values })));
+                size = 3;
+            }
+            size += key.len() + 2 + 16;
+            leaf.0.push(key);
+            leaf.1.push(value);
+        }
+        if !leaf.0.is_empty() {
+            let first = leaf.0[0].clone();
+            let (keys, values) = leaf;
+            level.push((first, self.allocate(Node::Leaf { keys, values })));
+        }
+
+        while level.len() > 1 {
+            let mut parents = Vec::new();
+            let mut children = level.into_iter();
+            let (mut first, child) = children.next().unwrap();
+            let mut node = (Vec::new(), vec![child]);
+            let mut size = 3 + 8;
+            for (key, child) in children {
+                if size + key.len() + 2 + 8 > PAGE_SIZE {
+                    let (keys, children) = std::mem::take(&mut node);
+                    let id = self.allocate(Node::Internal { keys, children });
+                    parents.push((std::mem::replace(&mut first, key), id));
+                    node.1.push(child);
+                    size = 3 + 8;
+                    continue;
+                }
+                size += key.len() + 2 + 8;
+                node.0.push(key);
+                node.1.push(child);
+            }
+            let (keys, children) = node;
+            parents.push((first, self.allocate(Node::Internal { keys, children })));
+            level = parents;
+        }
+        self.root = level.first().map_or(0, |(_, id)| *id);
+    }
+
+    fn is_changed(&self) -> bool {
+        !self.dirty.is_empty()
+            || self.root != self.header.root
+            || self.len as u64 != self.header.len
+    }
+
+    fn commit(&mut self, store: &mut Store) -> Result<()> {
+        for (id, node) in &self.dirty {
+            store.file.seek(SeekFrom::Start(id * PAGE_SIZE as u64))?;
+            store.file.write_all(&node.encode())?;
+        }
+        // Pages dropped before they were persisted leave holes, possibly at the end
+        store.file.set_len(self.pages * PAGE_SIZE as u64)?;
+        store.file.sync_data()?;
+
+        let header = Header {
+            sequence: self.header.sequence + 1,
+            root: self.root,
+            len: self.len as u64,
+            pages: self.pages,
+            dead_pages: self.dead_pages,
+        };
+        header.write(&mut store.file)?;
+        for (id, node) in self.dirty.drain() {
+            store.cache(id, node);
+        }
+        self.header = header;
+        Ok(())
+    }
+
+    // Writes the pages reachable from the root to a new file, which then replaces the current one
+    fn compact(&mut self, store: &mut Store, path: &Path) -> Result<()> {
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        writer.write_all(&[0; PAGE_SIZE])?;
+
+        let mut pages = 1;
+        let root = match self.root {
+            0 => 0,
+            root => self.copy_pages(store, root, &mut writer, &mut pages)?,
+        };
+        let mut file = writer.into_inner()?;
+        let header = Header {
+            sequence: self.header.sequence + 1,
+            root,
+            len: self.len as u64,
+            pages,
+            dead_pages: 0,
+        };
+        header.write(&mut file)?;
+        std::fs::rename(&temp_path, path)?;
+
+        store.file = OpenOptions::new().read(true).write(true).open(path)?;
+        store.clear();
+        *self = Self::new(header);
+        Ok(())
+    }
+
+    // Copies the pages below `id` in post order, returns the new id of the page
+    fn copy_pages(
+        &self,
+        store: &mut Store,
+        id: u64,
+        writer: &mut BufWriter<File>,
+        pages: &mut u64,
+    ) -> Result<u64> {
+        let node = self.node(store, id)?;
+        let page = match &*node {
+            Node::Leaf { .. } => node.encode(),
+            Node::Internal { keys, children } => {
+                let mut copied = Vec::with_capacity(children.len());
+                for child in children {
+                    copied.push(self.copy_pages(store, *child, writer, pages)?);
+                }
+                Node::Internal {
+                    keys: keys.clone(),
+                    children: copied,
+                }
+                .encode()
+            }
+        };
+        writer.write_all(&page)?;
+        *pages += 1;
+        Ok(*pages - 1)
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl BPlusTreeIndexTable {
+    /// Opens the index table file at `path`, which is created if it doesn't exist. Only the
+    /// header is read.
+    pub fn new(path: PathBuf) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        let header = Header::read(&mut file, &path)?;
+
+        Ok(Self {
+            path,
+            store: Arc::new(Mutex::new(Store {
+                file,
+                pages: HashMap::new(),
+                recency: List::new(),
+            })),
+            tree: Mutex::new(Tree::new(header)),
+        })
+    }
+
+    #[cfg(test)]
+    fn file_size(&self) -> u64 {
+        lock(&self.store).file.metadata().unwrap().len()
+    }
+}
+
+impl IndexTable for BPlusTreeIndexTable {
+    fn get(&self, key: &str) -> Option<Key> {
+        // Pages that can't be read are treated like missing keys
+        let index = self.try_get(key);
+        #[cfg(feature = "tracing")]
+        if let Err(error) = &index {
+            tracing::warn!(key, error = ?error, "Error reading the index");
+        }
+        index.unwrap_or_default()
+    }
+
+    fn try_get(&self, key: &str) -> Result<Option<Key>> {
+        let tree = lock(&self.tree);
+        tree.get(&mut lock(&self.store), key)
+    }
+
+    fn insert(&mut self, key: &str, value: Key) -> Result<()> {
+        let mut tree = lock(&self.tree);
+        tree.insert(&mut lock(&self.store), key, value)
+    }
+
+    fn delete(&mut self, key: &str) -> Result<()> {
+        let mut tree = lock(&self.tree);
+        tree.delete(&mut lock(&self.store), key)
+    }
+
+    fn load(&mut self) -> Result<()> {
+        let mut tree = lock(&self.tree);
+        let mut store = lock(&self.store);
+        let header = Header::read(&mut store.file, &self.path)?;
+        store.clear();
+        *tree = Tree::new(header);
+        Ok(())
+    }
+
+    fn persist(&self) -> Result<()> {
+        let mut tree = lock(&self.tree);
+        let mut store = lock(&self.store);
+        if !tree.is_changed() {
+            return Ok(());
+        }
+
+        let live_pages = tree.pages - 1 - tree.dead_pages;
+        if tree.pages >= MIN_COMPACT_PAGES && tree.dead_pages > live_pages {
+            return tree.compact(&mut store, &self.path);
+        }
+        tree.commit(&mut store)
+    }
+
+    #[cfg(test)]
+    fn index_type(&self) -> &str {
+        "bplus_tree"
+    }
+
+    fn len(&self) -> usize {
+        lock(&self.tree).len
+    }
+
+    fn all_key_values(&self) -> Vec<(String, Key)> {
+        self.range(Bound::Unbounded, Bound::Unbounded)
+    }
+
+    fn range(&self, start: Bound<&str>, end: Bound<&str>) -> Vec<(String, Key)> {
+        let tree = lock(&self.tree);
+        let mut key_values = Vec::new();
+        if tree.root != 0 && !is_empty_range(start, end) {
+            let mut store = lock(&self.store);
+            // Like lookups, pages that can't be read are skipped
+            let _ = tree.range(&mut store, tree.root, (start, end), &mut key_values);
+        }
+        key_values
+    }
+
+    fn replace_all(&mut self, mut key_values: Vec<(String, Key)>) -> Result<()> {
+        if let Some((key, _)) = key_values.iter().find(|(key, _)| key.len() > MAX_KEY_SIZE) {
+            bail!("Key {} is longer than {} bytes", key, MAX_KEY_SIZE);
+        }
+        key_values.sort_by(|(a, _), (b, _)| a.cmp(b));
+        key_values.dedup_by(|(a, _), (b, _)| a == b);
+        lock(&self.tree).build(key_values);
+        Ok(())
+    }
+
+    #[cfg(feature = "write")]
+    fn snapshot(&self) -> Box<dyn IndexTable> {
+        Box::new(Self {
+            path: self.path.clone(),
+            store: self.store.clone(),
+            tree: Mutex::new(lock(&self.tree).clone()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_matches_btree_map() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("index.bptree");
+        let mut table = BPlusTreeIndexTable::new(path.clone())?;
+        let mut expected = BTreeMap::new();
+
+        let mut rng = rand::thread_rng();
+        for round in 0..4 {
+            for i in 0..5000u64 {
+                let key = format!(
+                    "{}{}",
+                    "k".repeat(rng.gen_range(1..64)),
+                    rng.gen_range(0..20000)
+                );
+                if rng.gen_bool(0.3) {
+                    table.delete(&key)?;
+                    expected.remove(&key);
+                } else {
+                    table.insert(&key, (i, round))?;
+                    expected.insert(key, (i, round));
+                }
+            }
+            table.persist()?;
+
+            let mut table = BPlusTreeIndexTable::new(path.clone())?;
+            assert_eq!(table.len(), expected.len());
+            for (key, value) in &expected {
+                assert_eq!(table.get(key), Some(*value));
+            }
+            let all: Vec<(String, Key)> = expected.clone().into_iter().collect();
+            assert_eq!(table.all_key_values(), all);
+            let range: Vec<(String, Key)> = expected
+                .range::<str, _>((Bound::Excluded("kk5"), Bound::Included("kkk")))
+                .map(|(k, v)| (k.clone(), *v))
+                .collect();
+            assert_eq!(
+                table.range(Bound::Excluded("kk5"), Bound::Included("kkk")),
+                range
+            );
+            table.load()?;
+            assert_eq!(table.len(), expected.len());
+        }
+
+        assert!(table.insert(&"k".repeat(MAX_KEY_SIZE + 1), (0, 0)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_on_write() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("index.bptree");
+        let mut table = BPlusTreeIndexTable::new(path.clone())?;
+        let key_values: Vec<(String, Key)> = (0..20_000)
+            .map(|i| (format!("key{:05}", i), (i as u64, i)))
+            .collect();
+        table.replace_all(key_values.clone())?;
+        table.persist()?;
+        let size = table.file_size();
+
+        // Changes before persisting are invisible on disk, and only append pages
+        table.insert("key00000", (7, 7))?;
+        assert_eq!(
+            BPlusTreeIndexTable::new(path.clone())?.get("key00000"),
+            Some((0, 0))
+        );
+        table.persist()?;
+        assert!(table.file_size() > size);
+        assert_eq!(
+            BPlusTreeIndexTable::new(path.clone())?.get("key00000"),
+            Some((7, 7))
+        );
+
+        // Rebuilding replaces every page, so the file is compacted
+        table.replace_all(key_values)?;
+        table.persist()?;
+        assert_eq!(table.file_size(), size);
+        let table = BPlusTreeIndexTable::new(path.clone())?;
+        assert_eq!(table.get("key00000"), Some((0, 0)));
+        assert_eq!(table.get("key19999"), Some((19999, 19999)));
+        assert_eq!(table.len(), 20_000);
+
+        std::fs::write(&path, vec![1; PAGE_SIZE])?;
+        assert!(BPlusTreeIndexTable::new(path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unreadable_pages() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("index.bptree");
+        let mut table = BPlusTreeIndexTable::new(path.clone())?;
+        table.insert("key", (1, 1))?;
+        table.persist()?;
+
+        // Only the header is left, the pages it references are gone
+        OpenOptions::new()
+            .write(true)
+            .open(&path)?
+            .set_len(PAGE_SIZE as u64)?;
+        let table = BPlusTreeIndexTable::new(path)?;
+        assert_eq!(table.get("key"), None);
+        assert!(table.try_get("key").is_err());
+        Ok(())
+    }
+}
@@ -1,17 +1,20 @@
 use crate::cache::Key;
 use crate::default_persist;
-use crate::index_table::IndexTable;
-use bincode::deserialize_from;
+use crate::index_table::encoding::read_index;
+use crate::index_table::factory::IndexFormat;
+use crate::index_table::{is_empty_range, IndexTable};
 use fs2::FileExt;
 use std::collections::BTreeMap;
 use std::fs::{File, OpenOptions};
 use std::io::BufReader;
+use std::ops::Bound;
 use std::path::PathBuf;
 
 #[repr(C)]
 pub struct BTreeMapIndexTable {
     table: BTreeMap<String, Key>,
-    file_path: PathBuf,
+    file_path: Option<PathBuf>, // `None` keeps the table in memory only
+    format: IndexFormat,
 }
 
 impl BTreeMapIndexTable {
@@ -20,32 +23,46 @@ impl BTreeMapIndexTable {
             .read(true)
             .write(true)
             .create(true)
+            .truncate(false)
             .open(&path)?;
 
         // Lock the file
         file.lock_exclusive()?;
 
         let reader = BufReader::new(&file);
-        let table: BTreeMap<String, Key> = match deserialize_from(reader) {
-            Ok(table) => table,
-            Err(_) => BTreeMap::new(),
-        };
+        let table: BTreeMap<String, Key> = read_index(reader)?;
 
         // Remember to unlock the file when done
-        file.unlock()?;
+        FileExt::unlock(&file)?;
 
         Ok(Self {
             table,
-            file_path: path,
+            file_path: Some(path),
+            format: IndexFormat::default(),
         })
     }
 
     pub fn new_default(path: PathBuf) -> anyhow::Result<Self> {
         Ok(Self {
             table: BTreeMap::new(),
-            file_path: path,
+            file_path: Some(path),
+            format: IndexFormat::default(),
         })
     }
+
+    pub fn in_memory() -> Self {
+        Self {
+            table: BTreeMap::new(),
+            file_path: None,
+            format: IndexFormat::default(),
+        }
+    }
+
+    /// Sets the format the table is written in on `persist`.
+    pub fn with_format(mut self, format: IndexFormat) -> Self {
+        self.format = format;
+        self
+    }
 }
 
 impl IndexTable for BTreeMapIndexTable {
@@ -64,25 +81,27 @@ impl IndexTable for BTreeMapIndexTable {
     }
 
     fn load(&mut self) -> anyhow::Result<()> {
-        let file = File::open(&self.file_path)?;
+        let Some(file_path) = &self.file_path else {
+            return Ok(());
+        };
+        let file = File::open(file_path)?;
 
         // Lock the file
         file.lock_exclusive()?;
 
         let reader = BufReader::new(&file);
-        self.table = match deserialize_from(reader) {
-            Ok(table) => table,
-            Err(_) => BTreeMap::new(),
-        };
+        self.table = read_index(reader)?;
 
         // Remember to unlock the file when done
-        file.unlock()?;
+        FileExt::unlock(&file)?;
 
         Ok(())
     }
 
     fn persist(&self) -> anyhow::Result<()> {
-        default_persist!(self, self.file_path.clone(), self.table);
+        if let Some(file_path) = &self.file_path {
+            default_persist!(self, file_path, self.table, self.format);
+        }
         Ok(())
     }
 
@@ -91,10 +110,24 @@ impl IndexTable for BTreeMapIndexTable {
         "btree"
     }
 
+    fn len(&self) -> usize {
+        self.table.len()
+    }
+
     fn all_key_values(&self) -> Vec<(String, Key)> {
         self.table.iter().map(|(k, v)| (k.clone(), *v)).collect()
     }
 
+    fn range(&self, start: Bound<&str>, end: Bound<&str>) -> Vec<(String, Key)> {
+        if is_empty_range(start, end) {
+            return Vec::new();
+        }
+        self.table
+            .range::<str, _>((start, end))
+            .map(|(k, v)| (k.clone(), *v))
+            .collect()
+    }
+
     fn replace_all(&mut self, key_values: Vec<(String, Key)>) -> anyhow::Result<()> {
         self.table = key_values.into_iter().collect();
         Ok(())
@@ -105,6 +138,7 @@ impl IndexTable for BTreeMapIndexTable {
         Box::new(Self {
             table: self.table.clone(),
             file_path: self.file_path.clone(),
+            format: self.format,
         })
     }
 }
@@ -128,7 +162,7 @@ mod tests {
         index_table.insert(key, (value, 0)).unwrap();
 
         // Assert - get returns the correct value
-        assert_eq!(index_table.get(&key), Some((value, 0)));
+        assert_eq!(index_table.get(key), Some((value, 0)));
 
         // Test - persist to disk
         index_table.persist()?;
@@ -136,7 +170,7 @@ mod tests {
         // Assert - load from disk
         let mut loaded_table = BTreeMapIndexTable::new(index_path)?;
         loaded_table.load()?;
-        assert_eq!(loaded_table.get(&key), Some((value, 0)));
+        assert_eq!(loaded_table.get(key), Some((value, 0)));
 
         // Cleanup
         temp_dir.close()?;
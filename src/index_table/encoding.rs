@@ -0,0 +1,166 @@
+use crate::cache::Key;
+use anyhow::{bail, Context, Result};
+use std::io::{Read, Write};
+
+const MAGIC: &[u8; 8] = b"RDBIDX01";
+
+/// Writes the key-value pairs of an index table in the compact format read by [`read_index`].
+///
+/// The pairs are sorted by key, so each key only stores the bytes that differ from the previous
+/// one. Numbers are varints and offsets are stored as the difference to the previous offset, as
+/// values written together usually lie close together.
+pub(crate) fn write_index<'a, W: Write>(
+    mut writer: W,
+    key_values: impl IntoIterator<Item = (&'a String, &'a Key)>,
+) -> Result<()> {
+    let mut key_values: Vec<(&String, &Key)> = key_values.into_iter().collect();
+    if !key_values.windows(2).all(|pair| pair[0].0 <= pair[1].0) {
+        key_values.sort_unstable_by_key(|(key, _)| *key);
+    }
+
+    let mut bytes = Vec::with_capacity(16 + key_values.len() * 8);
+    bytes.extend_from_slice(MAGIC);
+    write_varint(&mut bytes, key_values.len() as u64);
+
+    let mut previous_key: &[u8] = &[];
+    let mut previous_offset = 0u64;
+    for (key, (offset, length)) in key_values {
+        let key = key.as_bytes();
+        let shared = key
+            .iter()
+            .zip(previous_key)
+            .take_while(|(a, b)| a == b)
+            .count();
+        write_varint(&mut bytes, shared as u64);
+        write_varint(&mut bytes, (key.len() - shared) as u64);
+        bytes.extend_from_slice(&key[shared..]);
+
+        // Zigzag encoding, so small steps backwards stay small as well
+        let delta = offset.wrapping_sub(previous_offset) as i64;
+        write_varint(&mut bytes, ((delta << 1) ^ (delta >> 63)) as u64);
+        write_varint(&mut bytes, *length as u64);
+
+        previous_key = key;
+        previous_offset = *offset;
+    }
+
+    writer.write_all(&bytes)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads an index table written by [`write_index`], or with bincode. An empty file is an empty
+/// table, and so is a file bincode can't decode, as before this format existed. A file in this
+/// format that can't be decoded is an error, so it is never replaced by an empty table.
+pub(crate) fn read_index<T, R>(mut reader: R) -> Result<T>
+where
+    T: Default + FromIterator<(String, Key)>,
+    R: Read,
+{
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    if bytes.is_empty() {
+        return Ok(T::default());
+    }
+    if !bytes.starts_with(MAGIC) {
+        // bincode writes maps just like sequences of pairs
+        let key_values: Vec<(String, Key)> = bincode::deserialize(&bytes).unwrap_or_default();
+        return Ok(key_values.into_iter().collect());
+    }
+
+    let mut input = &bytes[MAGIC.len()..];
+    let count = read_varint(&mut input)?;
+    // Every entry takes at least four bytes, so a corrupt count can't allocate much
+    let mut key_values = Vec::with_capacity((count as usize).min(input.len() / 4));
+    let mut key = Vec::new();
+    let mut previous_offset = 0u64;
+    for _ in 0..count {
+        let shared = read_varint(&mut input)? as usize;
+        let suffix = read_varint(&mut input)? as usize;
+        if shared > key.len() || suffix > input.len() {
+            bail!("Index entry out of bounds");
+        }
+        key.truncate(shared);
+        key.extend_from_slice(&input[..suffix]);
+        input = &input[suffix..];
+
+        let delta = read_varint(&mut input)?;
+        let delta = ((delta >> 1) as i64) ^ -((delta & 1) as i64);
+        let offset = previous_offset.wrapping_add(delta as u64);
+        let length = read_varint(&mut input)? as usize;
+        previous_offset = offset;
+
+        let key = String::from_utf8(key.clone()).context("Index key is not UTF-8")?;
+        key_values.push((key, (offset, length)));
+    }
+    if !input.is_empty() {
+        bail!("Trailing bytes after the index entries");
+    }
+
+    Ok(key_values.into_iter().collect())
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(input: &mut &[u8]) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let Some((&byte, rest)) = input.split_first() else {
+            bail!("Index ends within a number");
+        };
+        *input = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    bail!("Number in the index is too long")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{BTreeMap, HashMap};
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        let table: HashMap<String, Key> = (0..1000u64)
+            .map(|i| (format!("user/{:05}/name", i * 7 % 1000), (i * 64, 64)))
+            .chain([
+                (String::new(), (u64::MAX, usize::MAX)),
+                ("ü/🦀".to_string(), (3, 0)),
+                ("ü/🦁".to_string(), (0, 1)),
+            ])
+            .collect();
+
+        let mut bytes = Vec::new();
+        write_index(&mut bytes, &table)?;
+        let read: HashMap<String, Key> = read_index(bytes.as_slice())?;
+        assert_eq!(read, table);
+
+        // Shared prefixes and small offset steps make it a lot smaller than bincode
+        let legacy = bincode::serialize(&table)?;
+        assert!(bytes.len() * 2 < legacy.len(), "{} bytes", bytes.len());
+
+        // A torn file is an error, not a shorter table
+        assert!(read_index::<HashMap<String, Key>, _>(&bytes[..bytes.len() - 1]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reads_bincode() -> Result<()> {
+        let table: BTreeMap<String, Key> = (0..100u64).map(|i| (i.to_string(), (i, 1))).collect();
+        let read: BTreeMap<String, Key> = read_index(bincode::serialize(&table)?.as_slice())?;
+        assert_eq!(read, table);
+
+        let read: BTreeMap<String, Key> = read_index(&[][..])?;
+        assert!(read.is_empty());
+        Ok(())
+    }
+}
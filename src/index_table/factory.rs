@@ -1,30 +1,91 @@
+use crate::cache::Key;
+use crate::index_table::bplus_tree::BPlusTreeIndexTable;
 use crate::index_table::btree::BTreeMapIndexTable;
+#[cfg(feature = "fst-index")]
+use crate::index_table::fst_table::FstIndexTable;
 use crate::index_table::hash_map::HashMapIndexTable;
+#[cfg(feature = "static-index")]
+use crate::index_table::static_hash::StaticIndexTable;
 use crate::index_table::IndexTable;
 use anyhow::{bail, Result};
 use fs2::FileExt;
 use std::fs::File;
 use std::io::{BufRead, BufReader, BufWriter, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 const TYPE_EXTENSION: &str = "type";
+const BPLUS_TREE_EXTENSION: &str = "bptree";
+#[cfg(feature = "static-index")]
+const STATIC_EXTENSION: &str = "static";
+#[cfg(feature = "fst-index")]
+const FST_EXTENSION: &str = "fst";
 const DEFAULT_INDEX_NAME: &str = ".rdb.index";
 
-#[derive(PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum IndexType {
     HashMap,
     BTreeMap,
+    /// Sorted and paged, only the pages a lookup touches are read from disk, so opening it takes
+    /// the same time regardless of the number of keys. Keys are limited to 1024 bytes.
+    BPlusTree,
+    /// Read-only, memory mapped and addressed by a perfect hash function, so opening it takes
+    /// the same time regardless of the number of keys. Build it with [`convert_index`] once a
+    /// database is complete.
+    #[cfg(feature = "static-index")]
+    Static,
+    /// Sorted and memory mapped, for prefix scans and ranges over many keys. Changes are kept in
+    /// memory until the next `persist` merges them into the file.
+    #[cfg(feature = "fst-index")]
+    Fst,
     // Determined at runtime, or HashMap if not specified
     Auto,
 }
 
+/// How HashMap and BTreeMap index tables are written to disk. Both formats are always read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IndexFormat {
+    /// Every key and location in full, which all versions of readb can read.
+    #[default]
+    Bincode,
+    /// Sorted keys that only store the bytes differing from the previous key, and varints, a
+    /// fraction of the size. readb 0.4.1 and earlier can't read it, and open such a database with
+    /// an empty index.
+    Compact,
+}
+
+/// Rewrites the index table of the database at `path` as `index_type`, for example to build a
+/// static index once the database won't change anymore. The previous index stays in place until
+/// the new one is complete.
+pub fn convert_index<P: AsRef<Path>>(path: P, index_type: IndexType) -> Result<()> {
+    let path = path.as_ref().to_path_buf();
+    let key_values = IndexFactory::new(IndexType::Auto)
+        .load(path.clone())?
+        .all_key_values();
+    IndexFactory::new(index_type).write(path, key_values)
+}
+
 pub struct IndexFactory {
     index_type: IndexType,
+    format: IndexFormat,
 }
 
 impl IndexFactory {
     pub fn new(index_type: IndexType) -> Self {
-        IndexFactory { index_type }
+        IndexFactory {
+            index_type,
+            format: IndexFormat::default(),
+        }
+    }
+
+    /// Sets the format HashMap and BTreeMap index tables are written in.
+    pub fn with_format(mut self, format: IndexFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// The type of the created or loaded index tables, `Auto` is resolved by loading one.
+    pub fn index_type(&self) -> IndexType {
+        self.index_type
     }
 
     fn path2path(path: PathBuf) -> (PathBuf, PathBuf) {
@@ -37,9 +98,24 @@ impl IndexFactory {
         (path, type_path)
     }
 
-    pub fn create(&self, path: PathBuf) -> Result<Box<dyn IndexTable>> {
-        let (path, type_path) = IndexFactory::path2path(path);
+    // Paged index tables are stored next to the other index files as well, see `static_path`
+    fn bplus_tree_path(path: &Path) -> PathBuf {
+        path.with_extension(BPLUS_TREE_EXTENSION)
+    }
+
+    // Static index tables are stored next to the other index files, so switching to one never
+    // overwrites the previous index
+    #[cfg(feature = "static-index")]
+    fn static_path(path: &Path) -> PathBuf {
+        path.with_extension(STATIC_EXTENSION)
+    }
 
+    #[cfg(feature = "fst-index")]
+    fn fst_path(path: &Path) -> PathBuf {
+        path.with_extension(FST_EXTENSION)
+    }
+
+    fn write_type(&self, type_path: PathBuf) -> Result<File> {
         // Create the file
         let file = File::create(type_path)?;
         file.lock_exclusive()?;
@@ -49,20 +125,89 @@ impl IndexFactory {
         match self.index_type {
             IndexType::HashMap => writer.write_all(b"HashMap")?,
             IndexType::BTreeMap => writer.write_all(b"BTreeMap")?,
+            IndexType::BPlusTree => writer.write_all(b"BPlusTree")?,
+            #[cfg(feature = "static-index")]
+            IndexType::Static => writer.write_all(b"Static")?,
+            #[cfg(feature = "fst-index")]
+            IndexType::Fst => writer.write_all(b"Fst")?,
             IndexType::Auto => bail!("Cannot create index with type Auto"),
         }
+        writer.flush()?;
+        drop(writer);
+        Ok(file)
+    }
+
+    pub fn create(&self, path: PathBuf) -> Result<Box<dyn IndexTable>> {
+        let (path, type_path) = IndexFactory::path2path(path);
+        let file = self.write_type(type_path)?;
 
         // Create the appropriate index table
         let index_table: Box<dyn IndexTable> = match self.index_type {
-            IndexType::HashMap => Box::new(HashMapIndexTable::new_default(path)?),
-            IndexType::BTreeMap => Box::new(BTreeMapIndexTable::new_default(path)?),
+            IndexType::HashMap => {
+                Box::new(HashMapIndexTable::new_default(path)?.with_format(self.format))
+            }
+            IndexType::BTreeMap => {
+                Box::new(BTreeMapIndexTable::new_default(path)?.with_format(self.format))
+            }
+            IndexType::BPlusTree => Box::new(BPlusTreeIndexTable::new(
+                IndexFactory::bplus_tree_path(&path),
+            )?),
+            #[cfg(feature = "static-index")]
+            IndexType::Static => {
+                let static_path = IndexFactory::static_path(&path);
+                StaticIndexTable::build(&static_path, &[])?;
+                Box::new(StaticIndexTable::open(&static_path)?)
+            }
+            #[cfg(feature = "fst-index")]
+            IndexType::Fst => Box::new(FstIndexTable::new_default(IndexFactory::fst_path(&path))),
             IndexType::Auto => unreachable!(),
         };
 
-        file.unlock()?;
+        FileExt::unlock(&file)?;
         Ok(index_table)
     }
 
+    /// Writes a complete index table holding `key_values` to `path`. The type is switched last,
+    /// so an existing index table stays valid until then.
+    pub fn write(&self, path: PathBuf, key_values: Vec<(String, Key)>) -> Result<()> {
+        let (index_path, type_path) = IndexFactory::path2path(path);
+        match self.index_type {
+            IndexType::HashMap => {
+                let mut index_table =
+                    HashMapIndexTable::new_default(index_path)?.with_format(self.format);
+                index_table.replace_all(key_values)?;
+                index_table.persist()?;
+            }
+            IndexType::BTreeMap => {
+                let mut index_table =
+                    BTreeMapIndexTable::new_default(index_path)?.with_format(self.format);
+                index_table.replace_all(key_values)?;
+                index_table.persist()?;
+            }
+            IndexType::BPlusTree => {
+                let path = IndexFactory::bplus_tree_path(&index_path);
+                let mut index_table = BPlusTreeIndexTable::new(path)?;
+                index_table.replace_all(key_values)?;
+                index_table.persist()?;
+            }
+            #[cfg(feature = "static-index")]
+            IndexType::Static => {
+                StaticIndexTable::build(&IndexFactory::static_path(&index_path), &key_values)?
+            }
+            #[cfg(feature = "fst-index")]
+            IndexType::Fst => {
+                let mut index_table =
+                    FstIndexTable::new_default(IndexFactory::fst_path(&index_path));
+                index_table.replace_all(key_values)?;
+                index_table.persist()?;
+            }
+            IndexType::Auto => bail!("Cannot create index with type Auto"),
+        }
+
+        FileExt::unlock(&self.write_type(type_path)?)?;
+        Ok(())
+    }
+
     pub fn load(&mut self, path: PathBuf) -> Result<Box<dyn IndexTable>> {
         let (path, type_path) = IndexFactory::path2path(path);
 
@@ -79,6 +224,11 @@ impl IndexFactory {
         let file_type = match first_line.trim() {
             "HashMap" => IndexType::HashMap,
             "BTreeMap" => IndexType::BTreeMap,
+            "BPlusTree" => IndexType::BPlusTree,
+            #[cfg(feature = "static-index")]
+            "Static" => IndexType::Static,
+            #[cfg(feature = "fst-index")]
+            "Fst" => IndexType::Fst,
             _ => bail!("Unknown index type {} in file", first_line),
         };
 
@@ -87,25 +237,49 @@ impl IndexFactory {
                 self.index_type = file_type;
 
                 // If the index type is Auto, then use the type in the file
-                file.unlock()?;
+                FileExt::unlock(&file)?;
                 return self.load(path);
             }
 
             bail!("Index type in file does not match specified type");
         }
 
-        // Create the appropriate index table
-        let mut index_table: Box<dyn IndexTable> = match self.index_type {
-            IndexType::HashMap => Box::new(HashMapIndexTable::new(path)?),
-            IndexType::BTreeMap => Box::new(BTreeMapIndexTable::new(path)?),
+        // Open the appropriate index table, which reads it from the file
+        let index_table: Box<dyn IndexTable> = match self.index_type {
+            IndexType::HashMap => Box::new(HashMapIndexTable::new(path)?.with_format(self.format)),
+            IndexType::BTreeMap => {
+                Box::new(BTreeMapIndexTable::new(path)?.with_format(self.format))
+            }
+            IndexType::BPlusTree => Box::new(BPlusTreeIndexTable::new(
+                IndexFactory::bplus_tree_path(&path),
+            )?),
+            #[cfg(feature = "static-index")]
+            IndexType::Static => {
+                Box::new(StaticIndexTable::open(&IndexFactory::static_path(&path))?)
+            }
+            #[cfg(feature = "fst-index")]
+            IndexType::Fst => Box::new(FstIndexTable::new(IndexFactory::fst_path(&path))?),
             IndexType::Auto => unreachable!(),
         };
 
-        index_table.load()?;
-        file.unlock()?;
+        FileExt::unlock(&file)?;
         Ok(index_table)
     }
 
+    /// Creates an empty index table that is never persisted, `Auto` falls back to a HashMap. So
+    /// do static index tables, as they are read-only, and paged ones, as they need a file.
+    pub fn in_memory(&mut self) -> Box<dyn IndexTable> {
+        match self.index_type {
+            IndexType::BTreeMap => Box::new(BTreeMapIndexTable::in_memory()),
+            #[cfg(feature = "fst-index")]
+            IndexType::Fst => Box::new(FstIndexTable::in_memory()),
+            _ => {
+                self.index_type = IndexType::HashMap;
+                Box::new(HashMapIndexTable::in_memory())
+            }
+        }
+    }
+
     pub fn load_or_create(&mut self, path: PathBuf) -> Result<Box<dyn IndexTable>> {
         let (_, type_path) = IndexFactory::path2path(path.clone());
 
@@ -0,0 +1,466 @@
+use crate::cache::Key;
+use crate::index_table::{is_empty_range, IndexTable};
+use ::fst::{IntoStreamer, Map, MapBuilder, Streamer};
+use anyhow::{bail, Result};
+use memmap2::Mmap;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard};
+
+const MAGIC: &[u8; 8] = b"RDBFST01";
+const HEADER_SIZE: usize = 16;
+const VALUE_SIZE: usize = 16;
+
+/// A sorted index table for large databases, answering lookups, prefix scans and ranges straight
+/// from a memory mapped file.
+///
+/// Keys are stored in a finite state transducer, which shares common prefixes and suffixes of
+/// the keys and maps each key to its rank. The locations follow as a fixed size entry per rank.
+/// Changes are kept in a sorted delta, where deletes are tombstones, until `persist` merges them
+/// into a new file that replaces the previous one.
+///
+/// The file starts with a header (magic and length of the transducer), followed by the
+/// transducer and a value offset and length per key, each as `u64`. All integers are little
+/// endian.
+pub struct FstIndexTable {
+    state: RwLock<State>,
+    file_path: Option<PathBuf>, // `None` keeps the table in memory only
+}
+
+#[derive(Clone)]
+struct State {
+    base: Base,
+    delta: BTreeMap<String, Option<Key>>,
+    len: usize,
+}
+
+// The merged keys, either mapped from the file or built in memory and not persisted yet
+#[derive(Clone)]
+struct Base {
+    keys: Map<Slice>,
+    values: Slice,
+}
+
+#[derive(Clone)]
+enum Bytes {
+    Mapped(Arc<Mmap>),
+    Owned(Arc<Vec<u8>>),
+}
+
+#[derive(Clone)]
+struct Slice {
+    bytes: Bytes,
+    start: usize,
+    end: usize,
+}
+
+impl AsRef<[u8]> for Slice {
+    fn as_ref(&self) -> &[u8] {
+        let bytes = match &self.bytes {
+            Bytes::Mapped(map) => &map[..],
+            Bytes::Owned(data) => &data[..],
+        };
+        &bytes[self.start..self.end]
+    }
+}
+
+impl Base {
+    fn parse(bytes: Bytes) -> Result<Self> {
+        let data = match &bytes {
+            Bytes::Mapped(map) => &map[..],
+            Bytes::Owned(data) => &data[..],
+        };
+        if data.len() < HEADER_SIZE || &data[..8] != MAGIC {
+            bail!("Not an fst index table");
+        }
+        let keys_end = HEADER_SIZE + u64::from_le_bytes(data[8..16].try_into()?) as usize;
+        let end = data.len();
+        if end < keys_end {
+            bail!("Fst index table is truncated");
+        }
+
+        let keys = Map::new(Slice {
+            bytes: bytes.clone(),
+            start: HEADER_SIZE,
+            end: keys_end,
+        })?;
+        if (end - keys_end) != keys.len() * VALUE_SIZE {
+            bail!("Fst index table is truncated");
+        }
+        let values = Slice {
+            bytes,
+            start: keys_end,
+            end,
+        };
+        Ok(Self { keys, values })
+    }
+
+    fn empty() -> Self {
+        Self::build(std::iter::empty::<(&[u8], Key)>()).unwrap()
+    }
+
+    // Builds a table from entries in strictly ascending key order
+    fn build<K: AsRef<[u8]>>(entries: impl Iterator<Item = (K, Key)>) -> Result<Self> {
+        let mut builder = Builder::new();
+        for (key, value) in entries {
+            builder.push(key.as_ref(), value)?;
+        }
+        builder.finish()
+    }
+
+    fn value(&self, rank: u64) -> Key {
+        let values = self.values.as_ref();
+        let at = rank as usize * VALUE_SIZE;
+        let offset = u64::from_le_bytes(values[at..at + 8].try_into().unwrap());
+        let length = u64::from_le_bytes(values[at + 8..at + 16].try_into().unwrap());
+        (offset, length as usize)
+    }
+
+    fn get(&self, key: &str) -> Option<Key> {
+        self.keys.get(key).map(|rank| self.value(rank))
+    }
+
+    fn is_mapped(&self) -> bool {
+        matches!(self.values.bytes, Bytes::Mapped(_))
+    }
+
+    // The whole file, to write it out
+    fn data(&self) -> &[u8] {
+        match &self.values.bytes {
+            Bytes::Mapped(map) => &map[..],
+            Bytes::Owned(data) => &data[..],
+        }
+    }
+}
+
+struct Builder {
+    keys: MapBuilder<Vec<u8>>,
+    values: Vec<u8>,
+    len: u64,
+}
+
+impl Builder {
+    fn new() -> Self {
+        Self {
+            keys: MapBuilder::memory(),
+            values: Vec::new(),
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, key: &[u8], (offset, length): Key) -> Result<()> {
+        self.keys.insert(key, self.len)?;
+        self.values.extend_from_slice(&offset.to_le_bytes());
+        self.values
+            .extend_from_slice(&(length as u64).to_le_bytes());
+        self.len += 1;
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Base> {
+        let keys = self.keys.into_inner()?;
+        let mut data = Vec::with_capacity(HEADER_SIZE + keys.len() + self.values.len());
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&(keys.len() as u64).to_le_bytes());
+        data.extend_from_slice(&keys);
+        data.extend_from_slice(&self.values);
+        Base::parse(Bytes::Owned(Arc::new(data)))
+    }
+}
+
+impl State {
+    fn get(&self, key: &str) -> Option<Key> {
+        match self.delta.get(key) {
+            Some(value) => *value,
+            None => self.base.get(key),
+        }
+    }
+
+    // Visits the keys within the bounds in ascending order, the delta shadowing the base
+    fn for_each(
+        &self,
+        start: Bound<&str>,
+        end: Bound<&str>,
+        mut f: impl FnMut(&[u8], Key) -> Result<()>,
+    ) -> Result<()> {
+        if is_empty_range(start, end) {
+            return Ok(());
+        }
+
+        let mut builder = self.base.keys.range();
+        builder = match start {
+            Bound::Included(key) => builder.ge(key),
+            Bound::Excluded(key) => builder.gt(key),
+            Bound::Unbounded => builder,
+        };
+        builder = match end {
+            Bound::Included(key) => builder.le(key),
+            Bound::Excluded(key) => builder.lt(key),
+            Bound::Unbounded => builder,
+        };
+        let mut stream = builder.into_stream();
+        let mut delta = self.delta.range::<str, _>((start, end)).peekable();
+
+        // Keys of the stream are only borrowed until the next one is read
+        let mut base_key = Vec::new();
+        let mut base_rank = advance(&mut stream, &mut base_key);
+        loop {
+            let order = match (base_rank, delta.peek()) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(_), Some((key, _))) => base_key.as_slice().cmp(key.as_bytes()),
+            };
+            if let (Ordering::Less, Some(rank)) = (order, base_rank) {
+                f(&base_key, self.base.value(rank))?;
+                base_rank = advance(&mut stream, &mut base_key);
+                continue;
+            }
+            if order == Ordering::Equal {
+                base_rank = advance(&mut stream, &mut base_key);
+            }
+
+            let (key, value) = delta.next().unwrap();
+            if let Some(value) = value {
+                f(key.as_bytes(), *value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn merge(&mut self) -> Result<()> {
+        let mut builder = Builder::new();
+        self.for_each(Bound::Unbounded, Bound::Unbounded, |key, value| {
+            builder.push(key, value)
+        })?;
+        self.base = builder.finish()?;
+        self.delta.clear();
+        Ok(())
+    }
+}
+
+fn advance(stream: &mut ::fst::map::Stream<'_>, buffer: &mut Vec<u8>) -> Option<u64> {
+    let (key, rank) = stream.next()?;
+    buffer.clear();
+    buffer.extend_from_slice(key);
+    Some(rank)
+}
+
+impl FstIndexTable {
+    /// Opens the index table file at `path`, or starts an empty one if it doesn't exist yet.
+    pub fn new(path: PathBuf) -> Result<Self> {
+        let table = Self::new_default(path);
+        table.reload()?;
+        Ok(table)
+    }
+
+    pub fn new_default(path: PathBuf) -> Self {
+        Self {
+            state: RwLock::new(State {
+                base: Base::empty(),
+                delta: BTreeMap::new(),
+                len: 0,
+            }),
+            file_path: Some(path),
+        }
+    }
+
+    pub fn in_memory() -> Self {
+        Self {
+            file_path: None,
+            ..Self::new_default(PathBuf::new())
+        }
+    }
+
+    fn state(&self) -> RwLockReadGuard<'_, State> {
+        self.state.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn state_mut(&mut self) -> &mut State {
+        self.state.get_mut().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn reload(&self) -> Result<()> {
+        let Some(path) = self.file_path.as_deref().filter(|path| path.exists()) else {
+            return Ok(());
+        };
+        let base = map(path)?;
+
+        let mut state = self.state.write().unwrap_or_else(PoisonError::into_inner);
+        state.len = base.keys.len();
+        state.base = base;
+        state.delta.clear();
+        Ok(())
+    }
+}
+
+fn map(path: &Path) -> Result<Base> {
+    let file = File::open(path)?;
+    // SAFETY: the file is never modified in place, persisting writes a new file and renames it
+    let map = unsafe { Mmap::map(&file)? };
+    Base::parse(Bytes::Mapped(Arc::new(map)))
+        .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+}
+
+impl IndexTable for FstIndexTable {
+    fn get(&self, key: &str) -> Option<Key> {
+        self.state().get(key)
+    }
+
+    fn insert(&mut self, key: &str, value: Key) -> Result<()> {
+        let state = self.state_mut();
+        if state.get(key).is_none() {
+            state.len += 1;
+        }
+        state.delta.insert(key.to_string(), Some(value));
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &str) -> Result<()> {
+        let state = self.state_mut();
+        if state.get(key).is_some() {
+            state.len -= 1;
+        }
+        if state.base.get(key).is_some() {
+            state.delta.insert(key.to_string(), None);
+        } else {
+            state.delta.remove(key);
+        }
+        Ok(())
+    }
+
+    fn load(&mut self) -> Result<()> {
+        self.reload()
+    }
+
+    fn persist(&self) -> Result<()> {
+        let mut state = self.state.write().unwrap_or_else(PoisonError::into_inner);
+        if !state.delta.is_empty() {
+            state.merge()?;
+        }
+        let Some(path) = &self.file_path else {
+            return Ok(());
+        };
+        if state.base.is_mapped() {
+            return Ok(());
+        }
+
+        // Readers of a snapshot keep the previous file mapped, so it is replaced, not rewritten
+        let temp_path = path.with_extension("fst-tmp");
+        std::fs::write(&temp_path, state.base.data())?;
+        std::fs::rename(&temp_path, path)?;
+        state.base = map(path)?;
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn index_type(&self) -> &str {
+        "fst"
+    }
+
+    fn len(&self) -> usize {
+        self.state().len
+    }
+
+    fn all_key_values(&self) -> Vec<(String, Key)> {
+        self.range(Bound::Unbounded, Bound::Unbounded)
+    }
+
+    fn range(&self, start: Bound<&str>, end: Bound<&str>) -> Vec<(String, Key)> {
+        let mut key_values = Vec::new();
+        // Keys are only ever inserted as strings, and collecting never fails
+        let _ = self.state().for_each(start, end, |key, value| {
+            key_values.push((String::from_utf8_lossy(key).into_owned(), value));
+            Ok(())
+        });
+        key_values
+    }
+
+    fn replace_all(&mut self, mut key_values: Vec<(String, Key)>) -> Result<()> {
+        key_values.sort_by(|(a, _), (b, _)| a.cmp(b));
+        key_values.dedup_by(|(a, _), (b, _)| a == b);
+
+        let state = self.state_mut();
+        state.len = key_values.len();
+        state.base = Base::build(key_values.into_iter())?;
+        state.delta.clear();
+        Ok(())
+    }
+
+    #[cfg(feature = "write")]
+    fn snapshot(&self) -> Box<dyn IndexTable> {
+        Box::new(Self {
+            state: RwLock::new(self.state().clone()),
+            file_path: self.file_path.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delta_and_persist() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("index.fst");
+        let mut table = FstIndexTable::new(path.clone())?;
+        for i in 0..1000 {
+            table.insert(&format!("key{:04}", i), (i, i as usize))?;
+        }
+        table.persist()?;
+        assert!(table.state().delta.is_empty());
+
+        // Changes shadow the file until they are merged
+        table.insert("key0001", (7, 7))?;
+        table.insert("a", (1, 1))?;
+        table.delete("key0002")?;
+        table.delete("missing")?;
+        assert_eq!(table.len(), 1000);
+        assert_eq!(table.get("key0001"), Some((7, 7)));
+        assert_eq!(table.get("key0002"), None);
+
+        let prefix = table.scan_prefix("key000");
+        let keys: Vec<&str> = prefix.iter().map(|(key, _)| key.as_str()).collect();
+        assert_eq!(
+            keys,
+            ["key0000", "key0001", "key0003", "key0004", "key0005"]
+                .into_iter()
+                .chain(["key0006", "key0007", "key0008", "key0009"])
+                .collect::<Vec<_>>()
+        );
+        let range = table.range(Bound::Excluded("a"), Bound::Included("key0001"));
+        assert_eq!(
+            range,
+            vec![
+                ("key0000".to_string(), (0, 0)),
+                ("key0001".to_string(), (7, 7))
+            ]
+        );
+        assert!(table
+            .range(Bound::Excluded("b"), Bound::Excluded("b"))
+            .is_empty());
+
+        table.persist()?;
+        let loaded = FstIndexTable::new(path)?;
+        assert_eq!(loaded.len(), 1000);
+        assert_eq!(loaded.get("a"), Some((1, 1)));
+        assert_eq!(loaded.get("key0001"), Some((7, 7)));
+        assert_eq!(loaded.get("key0002"), None);
+        assert_eq!(loaded.all_key_values(), table.all_key_values());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_garbage() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("index.fst");
+        std::fs::write(&path, b"garbage")?;
+        assert!(FstIndexTable::new(path).is_err());
+        Ok(())
+    }
+}
@@ -1,7 +1,8 @@
 use crate::cache::Key;
 use crate::default_persist;
+use crate::index_table::encoding::read_index;
+use crate::index_table::factory::IndexFormat;
 use crate::index_table::IndexTable;
-use bincode::deserialize_from;
 use fs2::FileExt;
 use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
@@ -11,7 +12,8 @@ use std::path::PathBuf;
 #[repr(C)]
 pub struct HashMapIndexTable {
     table: HashMap<String, Key>,
-    file_path: PathBuf,
+    file_path: Option<PathBuf>, // `None` keeps the table in memory only
+    format: IndexFormat,
 }
 
 impl HashMapIndexTable {
@@ -20,32 +22,46 @@ impl HashMapIndexTable {
             .read(true)
             .write(true)
             .create(true)
+            .truncate(false)
             .open(&path)?;
 
         // Lock the file
         file.lock_exclusive()?;
 
         let reader = BufReader::new(&file);
-        let table: HashMap<String, Key> = match deserialize_from(reader) {
-            Ok(table) => table,
-            Err(_) => HashMap::new(),
-        };
+        let table: HashMap<String, Key> = read_index(reader)?;
 
         // Remember to unlock the file when done
-        file.unlock()?;
+        FileExt::unlock(&file)?;
 
         Ok(Self {
             table,
-            file_path: path,
+            file_path: Some(path),
+            format: IndexFormat::default(),
         })
     }
 
     pub fn new_default(path: PathBuf) -> anyhow::Result<Self> {
         Ok(Self {
             table: HashMap::new(),
-            file_path: path,
+            file_path: Some(path),
+            format: IndexFormat::default(),
         })
     }
+
+    pub fn in_memory() -> Self {
+        Self {
+            table: HashMap::new(),
+            file_path: None,
+            format: IndexFormat::default(),
+        }
+    }
+
+    /// Sets the format the table is written in on `persist`.
+    pub fn with_format(mut self, format: IndexFormat) -> Self {
+        self.format = format;
+        self
+    }
 }
 
 impl IndexTable for HashMapIndexTable {
@@ -64,25 +80,27 @@ impl IndexTable for HashMapIndexTable {
     }
 
     fn load(&mut self) -> anyhow::Result<()> {
-        let file = File::open(&self.file_path)?;
+        let Some(file_path) = &self.file_path else {
+            return Ok(());
+        };
+        let file = File::open(file_path)?;
 
         // Lock the file
         file.lock_exclusive()?;
 
         let reader = BufReader::new(&file);
-        self.table = match deserialize_from(reader) {
-            Ok(table) => table,
-            Err(_) => HashMap::new(),
-        };
+        self.table = read_index(reader)?;
 
         // Remember to unlock the file when done
-        file.unlock()?;
+        FileExt::unlock(&file)?;
 
         Ok(())
     }
 
     fn persist(&self) -> anyhow::Result<()> {
-        default_persist!(self, self.file_path.clone(), self.table);
+        if let Some(file_path) = &self.file_path {
+            default_persist!(self, file_path, self.table, self.format);
+        }
         Ok(())
     }
 
@@ -91,6 +109,10 @@ impl IndexTable for HashMapIndexTable {
         "hash_map"
     }
 
+    fn len(&self) -> usize {
+        self.table.len()
+    }
+
     fn all_key_values(&self) -> Vec<(String, Key)> {
         self.table.iter().map(|(k, v)| (k.clone(), *v)).collect()
     }
@@ -105,6 +127,7 @@ impl IndexTable for HashMapIndexTable {
         Box::new(Self {
             table: self.table.clone(),
             file_path: self.file_path.clone(),
+            format: self.format,
         })
     }
 }
@@ -128,7 +151,7 @@ mod tests {
         index_table.insert(key, value)?;
 
         // Assert - get returns the correct value
-        assert_eq!(index_table.get(&key), Some(value));
+        assert_eq!(index_table.get(key), Some(value));
 
         // Test - persist to disk
         index_table.persist()?;
@@ -136,7 +159,7 @@ mod tests {
         // Assert - load from disk
         let mut loaded_table = HashMapIndexTable::new(index_path)?;
         loaded_table.load()?;
-        assert_eq!(loaded_table.get(&key), Some(value));
+        assert_eq!(loaded_table.get(key), Some(value));
 
         // Cleanup
         temp_dir.close()?;
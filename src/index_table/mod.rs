@@ -2,13 +2,21 @@
 ///
 /// It supports both `BTree` and `HashMap` based index tables.
 /// The factory module provides utilities for creating these index tables.
+pub mod bloom;
+pub mod bplus_tree;
 pub mod btree;
+pub(crate) mod encoding;
+#[cfg(feature = "fst-index")]
+pub mod fst_table;
 pub mod hash_map;
+#[cfg(feature = "static-index")]
+pub mod static_hash;
 
 pub(crate) mod factory;
 
 use crate::cache::Key;
 use anyhow::Result;
+use std::ops::{Bound, RangeBounds};
 
 /// Represents an index table which can store key-value pairs, where the key is a string and
 /// the value is a tuple of two usize integers.
@@ -20,6 +28,12 @@ pub trait IndexTable: Send + Sync {
     /// Returns `None` if the key is not present in the table.
     fn get(&self, key: &str) -> Option<crate::cache::Key>;
 
+    /// Retrieves a value by its key like [`IndexTable::get`], but returns an error if the table
+    /// can't be read instead of treating the key as missing. Writes use it.
+    fn try_get(&self, key: &str) -> Result<Option<crate::cache::Key>> {
+        Ok(self.get(key))
+    }
+
     /// Inserts a key-value pair into the index table.
     ///
     /// Returns a `Result` indicating success or failure of the operation.
@@ -32,6 +46,11 @@ pub trait IndexTable: Send + Sync {
     /// Returns a `Result` indicating success or failure of the operation.
     fn delete(&mut self, key: &str) -> Result<()>;
 
+    /// Returns whether keys can be inserted and deleted. Writes check it before writing any data.
+    fn is_writable(&self) -> bool {
+        true
+    }
+
     /// Loads the index table from its storage.
     ///
     /// Returns a `Result` indicating success or failure of the operation.
@@ -46,9 +65,38 @@ pub trait IndexTable: Send + Sync {
     /// Returns the type of the index for testing purposes.
     fn index_type(&self) -> &str;
 
+    /// Returns the number of keys in the index table.
+    fn len(&self) -> usize;
+
+    /// Returns whether the index table contains no keys.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     /// Returns all the key-value pairs in the index table.
     fn all_key_values(&self) -> Vec<(String, Key)>;
 
+    /// Returns the key-value pairs with keys between `start` and `end`, sorted by key.
+    ///
+    /// The default implementation scans all keys, sorted tables only visit the ones in range.
+    fn range(&self, start: Bound<&str>, end: Bound<&str>) -> Vec<(String, Key)> {
+        let mut key_values: Vec<(String, Key)> = self
+            .all_key_values()
+            .into_iter()
+            .filter(|(key, _)| RangeBounds::<str>::contains(&(start, end), key.as_str()))
+            .collect();
+        key_values.sort_by(|(a, _), (b, _)| a.cmp(b));
+        key_values
+    }
+
+    /// Returns the key-value pairs whose keys start with `prefix`, sorted by key.
+    fn scan_prefix(&self, prefix: &str) -> Vec<(String, Key)> {
+        match prefix_end(prefix) {
+            Some(end) => self.range(Bound::Included(prefix), Bound::Excluded(&end)),
+            None => self.range(Bound::Included(prefix), Bound::Unbounded),
+        }
+    }
+
     /// Replaces all the key-value pairs in the index table.
     fn replace_all(&mut self, key_values: Vec<(String, Key)>) -> Result<()>;
 
@@ -58,15 +106,60 @@ pub trait IndexTable: Send + Sync {
     fn snapshot(&self) -> Box<dyn IndexTable>;
 }
 
+// The smallest key greater than all keys starting with `prefix`, `None` if there is none
+fn prefix_end(prefix: &str) -> Option<String> {
+    let mut end = prefix.to_string();
+    while let Some(last) = end.pop() {
+        // Skips the surrogates, which are no chars
+        let next = (last as u32 + 1..=char::MAX as u32).find_map(char::from_u32);
+        if let Some(next) = next {
+            end.push(next);
+            return Some(end);
+        }
+    }
+    None
+}
+
+// A hash that never changes between platforms or Rust versions, as it is persisted. FNV-1a,
+// with a finalizer so the high bits are mixed as well
+pub(crate) fn hash(key: &[u8], seed: u64) -> u64 {
+    let mut hash = 0xcbf29ce484222325 ^ seed;
+    for byte in key {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    mix(hash)
+}
+
+// The splitmix64 finalizer
+pub(crate) fn mix(mut x: u64) -> u64 {
+    x ^= x >> 30;
+    x = x.wrapping_mul(0xbf58476d1ce4e5b9);
+    x ^= x >> 27;
+    x = x.wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+// `BTreeMap::range` panics on bounds that can never contain a key
+pub(crate) fn is_empty_range(start: Bound<&str>, end: Bound<&str>) -> bool {
+    match (start, end) {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (Bound::Included(start) | Bound::Excluded(start), Bound::Excluded(end))
+        | (Bound::Excluded(start), Bound::Included(end)) => start >= end,
+        _ => false,
+    }
+}
+
 #[macro_export]
 /// This macro provides a default implementation for persisting an index table.
 ///
-/// It takes in three parameters:
+/// It takes in four parameters:
 /// - `$self`: the reference to the current index table.
 /// - `$file_path`: the path to the file where the index table will be persisted.
 /// - `$table`: the actual data table that needs to be persisted.
+/// - `$format`: the [`IndexFormat`](crate::IndexFormat) the table is written in.
 macro_rules! default_persist {
-    ($self:expr, $file_path:expr, $table:expr) => {
+    ($self:expr, $file_path:expr, $table:expr, $format:expr) => {
         let file = std::fs::OpenOptions::new()
             .write(true)
             .truncate(true)
@@ -77,9 +170,14 @@ macro_rules! default_persist {
         file.lock_exclusive()?;
 
         let writer = std::io::BufWriter::new(&file);
-        bincode::serialize_into(writer, &$table)?;
+        match $format {
+            $crate::IndexFormat::Bincode => bincode::serialize_into(writer, &$table)?,
+            $crate::IndexFormat::Compact => {
+                $crate::index_table::encoding::write_index(writer, &$table)?
+            }
+        }
 
         // Remember to unlock the file when done
-        file.unlock()?;
+        FileExt::unlock(&file)?;
     };
 }
@@ -0,0 +1,352 @@
+use crate::cache::Key;
+use crate::index_table::{hash, mix, IndexTable};
+use anyhow::{bail, Result};
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+const MAGIC: &[u8; 8] = b"RDBPHF01";
+const HEADER_SIZE: usize = 32;
+const ENTRY_SIZE: usize = 32;
+// Average number of keys per bucket, fewer keys per bucket make the pilots easier to find
+const BUCKET_SIZE: usize = 4;
+// Seeds tried before giving up, a seed only fails if hashes of different keys collide
+const MAX_SEEDS: u64 = 16;
+
+/// A read-only index table for databases that are built once and then only read.
+///
+/// Keys are mapped to their entry by a minimal perfect hash function, built with hash and
+/// displace: keys are hashed into buckets, and each bucket stores a pilot that moves its keys to
+/// free slots. The file is memory mapped, so opening it is O(1) regardless of the number of keys,
+/// and lookups compare the key stored in the entry, so unknown keys are never mistaken for
+/// known ones.
+///
+/// The file starts with a header (magic, seed, number of keys and buckets), followed by a `u32`
+/// pilot per bucket padded to 8 bytes, an entry per key (key offset and length, value offset and
+/// length, each as `u64`) and finally the key bytes. All integers are little endian.
+#[derive(Clone)]
+pub struct StaticIndexTable {
+    map: Arc<Mmap>,
+    seed: u64,
+    len: usize,
+    buckets: usize,
+    entries_start: usize,
+    keys_start: usize,
+}
+
+impl StaticIndexTable {
+    /// Maps the index table file at `path`.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: the file is never modified in place, `build` writes a new file and renames it
+        // over `path`, which leaves this mapping untouched
+        let map = unsafe { Mmap::map(&file)? };
+
+        if map.len() < HEADER_SIZE || &map[..8] != MAGIC {
+            bail!("{} is not a static index table", path.display());
+        }
+        let seed = read_u64(&map, 8);
+        let len = usize::try_from(read_u64(&map, 16))?;
+        let buckets = usize::try_from(read_u64(&map, 24))?;
+        let entries_start = buckets
+            .checked_mul(4)
+            .and_then(|size| size.checked_next_multiple_of(8))
+            .and_then(|size| size.checked_add(HEADER_SIZE));
+        let keys_start = entries_start
+            .zip(len.checked_mul(ENTRY_SIZE))
+            .and_then(|(start, size)| start.checked_add(size));
+        let (Some(entries_start), Some(keys_start)) = (entries_start, keys_start) else {
+            bail!("Static index table {} is corrupted", path.display());
+        };
+        if map.len() < keys_start {
+            bail!("Static index table {} is truncated", path.display());
+        }
+
+        Ok(Self {
+            map: Arc::new(map),
+            seed,
+            len,
+            buckets,
+            entries_start,
+            keys_start,
+        })
+    }
+
+    /// Writes an index table holding `key_values` to `path`.
+    ///
+    /// The table is written to a temporary file that then replaces `path`, so tables that are
+    /// still open keep mapping the previous file.
+    pub fn build(path: &Path, key_values: &[(String, Key)]) -> Result<()> {
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        for seed in 0..MAX_SEEDS {
+            let hashes: Vec<u64> = key_values
+                .iter()
+                .map(|(key, _)| hash(key.as_bytes(), seed))
+                .collect();
+            let Some((pilots, slots)) = find_pilots(&hashes) else {
+                continue;
+            };
+
+            let mut writer = BufWriter::new(File::create(&temp_path)?);
+            writer.write_all(MAGIC)?;
+            for value in [seed, key_values.len() as u64, pilots.len() as u64] {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+            for pilot in &pilots {
+                writer.write_all(&pilot.to_le_bytes())?;
+            }
+            if pilots.len() % 2 == 1 {
+                writer.write_all(&[0; 4])?;
+            }
+
+            let mut key_offset = 0;
+            let mut ordered = vec![0; key_values.len()];
+            for (i, slot) in slots.iter().enumerate() {
+                ordered[*slot] = i;
+            }
+            for i in &ordered {
+                let (key, (offset, length)) = &key_values[*i];
+                let entry = [key_offset, key.len() as u64, *offset, *length as u64];
+                for value in entry {
+                    writer.write_all(&value.to_le_bytes())?;
+                }
+                key_offset += key.len() as u64;
+            }
+            for i in &ordered {
+                writer.write_all(key_values[*i].0.as_bytes())?;
+            }
+
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+            std::fs::rename(&temp_path, path)?;
+            return Ok(());
+        }
+
+        bail!("Failed to build a perfect hash function, are the keys unique?")
+    }
+
+    // The stored key and the location of an entry, `None` if the file is corrupted
+    fn entry(&self, slot: usize) -> Option<(&[u8], Key)> {
+        let start = self.entries_start + slot * ENTRY_SIZE;
+        let key_offset = usize::try_from(read_u64(&self.map, start)).ok()?;
+        let key_offset = self.keys_start.checked_add(key_offset)?;
+        let key_length = usize::try_from(read_u64(&self.map, start + 8)).ok()?;
+        let offset = read_u64(&self.map, start + 16);
+        let length = read_u64(&self.map, start + 24) as usize;
+        let key = self
+            .map
+            .get(key_offset..key_offset.checked_add(key_length)?)?;
+        Some((key, (offset, length)))
+    }
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+fn bucket(hash: u64, buckets: usize) -> usize {
+    ((hash as u128 * buckets as u128) >> 64) as usize
+}
+
+fn slot(hash: u64, pilot: u32, len: usize) -> usize {
+    ((hash ^ mix(pilot as u64 + 1)) % len as u64) as usize
+}
+
+// Finds a pilot per bucket such that all keys land in distinct slots, largest buckets first.
+// Returns the pilots and the slot of every key, or `None` if some bucket has no valid pilot
+fn find_pilots(hashes: &[u64]) -> Option<(Vec<u32>, Vec<usize>)> {
+    let len = hashes.len();
+    let buckets = len.div_ceil(BUCKET_SIZE).max(1);
+    let mut members = vec![Vec::new(); buckets];
+    for (i, hash) in hashes.iter().enumerate() {
+        members[bucket(*hash, buckets)].push(i);
+    }
+    let mut order: Vec<usize> = (0..buckets).collect();
+    order.sort_by_key(|b| std::cmp::Reverse(members[*b].len()));
+
+    // The last keys only find a free slot every `len` pilots on average
+    let max_pilot = (len * 64).clamp(1 << 10, u32::MAX as usize) as u32;
+    let mut pilots = vec![0; buckets];
+    let mut slots = vec![0; len];
+    let mut taken = vec![false; len];
+    let mut candidate = Vec::with_capacity(BUCKET_SIZE * 4);
+    for b in order {
+        if members[b].is_empty() {
+            break;
+        }
+
+        let pilot = (0..max_pilot).find(|pilot| {
+            candidate.clear();
+            for i in &members[b] {
+                let slot = slot(hashes[*i], *pilot, len);
+                if taken[slot] || candidate.contains(&slot) {
+                    return false;
+                }
+                candidate.push(slot);
+            }
+            true
+        })?;
+
+        pilots[b] = pilot;
+        for (i, slot) in members[b].iter().zip(&candidate) {
+            slots[*i] = *slot;
+            taken[*slot] = true;
+        }
+    }
+    Some((pilots, slots))
+}
+
+impl IndexTable for StaticIndexTable {
+    fn get(&self, key: &str) -> Option<Key> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let hash = hash(key.as_bytes(), self.seed);
+        let b = bucket(hash, self.buckets);
+        let pilot_at = HEADER_SIZE + b * 4;
+        let pilot = u32::from_le_bytes(self.map[pilot_at..pilot_at + 4].try_into().unwrap());
+
+        let (stored, location) = self.entry(slot(hash, pilot, self.len))?;
+        (stored == key.as_bytes()).then_some(location)
+    }
+
+    fn insert(&mut self, _key: &str, _value: Key) -> Result<()> {
+        bail!("Static index tables are read-only")
+    }
+
+    fn delete(&mut self, _key: &str) -> Result<()> {
+        bail!("Static index tables are read-only")
+    }
+
+    fn is_writable(&self) -> bool {
+        false
+    }
+
+    fn load(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn persist(&self) -> Result<()> {
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn index_type(&self) -> &str {
+        "static"
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn all_key_values(&self) -> Vec<(String, Key)> {
+        (0..self.len)
+            .filter_map(|slot| self.entry(slot))
+            .map(|(key, location)| (String::from_utf8_lossy(key).into_owned(), location))
+            .collect()
+    }
+
+    fn replace_all(&mut self, _key_values: Vec<(String, Key)>) -> Result<()> {
+        bail!("Static index tables are read-only")
+    }
+
+    #[cfg(feature = "write")]
+    fn snapshot(&self) -> Box<dyn IndexTable> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_and_get() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("index.static");
+        let key_values: Vec<(String, Key)> = (0..10_000)
+            .map(|i| (format!("key{}", i), (i as u64 * 10, i)))
+            .collect();
+        StaticIndexTable::build(&path, &key_values)?;
+
+        let mut table = StaticIndexTable::open(&path)?;
+        assert_eq!(table.len(), 10_000);
+        for (key, location) in &key_values {
+            assert_eq!(table.get(key), Some(*location));
+        }
+        assert_eq!(table.get("key10000"), None);
+        assert_eq!(table.get(""), None);
+        assert!(table.insert("key", (0, 0)).is_err());
+
+        let mut all = table.all_key_values();
+        all.sort();
+        let mut expected = key_values;
+        expected.sort();
+        assert_eq!(all, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("index.static");
+        StaticIndexTable::build(&path, &[])?;
+
+        let table = StaticIndexTable::open(&path)?;
+        assert!(table.is_empty());
+        assert_eq!(table.get("key"), None);
+
+        std::fs::write(&path, b"garbage")?;
+        assert!(StaticIndexTable::open(&path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rebuild_while_open() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("index.static");
+        StaticIndexTable::build(&path, &[("old".to_string(), (1, 1))])?;
+        let table = StaticIndexTable::open(&path)?;
+
+        let key_values: Vec<(String, Key)> = (0..1000)
+            .map(|i| (format!("new{}", i), (i as u64, i)))
+            .collect();
+        StaticIndexTable::build(&path, &key_values)?;
+        assert_eq!(table.get("old"), Some((1, 1)));
+        assert_eq!(table.len(), 1);
+        assert_eq!(StaticIndexTable::open(&path)?.len(), 1000);
+        assert!(!temp_dir.path().join("index.static.tmp").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupted() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("index.static");
+        StaticIndexTable::build(&path, &[("key".to_string(), (1, 1))])?;
+        let valid = std::fs::read(&path)?;
+
+        // Numbers of keys and buckets whose sizes overflow
+        for at in [16, 24] {
+            let mut data = valid.clone();
+            data[at..at + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+            std::fs::write(&path, data)?;
+            assert!(StaticIndexTable::open(&path).is_err());
+        }
+
+        // A key offset past the end of the file
+        let mut data = valid;
+        let entries_start = HEADER_SIZE + 8;
+        data[entries_start..entries_start + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        std::fs::write(&path, data)?;
+        let table = StaticIndexTable::open(&path)?;
+        assert_eq!(table.get("key"), None);
+        assert!(table.all_key_values().is_empty());
+        Ok(())
+    }
+}
@@ -2,15 +2,18 @@
 
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 const DEFAULT_BUFFER_SIZE: usize = 4096; // For example, 4KB
 
+// Without a path, the whole content is kept in the buffer and never persisted
 pub(crate) struct BufferedFile {
-    path: PathBuf,
+    path: Option<PathBuf>,
     buffer: Vec<u8>,
     file_length: u64,
     file: Option<File>,
+    // Kept open for writes in place, appending goes through `persist`
+    writer: Option<File>,
 }
 
 impl BufferedFile {
@@ -26,6 +29,7 @@ impl BufferedFile {
         let file = Some(File::open(&path).unwrap_or_else(|_| {
             OpenOptions::new()
                 .create(true)
+                .truncate(false)
                 .read(true)
                 .write(true)
                 .open(&path)
@@ -33,17 +37,41 @@ impl BufferedFile {
         }));
 
         BufferedFile {
-            path,
+            path: Some(path),
             buffer: Vec::with_capacity(DEFAULT_BUFFER_SIZE),
             file_length,
             file,
+            writer: None,
         }
     }
 
+    pub fn in_memory(data: Vec<u8>) -> Self {
+        BufferedFile {
+            path: None,
+            file_length: data.len() as u64,
+            buffer: data,
+            file: None,
+            writer: None,
+        }
+    }
+
+    /// Writes the content, including anything still buffered, to the file at `path`.
+    pub fn save_to(&self, path: &Path) -> Result<(), std::io::Error> {
+        let mut file = File::create(path)?;
+        if let Some(source) = &self.path {
+            let persisted = self.file_length - self.buffer.len() as u64;
+            std::io::copy(&mut File::open(source)?.take(persisted), &mut file)?;
+        }
+        file.write_all(&self.buffer)
+    }
+
     pub fn read(&mut self, offset: u64, length: usize) -> Result<Vec<u8>, std::io::Error> {
         if offset >= (self.file_length - self.buffer.len() as u64) {
             let start = (offset - (self.file_length - self.buffer.len() as u64)) as usize;
             let end = start + length;
+            if end > self.buffer.len() {
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+            }
 
             return Ok(self.buffer[start..end].to_vec());
         }
@@ -72,31 +100,119 @@ impl BufferedFile {
     }
 
     pub fn persist(&mut self) -> Result<(), std::io::Error> {
+        if self.path.is_none() {
+            return Ok(());
+        }
         self.ensure_file_closed();
 
-        let mut file = OpenOptions::new().append(true).open(&self.path)?;
+        let mut file = OpenOptions::new().append(true).open(self.path()?)?;
         file.write_all(&self.buffer)?;
 
         self.buffer.clear();
         Ok(())
     }
 
+    fn path(&self) -> Result<&Path, std::io::Error> {
+        self.path
+            .as_deref()
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound))
+    }
+
     fn ensure_file_open(&mut self) -> Result<(), std::io::Error> {
         if self.file.is_none() {
-            self.file = Some(File::open(&self.path)?);
+            self.file = Some(File::open(self.path()?)?);
         }
         Ok(())
     }
 
+    fn writer(&mut self) -> Result<&mut File, std::io::Error> {
+        if self.writer.is_none() {
+            self.writer = Some(OpenOptions::new().write(true).open(self.path()?)?);
+        }
+        Ok(self.writer.as_mut().unwrap())
+    }
+
     fn ensure_file_closed(&mut self) {
         if let Some(file) = self.file.take() {
             drop(file); // Close the file explicitly
         }
     }
 
+    pub fn size(&self) -> u64 {
+        self.file_length
+    }
+
+    #[cfg(feature = "garbage-collection")]
+    pub fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<(), std::io::Error> {
+        // The target range might still be buffered, which is always the case in memory
+        let buffer_start = self.file_length - self.buffer.len() as u64;
+        if offset >= buffer_start {
+            let start = (offset - buffer_start) as usize;
+            self.buffer[start..start + data.len()].copy_from_slice(data);
+            return Ok(());
+        }
+        if offset + data.len() as u64 > buffer_start {
+            self.persist()?;
+        }
+
+        let file = self.writer()?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(data)?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "garbage-collection")]
+    pub fn truncate(&mut self, length: u64) -> Result<(), std::io::Error> {
+        self.persist()?;
+        if self.path.is_none() {
+            self.buffer.truncate(length as usize);
+            self.file_length = length;
+            return Ok(());
+        }
+
+        self.writer()?.set_len(length)?;
+        self.file_length = length;
+
+        Ok(())
+    }
+
+    /// Deallocates the given range of the file, without changing its size.
+    ///
+    /// Fails with [`std::io::ErrorKind::Unsupported`] if the platform or file system can't.
+    #[cfg(feature = "garbage-collection")]
+    pub fn punch_hole(&mut self, offset: u64, length: u64) -> Result<(), std::io::Error> {
+        self.persist()?;
+        if self.path.is_none() {
+            return Err(std::io::Error::from(std::io::ErrorKind::Unsupported));
+        }
+
+        punch_hole(self.writer()?, offset, length)
+    }
+
+    /// Number of bytes the file occupies on disk.
+    #[cfg(feature = "garbage-collection")]
+    pub fn allocated_size(&self) -> Result<u64, std::io::Error> {
+        let Some(path) = &self.path else {
+            return Ok(self.buffer.len() as u64);
+        };
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            Ok(std::fs::metadata(path)?.blocks() * 512)
+        }
+
+        #[cfg(not(unix))]
+        Ok(std::fs::metadata(path)?.len())
+    }
+
     #[cfg(feature = "garbage-collection")]
     pub fn read_all(&mut self) -> Result<Vec<u8>, std::io::Error> {
         self.persist()?; // Persist the buffer before reading the file
+        if self.path.is_none() {
+            return Ok(self.buffer.clone());
+        }
         self.ensure_file_open()?;
 
         let file = self.file.as_mut().unwrap();
@@ -111,8 +227,13 @@ impl BufferedFile {
 
     #[cfg(feature = "garbage-collection")]
     pub fn replace_with(&mut self, data: &[u8]) -> Result<(), std::io::Error> {
+        if self.path.is_none() {
+            self.buffer = data.to_vec();
+            self.file_length = self.buffer.len() as u64;
+            return Ok(());
+        }
         self.ensure_file_closed();
-        let mut file = OpenOptions::new().write(true).open(&self.path)?;
+        let mut file = OpenOptions::new().write(true).open(self.path()?)?;
         file.write_all(data)?;
         self.file_length = data.len() as u64;
 
@@ -122,3 +243,35 @@ impl BufferedFile {
         Ok(())
     }
 }
+
+#[cfg(all(feature = "garbage-collection", target_os = "linux"))]
+fn punch_hole(file: &File, offset: u64, length: u64) -> Result<(), std::io::Error> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: the file descriptor belongs to `file`, which stays open for the whole call, and
+    // fallocate doesn't access any memory of this process
+    let result = unsafe {
+        libc::fallocate(
+            file.as_raw_fd(),
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            offset as libc::off_t,
+            length as libc::off_t,
+        )
+    };
+    if result == 0 {
+        return Ok(());
+    }
+
+    let error = std::io::Error::last_os_error();
+    match error.raw_os_error() {
+        Some(libc::EOPNOTSUPP) | Some(libc::ENOSYS) => {
+            Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+        }
+        _ => Err(error),
+    }
+}
+
+#[cfg(all(feature = "garbage-collection", not(target_os = "linux")))]
+fn punch_hole(_file: &File, _offset: u64, _length: u64) -> Result<(), std::io::Error> {
+    Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+}
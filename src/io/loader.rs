@@ -1,32 +1,277 @@
 use crate::io::buffered_file::BufferedFile;
 use crate::io::Loader;
-use std::path::PathBuf;
+use anyhow::bail;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
+pub(crate) const DATA_FILE_NAME: &str = ".rdb.data";
+pub(crate) const SEGMENT_MANIFEST_NAME: &str = ".rdb.segments";
+
+// The upper bits of an offset hold the segment id, the lower bits the offset within the segment.
+// The first segment is the plain data file, so unsegmented databases remain unchanged.
+const SEGMENT_BITS: u32 = 40;
+// Offsets within a segment have to fit into the lower bits, so no segment can grow any larger
+const MAX_SEGMENT_SIZE: u64 = 1 << SEGMENT_BITS;
+
+pub(crate) fn segment_of(offset: u64) -> u32 {
+    (offset >> SEGMENT_BITS) as u32
+}
+
+#[cfg(any(feature = "write", feature = "garbage-collection", test))]
+pub(crate) fn segment_start(segment: u32) -> u64 {
+    (segment as u64) << SEGMENT_BITS
+}
+
+fn local_offset(offset: u64) -> u64 {
+    offset & ((1 << SEGMENT_BITS) - 1)
+}
+
+fn check_segment_size(segment_size: Option<u64>) -> anyhow::Result<()> {
+    if let Some(size) = segment_size.filter(|size| *size > MAX_SEGMENT_SIZE) {
+        bail!(
+            "Segment size {} exceeds the maximum of {} bytes",
+            size,
+            MAX_SEGMENT_SIZE
+        );
+    }
+    Ok(())
+}
+
+fn segment_path(directory: &Path, segment: u32) -> PathBuf {
+    match segment {
+        0 => directory.join(DATA_FILE_NAME),
+        _ => directory.join(format!("{}.{}", DATA_FILE_NAME, segment)),
+    }
+}
+
+// The first segment always exists, the others are found by their file names
+fn segment_ids(directory: &Path) -> Vec<u32> {
+    let mut segments = vec![0];
+    if let Ok(entries) = std::fs::read_dir(directory) {
+        let prefix = format!("{}.", DATA_FILE_NAME);
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let segment = name
+                .to_str()
+                .and_then(|name| name.strip_prefix(prefix.as_str()))
+                .and_then(|id| id.parse::<u32>().ok());
+            if let Some(segment) = segment.filter(|segment| *segment > 0) {
+                segments.push(segment);
+            }
+        }
+    }
+    segments
+}
+
+/// Loads values from the data files of a database.
+///
+/// The data is split into segments, of which only the last one is written to. Once a segment
+/// exceeds the configured segment size, it is sealed and a new one is started. Without a
+/// directory, all segments are kept in memory.
+#[cfg_attr(
+    not(any(feature = "write", feature = "garbage-collection")),
+    allow(dead_code) // Read-only databases never create segments
+)]
 pub struct LazyLoader {
-    file: BufferedFile,
+    directory: Option<PathBuf>,
+    segments: BTreeMap<u32, BufferedFile>,
+    segment_size: Option<u64>,
+    reads: u64,
+    bytes_read: u64,
 }
 
 impl LazyLoader {
-    pub fn new<P: Into<PathBuf> + Clone>(path: P) -> Self {
-        let file = BufferedFile::new(path);
-        Self { file }
+    pub fn new<P: Into<PathBuf> + Clone>(directory: P, segment_size: Option<u64>) -> Self {
+        check_segment_size(segment_size).unwrap();
+        let directory = directory.into();
+        let segments = segment_ids(&directory)
+            .into_iter()
+            .map(|segment| {
+                (
+                    segment,
+                    BufferedFile::new(segment_path(&directory, segment)),
+                )
+            })
+            .collect();
+
+        Self {
+            directory: Some(directory),
+            segments,
+            segment_size,
+            reads: 0,
+            bytes_read: 0,
+        }
+    }
+
+    pub fn in_memory(segment_size: Option<u64>) -> Self {
+        check_segment_size(segment_size).unwrap();
+        Self {
+            directory: None,
+            segments: BTreeMap::from([(0, BufferedFile::in_memory(Vec::new()))]),
+            segment_size,
+            reads: 0,
+            bytes_read: 0,
+        }
+    }
+
+    /// Reads the data files in `directory` into memory.
+    pub fn load_into_memory(directory: &Path, segment_size: Option<u64>) -> anyhow::Result<Self> {
+        check_segment_size(segment_size)?;
+        let mut loader = Self::in_memory(segment_size);
+        for segment in segment_ids(directory) {
+            let data = std::fs::read(segment_path(directory, segment))?;
+            loader
+                .segments
+                .insert(segment, BufferedFile::in_memory(data));
+        }
+        Ok(loader)
+    }
+
+    /// Writes all segments, including buffered data, as data files to `directory`.
+    pub fn save_to(&self, directory: &Path) -> anyhow::Result<()> {
+        for (segment, file) in &self.segments {
+            file.save_to(&segment_path(directory, *segment))?;
+        }
+        if self.segments.len() > 1 || self.segment_size.is_some() {
+            self.write_manifest(directory)?;
+        }
+        Ok(())
+    }
+
+    fn segment(&mut self, offset: u64) -> anyhow::Result<&mut BufferedFile> {
+        let segment = segment_of(offset);
+        self.segments
+            .get_mut(&segment)
+            .ok_or_else(|| anyhow::anyhow!("Segment {} does not exist", segment))
+    }
+
+    #[cfg(any(feature = "write", feature = "garbage-collection"))]
+    fn active_segment(&mut self, incoming: usize) -> anyhow::Result<(u32, &mut BufferedFile)> {
+        let (&active, file) = self.segments.iter().next_back().unwrap();
+        let full = match self.segment_size {
+            Some(limit) => file.size() > 0 && file.size() + incoming as u64 > limit,
+            None => false,
+        };
+
+        let segment = if full {
+            // Seal the active segment, and start a new one
+            self.segments.get_mut(&active).unwrap().persist()?;
+            let file = match &self.directory {
+                Some(directory) => BufferedFile::new(segment_path(directory, active + 1)),
+                None => BufferedFile::in_memory(Vec::new()),
+            };
+            self.segments.insert(active + 1, file);
+            active + 1
+        } else {
+            active
+        };
+
+        let file = self.segments.get_mut(&segment).unwrap();
+        if file.size() + incoming as u64 > MAX_SEGMENT_SIZE {
+            bail!("Segment {} can't hold another {} bytes", segment, incoming);
+        }
+        Ok((segment, file))
+    }
+
+    // Lists every segment with its size, so a remote copy can tell which segments changed
+    fn write_manifest(&self, directory: &Path) -> anyhow::Result<()> {
+        let manifest = self
+            .segments
+            .iter()
+            .map(|(segment, file)| format!("{} {}\n", segment, file.size()))
+            .collect::<String>();
+        std::fs::write(directory.join(SEGMENT_MANIFEST_NAME), manifest)?;
+        Ok(())
     }
 }
 
 impl Loader for LazyLoader {
     fn load(&mut self, offset: u64, length: usize) -> anyhow::Result<Vec<u8>> {
-        Ok(self.file.read(offset, length)?)
+        let data = self.segment(offset)?.read(local_offset(offset), length)?;
+        self.reads += 1;
+        self.bytes_read += data.len() as u64;
+        Ok(data)
     }
 
-    #[cfg(feature = "write")]
+    #[cfg(any(feature = "write", feature = "garbage-collection"))]
     fn add(&mut self, data: &[u8]) -> anyhow::Result<(u64, usize)> {
-        let (offset, length) = self.file.add(data)?;
-        Ok((offset, length))
+        let (segment, file) = self.active_segment(data.len())?;
+        let (offset, length) = file.add(data)?;
+        Ok((segment_start(segment) + offset, length))
     }
 
-    #[cfg(feature = "write")]
+    #[cfg(any(feature = "write", feature = "garbage-collection"))]
     fn persist(&mut self) -> anyhow::Result<()> {
-        Ok(self.file.persist()?)
+        // Sealed segments never hold buffered data
+        let (_, active) = self.segments.iter_mut().next_back().unwrap();
+        active.persist()?;
+
+        if let Some(directory) = self.directory.as_ref().filter(|_| self.is_segmented()) {
+            self.write_manifest(directory)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(any(feature = "write", feature = "garbage-collection"))]
+    fn is_segmented(&self) -> bool {
+        self.segment_size.is_some() || self.segments.len() > 1
+    }
+
+    fn size(&self) -> u64 {
+        self.segments.values().map(|file| file.size()).sum()
+    }
+
+    fn read_stats(&self) -> (u64, u64) {
+        (self.reads, self.bytes_read)
+    }
+
+    #[cfg(feature = "garbage-collection")]
+    fn segments(&self) -> Vec<(u64, u64)> {
+        self.segments
+            .iter()
+            .map(|(segment, file)| (segment_start(*segment), file.size()))
+            .collect()
+    }
+
+    #[cfg(feature = "garbage-collection")]
+    fn remove_segment(&mut self, offset: u64) -> anyhow::Result<()> {
+        let segment = segment_of(offset);
+        if Some(&segment) == self.segments.keys().next_back() {
+            anyhow::bail!("The active segment cannot be removed");
+        }
+
+        self.segments.remove(&segment);
+        if let Some(directory) = &self.directory {
+            std::fs::remove_file(segment_path(directory, segment))?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "garbage-collection")]
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> anyhow::Result<()> {
+        Ok(self.segment(offset)?.write_at(local_offset(offset), data)?)
+    }
+
+    #[cfg(feature = "garbage-collection")]
+    fn truncate(&mut self, length: u64) -> anyhow::Result<()> {
+        Ok(self.segment(length)?.truncate(local_offset(length))?)
+    }
+
+    #[cfg(feature = "garbage-collection")]
+    fn punch_hole(&mut self, offset: u64, length: u64) -> std::io::Result<()> {
+        match self.segments.get_mut(&segment_of(offset)) {
+            Some(file) => file.punch_hole(local_offset(offset), length),
+            None => Err(std::io::Error::from(std::io::ErrorKind::NotFound)),
+        }
+    }
+
+    #[cfg(feature = "garbage-collection")]
+    fn allocated_size(&self) -> anyhow::Result<u64> {
+        let mut total = 0;
+        for file in self.segments.values() {
+            total += file.allocated_size()?;
+        }
+        Ok(total)
     }
 
     #[cfg(feature = "garbage-collection")]
@@ -34,9 +279,88 @@ impl Loader for LazyLoader {
         &mut self,
         f: F,
     ) -> anyhow::Result<()> {
-        let data = self.file.read_all()?;
+        if self.is_segmented() {
+            anyhow::bail!("Segmented data files cannot be replaced at once");
+        }
+
+        let file = self.segments.get_mut(&0).unwrap();
+        let data = file.read_all()?;
         let new_data = f(&data)?;
-        self.file.replace_with(&new_data)?;
+        file.replace_with(&new_data)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_segment_offsets() {
+        let offset = segment_start(3) + 42;
+        assert_eq!(segment_of(offset), 3);
+        assert_eq!(local_offset(offset), 42);
+        assert_eq!(segment_of(42), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "write")]
+    fn test_segments_roll_over() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let mut loader = LazyLoader::new(temp_dir.path(), Some(10));
+
+        assert_eq!(loader.add(b"0123456")?, (0, 7));
+        assert_eq!(loader.add(b"abc")?, (7, 3));
+        assert_eq!(loader.add(b"x")?, (segment_start(1), 1));
+        assert_eq!(loader.add(b"0123456789abc")?, (segment_start(2), 13));
+        loader.persist()?;
+
+        let mut loader = LazyLoader::new(temp_dir.path(), Some(10));
+        assert_eq!(loader.load(segment_start(1), 1)?, b"x");
+        assert_eq!(loader.load(7, 3)?, b"abc");
+        assert_eq!(loader.add(b"y")?, (segment_start(3), 1));
+
+        let manifest = std::fs::read_to_string(temp_dir.path().join(SEGMENT_MANIFEST_NAME))?;
+        assert_eq!(manifest, "0 10\n1 1\n2 13\n");
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "write")]
+    fn test_segment_size_limit() -> anyhow::Result<()> {
+        assert!(LazyLoader::load_into_memory(Path::new("."), Some(MAX_SEGMENT_SIZE + 1)).is_err());
+
+        // A value may end right at the limit, but not past it
+        let temp_dir = tempfile::tempdir()?;
+        let file = std::fs::File::create(temp_dir.path().join(DATA_FILE_NAME))?;
+        file.set_len(MAX_SEGMENT_SIZE - 4)?;
+        let mut loader = LazyLoader::new(temp_dir.path(), None);
+        assert!(loader.add(b"01234567").is_err());
+        assert_eq!(loader.add(b"0123")?, (MAX_SEGMENT_SIZE - 4, 4));
+        assert!(loader.add(b"4").is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "write")]
+    fn test_in_memory_segments() -> anyhow::Result<()> {
+        let mut loader = LazyLoader::in_memory(Some(10));
+        assert_eq!(loader.add(b"0123456")?, (0, 7));
+        assert_eq!(loader.add(b"abcd")?, (segment_start(1), 4));
+        loader.persist()?;
+        assert_eq!(loader.load(segment_start(1), 4)?, b"abcd");
+        assert!(loader.load(segment_start(1) + 2, 4).is_err());
+
+        let temp_dir = tempfile::tempdir()?;
+        loader.save_to(temp_dir.path())?;
+        let manifest = std::fs::read_to_string(temp_dir.path().join(SEGMENT_MANIFEST_NAME))?;
+        assert_eq!(manifest, "0 7\n1 4\n");
+
+        let mut loaded = LazyLoader::load_into_memory(temp_dir.path(), Some(10))?;
+        assert_eq!(loaded.load(0, 7)?, b"0123456");
+        assert_eq!(loaded.add(b"x")?, (segment_start(1) + 4, 1));
+
         Ok(())
     }
 }
@@ -5,11 +5,33 @@ pub(crate) mod loader;
 pub trait Loader {
     fn load(&mut self, offset: u64, length: usize) -> anyhow::Result<Vec<u8>>;
 
-    #[cfg(feature = "write")]
+    #[cfg(any(feature = "write", feature = "garbage-collection"))]
     fn add(&mut self, data: &[u8]) -> anyhow::Result<(u64, usize)>;
-    #[cfg(feature = "write")]
+    #[cfg(any(feature = "write", feature = "garbage-collection"))]
     fn persist(&mut self) -> anyhow::Result<()>;
 
+    /// Whether the data is split into several segment files.
+    #[cfg(any(feature = "write", feature = "garbage-collection"))]
+    fn is_segmented(&self) -> bool;
+
+    fn size(&self) -> u64;
+    /// Returns how many values have been loaded, and their total size in bytes.
+    fn read_stats(&self) -> (u64, u64);
+    /// Returns the start offset and size of every segment.
+    #[cfg(feature = "garbage-collection")]
+    fn segments(&self) -> Vec<(u64, u64)>;
+    /// Deletes the (sealed) segment containing `offset`.
+    #[cfg(feature = "garbage-collection")]
+    fn remove_segment(&mut self, offset: u64) -> anyhow::Result<()>;
+    #[cfg(feature = "garbage-collection")]
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> anyhow::Result<()>;
+    #[cfg(feature = "garbage-collection")]
+    fn truncate(&mut self, length: u64) -> anyhow::Result<()>;
+    #[cfg(feature = "garbage-collection")]
+    fn punch_hole(&mut self, offset: u64, length: u64) -> std::io::Result<()>;
+    #[cfg(feature = "garbage-collection")]
+    fn allocated_size(&self) -> anyhow::Result<u64>;
+
     #[cfg(feature = "garbage-collection")]
     fn read_and_replace<F: FnOnce(&[u8]) -> anyhow::Result<Vec<u8>>>(
         &mut self,
@@ -17,9 +17,19 @@
 //! ## 🔧 Core API
 //! - `new`: Initialize a new database.
 //! - `get`: Fetch the value paired with a particular key.
+//! - `scan_prefix` / `range`: Fetch the keys starting with a prefix, or within a range, in order.
 //! - `link`: Set up an alias between two keys.
+//! - `open_tree`: Open a named keyspace with an index of its own, sharing the data file, cache and `persist`.
 //! - `delete`: Eliminate a key from the index; actual data remains untouched.
+//! - `watch_prefix`: Subscribe to the inserts, deletes and links of the keys starting with a prefix.
+//! - `register_index` / `get_by_index`: Look up records by a key extracted from their values, kept up to date on writes.
 //! - `persist`: Make certain the recent changes are stored permanently.
+//! - `stats`: Report cache hits and misses, disk reads, and the size of the index and data files.
+//! - `apply_batch`: Apply writes and deletes across keyspaces at once, or none of them (`write` feature).
+//! - `put_with_ttl`: Write a key that reads as missing once its time to live passed, removed on `persist` or garbage collection (`write` feature).
+//! - `compare_and_swap` / `update_and_fetch` / `fetch_add`: Update a value based on the current one, atomically (`write` feature).
+//! - `register_merge_operator` / `merge`: Append operands to a value without reading it, folded on reads and garbage collection (`write` feature).
+//! - `LLDatabase::in_memory`: Keep a database in memory only, `save_to` and `load_from` convert from and to disk.
 //!
 //! ## 🌐 `remote-cloning` Feature
 //! Upon activating the `remote-cloning` feature:
@@ -28,12 +38,35 @@
 //!   - `path`: The target directory on the local machine.
 //!   - `compression`: Dictates the compression type during the transfer, defaulting to `None` for no compression.
 //!
+//! ## 🧹 `garbage-collection` Feature
+//! Upon activating the `garbage-collection` feature:
+//! - `gc`: Rewrites the data file, dropping everything that is no longer referenced.
+//! - `gc_step`: Compacts a bounded region of the data file, so it can be interleaved with reads.
+//! - `space_stats`: Reports live bytes, dead bytes and fragmentation of the data file.
+//! - `DatabaseSettings::gc_policy`: Collects automatically once enough of the data file is dead.
+//! - `DatabaseSettings::gc_mode`: Punches holes into dead regions instead of compacting (Linux).
+//! - `DatabaseSettings::allocation_mode`: Lets `put` reuse dead regions instead of growing the file.
+//!
+//! ## 📇 `static-index` Feature
+//! - `IndexType::Static`: A read-only index addressed by a perfect hash function and memory mapped, opening it is O(1).
+//! - `convert_index`: Rewrites the index of a database as another type, e.g. once it won't change anymore.
+//!
+//! ## 🔤 `fst-index` Feature
+//! - `IndexType::Fst`: A sorted index stored as a memory mapped finite state transducer, for prefix scans and ranges over millions of keys. Changes are merged into it on `persist`.
+//!
+//! ## 👀 `async-watch` Feature
+//! - `Subscriber`: The changes `watch_prefix` reports can be received as a `futures_core::Stream`, besides the blocking iterator.
+//!
+//! ## 📈 `metrics` and `tracing` Features
+//! - `metrics`: Exports cache hits, misses, disk reads and the database sizes through the `metrics` crate.
+//! - `tracing`: Emits spans for `persist` and garbage collection, and logs failed reads as warnings.
+//!
 //! Consult the README for a comprehensive guide, feature details, and performance benchmarks.
 
-mod cache;
+pub mod cache;
 pub(crate) mod index_table;
 mod io;
-pub use index_table::factory::IndexType;
+pub use index_table::factory::{convert_index, IndexFormat, IndexType};
 
 mod api;
 mod databases;
@@ -49,6 +82,8 @@ pub use remote::{cloner::clone_from, compression::CompressionType};
 
 #[cfg(feature = "garbage-collection")]
 mod garbage_collection;
+#[cfg(feature = "garbage-collection")]
+pub use garbage_collection::{AllocationMode, GcMode, GcPolicy, GcReport, SpaceStats};
 
 #[cfg(feature = "write")]
 mod transactions;
@@ -1,10 +1,19 @@
 use crate::remote::compression::CompressionType;
 use anyhow::Result;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 const LOCAL_PREFIX: &str = ".rdb";
-const EXTENSIONS: [&str; 3] = ["type", "index", "data"];
+const EXTENSIONS: [&str; 2] = ["type", "index"];
+const DATA_EXTENSION: &str = "data";
+const SEGMENTS_EXTENSION: &str = "segments";
+const STATIC_EXTENSION: &str = "static";
+const FST_EXTENSION: &str = "fst";
+const BPLUS_TREE_EXTENSION: &str = "bptree";
+const BLOOM_FILTER_EXTENSION: &str = "bloom";
+const GENERATION_EXTENSION: &str = "generation";
+const EXPIRY_EXTENSION: &str = "expiry";
+const OPERANDS_EXTENSION: &str = "operands";
 
 pub async fn clone_from(
     address: &str,
@@ -13,22 +22,117 @@ pub async fn clone_from(
 ) -> Result<()> {
     let local_path = PathBuf::from(path);
     for e in EXTENSIONS {
-        let local_extension = format!("./{}.{}", LOCAL_PREFIX, e);
-        let local_file = local_path.join(local_extension);
-        let remote_file = format!("{}/{}", address, e);
+        clone_extension(address, &local_path, e, &compression).await?;
+    }
+    // Some index types are stored in a file of their own
+    let index_extension = match std::fs::read_to_string(local_file(&local_path, "type"))?.as_str() {
+        "Static" => Some(STATIC_EXTENSION),
+        "Fst" => Some(FST_EXTENSION),
+        "BPlusTree" => Some(BPLUS_TREE_EXTENSION),
+        _ => None,
+    };
+    if let Some(extension) = index_extension {
+        clone_extension(address, &local_path, extension, &compression).await?;
+    }
+    // The Bloom filter of a previous copy doesn't match the new index, it is rebuilt on open
+    let _ = std::fs::remove_file(local_file(&local_path, BLOOM_FILTER_EXTENSION));
+    // Neither do its secondary indexes, dropping the generation makes them rebuilt on registration
+    let _ = std::fs::remove_file(local_file(&local_path, GENERATION_EXTENSION));
+    // Only databases with keys that expire have an expiry table, and only databases with merge
+    // operands that weren't folded have an operands table
+    for extension in [EXPIRY_EXTENSION, OPERANDS_EXTENSION] {
+        clone_optional_extension(address, &local_path, extension, &compression).await?;
+    }
+
+    // Segmented databases list their data files in a manifest, databases without one only have
+    // a single data file
+    if !clone_optional_extension(address, &local_path, SEGMENTS_EXTENSION, &compression).await? {
+        return clone_extension(address, &local_path, DATA_EXTENSION, &compression).await;
+    }
 
-        clone_from_remote(&remote_file, &local_file, &compression).await?;
+    let manifest = local_file(&local_path, SEGMENTS_EXTENSION);
+    let segments = parse_manifest(&std::fs::read_to_string(manifest)?)?;
+    for (i, (segment, size)) in segments.iter().enumerate() {
+        let extension = match segment {
+            0 => DATA_EXTENSION.to_string(),
+            _ => format!("{}.{}", DATA_EXTENSION, segment),
+        };
+
+        // Sealed segments never change, so a local copy of the same size is up to date
+        let sealed = i + 1 < segments.len();
+        let local_size = std::fs::metadata(local_file(&local_path, &extension)).map(|m| m.len());
+        if sealed && local_size.ok() == Some(*size) {
+            continue;
+        }
+
+        clone_extension(address, &local_path, &extension, &compression).await?;
     }
 
     Ok(())
 }
 
+fn local_file(local_path: &Path, extension: &str) -> PathBuf {
+    local_path.join(format!("./{}.{}", LOCAL_PREFIX, extension))
+}
+
+// Each line of the manifest holds the id and size of a segment
+fn parse_manifest(manifest: &str) -> Result<Vec<(u32, u64)>> {
+    manifest
+        .lines()
+        .map(|line| {
+            let (segment, size) = line
+                .split_once(' ')
+                .ok_or_else(|| anyhow::anyhow!("Invalid segment manifest line: {}", line))?;
+            Ok((segment.parse()?, size.parse()?))
+        })
+        .collect()
+}
+
+async fn clone_extension(
+    address: &str,
+    local_path: &Path,
+    extension: &str,
+    compression: &Option<CompressionType>,
+) -> Result<()> {
+    let local_file = local_file(local_path, extension);
+    let remote_file = format!("{}/{}", address, extension);
+
+    clone_from_remote(&remote_file, &local_file, compression).await
+}
+
+// Clones a file the remote database may not have, returns whether it had one. A local copy is
+// removed when the remote file is missing, any other error is returned
+async fn clone_optional_extension(
+    address: &str,
+    local_path: &Path,
+    extension: &str,
+    compression: &Option<CompressionType>,
+) -> Result<bool> {
+    match clone_extension(address, local_path, extension, compression).await {
+        Ok(()) => Ok(true),
+        Err(error) if is_not_found(&error) => {
+            match std::fs::remove_file(local_file(local_path, extension)) {
+                Err(error) if error.kind() != std::io::ErrorKind::NotFound => Err(error.into()),
+                _ => Ok(false),
+            }
+        }
+        Err(error) => Err(error),
+    }
+}
+
+fn is_not_found(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<reqwest::Error>()
+        .and_then(|error| error.status())
+        == Some(reqwest::StatusCode::NOT_FOUND)
+}
+
 async fn clone_from_remote(
     address: &str,
     path: &PathBuf,
     compression: &Option<CompressionType>,
 ) -> Result<()> {
-    let mut response = reqwest::get(address).await?;
+    let mut response = reqwest::get(address).await?.error_for_status()?;
     let mut file = std::fs::File::create(path)?;
     while let Some(chunk) = response.chunk().await? {
         file.write_all(&chunk)?;
@@ -57,8 +161,26 @@ mod tests {
     async fn start_mock_server() {
         SERVER_STARTED
             .get_or_init(|| async {
-                let route = warp::any()
-                    .map(|| {
+                let route = warp::path::tail()
+                    .map(|tail: warp::path::Tail| {
+                        // Databases at broken/<extension> can't serve that file
+                        if let Some(rest) = tail.as_str().strip_prefix("broken/") {
+                            if rest.split('/').next() == rest.rsplit('/').next() {
+                                let mut response =
+                                    warp::http::Response::new(warp::hyper::Body::empty());
+                                *response.status_mut() =
+                                    warp::http::StatusCode::INTERNAL_SERVER_ERROR;
+                                return response;
+                            }
+                        }
+                        // The mock database is not segmented, so it has no manifest
+                        if tail.as_str().ends_with(SEGMENTS_EXTENSION) {
+                            let mut response =
+                                warp::http::Response::new(warp::hyper::Body::empty());
+                            *response.status_mut() = warp::http::StatusCode::NOT_FOUND;
+                            return response;
+                        }
+
                         let mut response =
                             warp::http::Response::new(warp::hyper::Body::from("Hello, World!"));
                         response.headers_mut().insert(
@@ -69,11 +191,20 @@ mod tests {
                     })
                     .with(warp::log("mock_server"));
 
-                let _ = tokio::spawn(warp::serve(route).run(([127, 0, 0, 1], 8080)));
+                drop(tokio::spawn(warp::serve(route).run(([127, 0, 0, 1], 8080))));
             })
             .await;
     }
 
+    #[test]
+    fn test_parse_manifest() {
+        assert_eq!(
+            parse_manifest("0 10\n1 2\n").unwrap(),
+            vec![(0, 10), (1, 2)]
+        );
+        assert!(parse_manifest("0").is_err());
+    }
+
     #[tokio::test]
     async fn test_clone_from() {
         start_mock_server().await;
@@ -84,6 +215,8 @@ mod tests {
         let path = temp.path().to_str().unwrap();
 
         let compression = Some(CompressionType::Uncompressed);
+        // A manifest left by a previous copy, the remote database isn't segmented anymore
+        std::fs::write(local_file(temp.path(), SEGMENTS_EXTENSION), "0 1").unwrap();
 
         let s_path = format!("{}{}", SERVER_ADDR, SERVER_FILE_PATH);
         let result = clone_from(s_path.as_str(), path, compression).await;
@@ -92,5 +225,28 @@ mod tests {
         // You can perform more assertions here if needed, like checking the content of the cloned files.
 
         assert!(result.is_ok());
+        assert!(!local_file(temp.path(), SEGMENTS_EXTENSION).exists());
+    }
+
+    #[tokio::test]
+    async fn test_clone_from_server_error() {
+        start_mock_server().await;
+        tokio::time::sleep(Duration::from_millis(100)).await; // Give the server some time to start
+
+        let temp = tempdir().unwrap();
+        let path = temp.path().to_str().unwrap();
+        for extension in [EXPIRY_EXTENSION, OPERANDS_EXTENSION, SEGMENTS_EXTENSION] {
+            std::fs::write(local_file(temp.path(), extension), "local").unwrap();
+
+            // Only a missing file means the database has none, the local copy is kept otherwise
+            let s_path = format!("{}/broken/{}", SERVER_ADDR, extension);
+            let result =
+                clone_from(s_path.as_str(), path, Some(CompressionType::Uncompressed)).await;
+            assert!(result.is_err());
+            assert_eq!(
+                std::fs::read_to_string(local_file(temp.path(), extension)).unwrap(),
+                "local"
+            );
+        }
     }
 }
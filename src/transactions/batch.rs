@@ -0,0 +1,78 @@
+/// Writes and deletes across the default keyspace and any number of trees, applied at once by
+/// [`Database::apply_batch`](crate::Database::apply_batch). Either all operations are applied or,
+/// if one of them fails, none is.
+///
+/// Operations are applied in the order they were added, so a later operation on a key wins.
+///
+/// # Example
+/// ```no_run
+/// use readb::{Batch, Database, DefaultDatabase};
+///
+/// let mut db = DefaultDatabase::new_default("./test_db".into());
+/// let mut batch = Batch::new();
+/// batch.put_in("users", "alice", b"Alice");
+/// batch.put_in("emails", "alice@example.com", b"alice");
+/// batch.delete("pending:alice");
+/// db.apply_batch(batch).unwrap();
+/// ```
+#[derive(Default)]
+pub struct Batch {
+    pub(crate) operations: Vec<BatchOperation>,
+}
+
+pub(crate) struct BatchOperation {
+    tree: Option<String>,
+    pub key: String,
+    // `None` deletes the key
+    pub value: Option<Vec<u8>>,
+}
+
+impl BatchOperation {
+    pub fn tree(&self) -> Option<&str> {
+        self.tree.as_deref()
+    }
+}
+
+impl Batch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `key` with `value` to the default keyspace.
+    pub fn put(&mut self, key: &str, value: &[u8]) {
+        self.push(None, key, Some(value));
+    }
+
+    /// Deletes `key` from the default keyspace.
+    pub fn delete(&mut self, key: &str) {
+        self.push(None, key, None);
+    }
+
+    /// Adds `key` with `value` to the tree `tree`, which is created if needed.
+    pub fn put_in(&mut self, tree: &str, key: &str, value: &[u8]) {
+        self.push(Some(tree), key, Some(value));
+    }
+
+    /// Deletes `key` from the tree `tree`.
+    pub fn delete_in(&mut self, tree: &str, key: &str) {
+        self.push(Some(tree), key, None);
+    }
+
+    /// Returns the number of operations in the batch.
+    pub fn len(&self) -> usize {
+        self.operations.len()
+    }
+
+    /// Returns whether the batch holds no operations.
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    fn push(&mut self, tree: Option<&str>, key: &str, value: Option<&[u8]>) {
+        self.operations.push(BatchOperation {
+            tree: tree.map(str::to_string),
+            key: key.to_string(),
+            value: value.map(<[u8]>::to_vec),
+        });
+    }
+}
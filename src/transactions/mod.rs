@@ -8,6 +8,7 @@ pub trait Transaction: Send + Sync {
     fn rollback(&mut self) -> anyhow::Result<()>;
 }
 
+pub(crate) mod batch;
 pub(crate) mod simple;
 
 #[derive(Debug, PartialEq, Eq)]
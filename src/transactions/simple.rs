@@ -10,7 +10,7 @@ where
     D: DatabaseTransactionsIO,
 {
     database: &'a mut D,
-    index_table_snapshot: Box<dyn IndexTable>,
+    index_table_snapshot: Option<Box<dyn IndexTable>>,
 
     new_entries: HashMap<String, Vec<u8>>,
 
@@ -25,7 +25,7 @@ where
         let index_table_snapshot = database.snapshot();
         Self {
             database,
-            index_table_snapshot,
+            index_table_snapshot: Some(index_table_snapshot),
             new_entries: HashMap::new(),
             state: TransactionState::Unknown,
         }
@@ -78,21 +78,23 @@ where
         };
 
         let mut running_offset = offset;
+        let mut new_key_values = Vec::with_capacity(new_entries.len());
         for (key, value) in new_entries {
             let len = value.len();
-            match self
-                .index_table_snapshot
-                .insert(key.as_str(), (running_offset, len))
-            {
-                Ok(_) => (),
-                Err(e) => {
-                    self.rollback()?;
-                    bail!("Failed to write to database: {:?}, rolling back", e);
-                }
-            }
+            new_key_values.push((key, (running_offset, len)));
             running_offset += len as u64;
         }
 
+        // The snapshot is only taken once, and the database restores it if merging fails
+        let backup = match self.index_table_snapshot.take() {
+            Some(backup) => backup,
+            None => self.database.snapshot(),
+        };
+        if let Err(e) = self.database.merge_index_table(backup, new_key_values) {
+            self.rollback()?;
+            bail!("Failed to write to database: {:?}, rolling back", e);
+        }
+
         self.state = TransactionState::Commit;
         Ok(())
     }
@@ -1,8 +1,21 @@
+// The older tests build their values by pushing in loops
+#![allow(clippy::same_item_push)]
+
 #[cfg(feature = "write")]
 mod tests {
     use rand::Rng;
 
-    use readb::{Database, DatabaseSettings, DefaultDatabase, IndexType};
+    use readb::cache::{LfuCache, ShardedCache};
+    #[cfg(feature = "garbage-collection")]
+    use readb::{AllocationMode, GcMode, GcPolicy};
+    use readb::{
+        Batch, Clock, Database, DatabaseSettings, DefaultDatabase, Event, IndexFormat, IndexType,
+        LLDatabase, Preload,
+    };
+    use std::ops::Bound;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
     #[cfg(feature = "garbage-collection")]
     use walkdir::WalkDir;
 
@@ -196,6 +209,45 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_multithreaded_read_shared_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut db = DefaultDatabase::new_default(dir.path().to_path_buf());
+            for i in 0..10000 {
+                db.put(
+                    format!("key{}", i).as_str(),
+                    format!("value{}", i).as_bytes(),
+                )
+                .unwrap();
+            }
+            db.persist().unwrap();
+        }
+
+        let cache: Arc<ShardedCache<LfuCache>> = Arc::new(ShardedCache::new(4096));
+        std::thread::scope(|s| {
+            for _ in 0..10 {
+                let cache = cache.clone();
+                let dir = dir.path().to_path_buf();
+                s.spawn(move || {
+                    let mut db = LLDatabase::with_cache(
+                        DatabaseSettings {
+                            path: Some(dir),
+                            ..Default::default()
+                        },
+                        cache,
+                    );
+
+                    for i in 0..10000 {
+                        let key = format!("key{}", i);
+                        let value = format!("value{}", i);
+                        assert_eq!(db.get(key.as_str()).unwrap().unwrap(), value.as_bytes());
+                    }
+                });
+            }
+        });
+    }
+
     #[test]
     fn tests_around_buffering() {
         let temp_dir = tempfile::tempdir().unwrap();
@@ -325,6 +377,11 @@ mod tests {
 
             db.gc().unwrap();
             db.persist().unwrap();
+
+            // Only key3 moved, key1 is still at the start of the file
+            let report = db.last_gc_report().unwrap();
+            assert_eq!(report.moved_bytes, 6);
+            assert_eq!(report.reclaimed_bytes, 6);
         }
 
         // Now we want to see the file size of the database / folder
@@ -338,6 +395,1561 @@ mod tests {
         assert!(total_size_after_gc < total_size);
     }
 
+    #[test]
+    #[cfg(feature = "garbage-collection")]
+    fn test_space_stats() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut db = DefaultDatabase::new(DatabaseSettings {
+            path: Some(temp_dir.path().to_path_buf()),
+            ..Default::default()
+        });
+
+        db.put("key1", "value1".as_bytes()).unwrap();
+        db.put("key2", "value2".as_bytes()).unwrap();
+        db.link("key1", "alias").unwrap();
+        assert_eq!(db.space_stats().live_bytes, 12);
+        assert_eq!(db.space_stats().dead_bytes, 0);
+
+        // The alias still references the data
+        db.delete("key1").unwrap();
+        assert_eq!(db.space_stats().dead_bytes, 0);
+
+        db.put("alias", "other".as_bytes()).unwrap();
+        db.delete("key2").unwrap();
+        let stats = db.space_stats();
+        assert_eq!(stats.live_bytes, 5);
+        assert_eq!(stats.dead_bytes, 12);
+        assert!(stats.fragmentation > 0.7);
+    }
+
+    #[test]
+    #[cfg(feature = "garbage-collection")]
+    fn test_incremental_garbage_collect() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut db = DefaultDatabase::new(DatabaseSettings {
+            path: Some(temp_dir.path().to_path_buf()),
+            ..Default::default()
+        });
+
+        for i in 0..1000 {
+            db.put(
+                format!("key{}", i).as_str(),
+                format!("value{}", i).as_bytes(),
+            )
+            .unwrap();
+        }
+        for i in (0..1000).step_by(2) {
+            db.put(format!("key{}", i).as_str(), format!("new{}", i).as_bytes())
+                .unwrap();
+        }
+        db.link("key1", "alias").unwrap();
+
+        let before = db.space_stats();
+        assert!(before.dead_bytes > 0);
+
+        let mut steps = 0;
+        let mut reclaimed = 0;
+        loop {
+            let report = db.gc_step(1024).unwrap();
+            steps += 1;
+            reclaimed += report.reclaimed_bytes;
+
+            // Reads and writes keep working in between steps
+            assert_eq!(db.get("key1").unwrap().unwrap(), "value1".as_bytes());
+            if steps == 2 {
+                db.put("key3", "during".as_bytes()).unwrap();
+            }
+
+            if report.finished {
+                break;
+            }
+        }
+
+        assert!(steps > 2);
+        // "value3" was overwritten after the compaction passed it, it'll be reclaimed next run
+        assert_eq!(db.space_stats().dead_bytes, 6);
+        assert_eq!(reclaimed, before.dead_bytes);
+        assert_eq!(db.get("alias").unwrap().unwrap(), "value1".as_bytes());
+        assert_eq!(db.get("key3").unwrap().unwrap(), "during".as_bytes());
+        assert_eq!(db.get("key998").unwrap().unwrap(), "new998".as_bytes());
+        assert_eq!(db.get("key999").unwrap().unwrap(), "value999".as_bytes());
+
+        // The compacted state is persisted
+        drop(db);
+        let mut db = DefaultDatabase::new(DatabaseSettings {
+            path: Some(temp_dir.path().to_path_buf()),
+            ..Default::default()
+        });
+        assert_eq!(db.get("key500").unwrap().unwrap(), "new500".as_bytes());
+        assert_eq!(db.get("key501").unwrap().unwrap(), "value501".as_bytes());
+    }
+
+    #[test]
+    #[cfg(feature = "garbage-collection")]
+    fn test_incremental_garbage_collect_small_gap() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut db = DefaultDatabase::new_default(temp_dir.path().to_path_buf());
+        db.put("a", &[1; 10]).unwrap();
+        db.put("b", &[2; 1000]).unwrap();
+        db.link("b", "c").unwrap();
+        db.put("d", &[3; 10]).unwrap();
+        db.delete("a").unwrap();
+
+        // The values are copied over their own previous location, by way of the end of the file
+        let report = db.gc_step(1024).unwrap();
+        assert!(report.finished);
+        assert_eq!(report.moved_bytes, 1010);
+        assert_eq!(report.reclaimed_bytes, 10);
+        assert_eq!(db.stats().data_file_size, 1010);
+        assert_eq!(db.get("c").unwrap().unwrap(), vec![2; 1000]);
+
+        drop(db);
+        let mut db = DefaultDatabase::new_default(temp_dir.path().to_path_buf());
+        assert_eq!(db.get("b").unwrap().unwrap(), vec![2; 1000]);
+        assert_eq!(db.get("d").unwrap().unwrap(), vec![3; 10]);
+        assert_eq!(db.space_stats().dead_bytes, 0);
+    }
+
+    #[test]
+    #[cfg(feature = "garbage-collection")]
+    fn test_incremental_garbage_collect_trees() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut db = DefaultDatabase::new_default(temp_dir.path().to_path_buf());
+        db.put("a", &[1; 100]).unwrap();
+        let mut tree = db.open_tree("t").unwrap();
+        tree.put("x", &[7; 100]).unwrap();
+        drop(tree);
+        db.put("b", &[2; 100]).unwrap();
+        db.persist().unwrap();
+
+        db.delete("a").unwrap();
+        while !db.gc_step(1024).unwrap().finished {}
+
+        // The tree's index on disk follows the moved values without another persist
+        drop(db);
+        let mut db = DefaultDatabase::new_default(temp_dir.path().to_path_buf());
+        assert_eq!(db.get("b").unwrap().unwrap(), vec![2; 100]);
+        let mut tree = db.open_tree("t").unwrap();
+        assert_eq!(tree.get("x").unwrap().unwrap(), vec![7; 100]);
+    }
+
+    #[test]
+    #[cfg(feature = "garbage-collection")]
+    fn test_gc_policy() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut db = DefaultDatabase::new(DatabaseSettings {
+            path: Some(temp_dir.path().to_path_buf()),
+            gc_policy: Some(GcPolicy {
+                dead_ratio: 0.5,
+                min_file_size: 1024,
+                min_interval: Duration::from_secs(3600),
+            }),
+            ..Default::default()
+        });
+
+        // Too small to be collected
+        db.put("key", "value".as_bytes()).unwrap();
+        db.put("key", "other".as_bytes()).unwrap();
+        db.persist().unwrap();
+        assert!(db.last_gc_report().is_none());
+
+        let value = vec![7; 1000];
+        db.put("big", &value).unwrap();
+        db.put("big", &value).unwrap();
+        let report = db.last_gc_report().unwrap();
+        assert!(report.finished);
+        assert_eq!(report.reclaimed_bytes, 1005);
+        assert_eq!(db.space_stats().dead_bytes, 0);
+        assert_eq!(db.get("big").unwrap().unwrap(), value);
+
+        // The interval didn't pass yet
+        db.put("big", &value).unwrap();
+        db.persist().unwrap();
+        assert_eq!(db.space_stats().dead_bytes, 1000);
+        assert_eq!(db.last_gc_report().unwrap(), report);
+    }
+
+    #[test]
+    #[cfg(feature = "garbage-collection")]
+    fn test_free_space_reuse() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let settings = || DatabaseSettings {
+            path: Some(temp_dir.path().to_path_buf()),
+            allocation_mode: AllocationMode::BestFit,
+            ..Default::default()
+        };
+
+        {
+            let mut db = DefaultDatabase::new(settings());
+            for i in 0..10 {
+                db.put(format!("key{}", i).as_str(), &[i; 100]).unwrap();
+            }
+            db.persist().unwrap();
+
+            // Update heavy workload, the file reaches a steady state
+            for round in 0..50u8 {
+                for i in 0..10 {
+                    db.put(format!("key{}", i).as_str(), &[round; 100]).unwrap();
+                }
+                db.persist().unwrap();
+            }
+
+            let stats = db.space_stats();
+            assert_eq!(stats.live_bytes, 1000);
+            assert!(stats.live_bytes + stats.dead_bytes <= 2000);
+            assert_eq!(db.get("key3").unwrap().unwrap(), vec![49; 100]);
+        }
+
+        // The free list survives a restart
+        let mut db = DefaultDatabase::new(settings());
+        for i in 0..10 {
+            db.put(format!("key{}", i).as_str(), &[42; 100]).unwrap();
+        }
+        db.persist().unwrap();
+
+        let stats = db.space_stats();
+        assert!(stats.live_bytes + stats.dead_bytes <= 2000);
+        assert_eq!(db.get("key9").unwrap().unwrap(), vec![42; 100]);
+    }
+
+    #[test]
+    #[cfg(feature = "garbage-collection")]
+    fn test_no_stale_reads_after_compaction() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let open = |path: &std::path::Path| {
+            DefaultDatabase::new(DatabaseSettings {
+                path: Some(path.to_path_buf()),
+                create_path: true,
+                ..Default::default()
+            })
+        };
+
+        // After "key1" is gone, "key2" moves to the location "key1" had in the cache
+        for incremental in [false, true] {
+            let dir = temp_dir.path().join(incremental.to_string());
+            let mut db = open(&dir);
+            for i in 1..=3 {
+                db.put(
+                    format!("key{}", i).as_str(),
+                    format!("value{}", i).as_bytes(),
+                )
+                .unwrap();
+                db.get(format!("key{}", i).as_str()).unwrap();
+            }
+            db.pin("key3").unwrap();
+            db.delete("key1").unwrap();
+
+            match incremental {
+                true => while !db.gc_step(1).unwrap().finished {},
+                false => db.gc().unwrap(),
+            }
+            assert_eq!(db.space_stats().dead_bytes, 0);
+
+            // Cached values moved along with their data
+            let reads = db.stats().loader_reads;
+            assert_eq!(db.get("key2").unwrap().unwrap(), "value2".as_bytes());
+            assert_eq!(db.get("key3").unwrap().unwrap(), "value3".as_bytes());
+            assert!(db.get("key1").unwrap().is_none());
+            assert_eq!(db.stats().loader_reads, reads);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "garbage-collection")]
+    fn test_no_stale_reads_after_reuse() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut db = DefaultDatabase::new(DatabaseSettings {
+            path: Some(temp_dir.path().to_path_buf()),
+            allocation_mode: AllocationMode::BestFit,
+            ..Default::default()
+        });
+
+        db.put("key1", "value1".as_bytes()).unwrap();
+        db.get("key1").unwrap();
+        db.put("key1", "other1".as_bytes()).unwrap();
+        db.persist().unwrap();
+
+        // The region of the first value is reused, while it might still be cached
+        db.put("key2", "value2".as_bytes()).unwrap();
+        assert_eq!(
+            db.space_stats().live_bytes + db.space_stats().dead_bytes,
+            12
+        );
+        assert_eq!(db.get("key2").unwrap().unwrap(), "value2".as_bytes());
+        assert_eq!(db.get("key1").unwrap().unwrap(), "other1".as_bytes());
+    }
+
+    // Whether the file system of `dir` supports punching holes, anything but a clear no fails
+    #[cfg(all(feature = "garbage-collection", target_os = "linux"))]
+    fn supports_punching_holes(dir: &std::path::Path) -> bool {
+        use std::os::unix::io::AsRawFd;
+
+        let file = tempfile::tempfile_in(dir).unwrap();
+        file.set_len(64 * 1024).unwrap();
+        // SAFETY: the file descriptor stays open while `file` is alive
+        let result = unsafe {
+            libc::fallocate(
+                file.as_raw_fd(),
+                libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                0,
+                32 * 1024,
+            )
+        };
+        if result == 0 {
+            return true;
+        }
+        let error = std::io::Error::last_os_error();
+        assert!(
+            matches!(error.raw_os_error(), Some(libc::EOPNOTSUPP | libc::ENOSYS)),
+            "{}",
+            error
+        );
+        false
+    }
+
+    #[cfg(all(feature = "garbage-collection", not(target_os = "linux")))]
+    fn supports_punching_holes(_dir: &std::path::Path) -> bool {
+        false
+    }
+
+    #[cfg(feature = "garbage-collection")]
+    fn punch_holes_in(dir: &std::path::Path, supported: bool) {
+        let mut db = DefaultDatabase::new(DatabaseSettings {
+            path: Some(dir.to_path_buf()),
+            gc_mode: GcMode::PunchHoles,
+            ..Default::default()
+        });
+
+        let value = vec![1; 64 * 1024];
+        for i in 0..8 {
+            db.put(format!("key{}", i).as_str(), &value).unwrap();
+        }
+        for i in (0..8).step_by(2) {
+            db.delete(format!("key{}", i).as_str()).unwrap();
+        }
+        db.persist().unwrap();
+
+        let data_file = dir.join(".rdb.data");
+        let size_before = std::fs::metadata(&data_file).unwrap().len();
+        db.gc().unwrap();
+        let size_after = std::fs::metadata(&data_file).unwrap().len();
+        let report = db.last_gc_report().unwrap();
+
+        if supported {
+            // Holes were punched, offsets stay valid
+            assert_eq!(size_after, size_before);
+            assert_eq!(report.moved_bytes, 0);
+            assert!(report.reclaimed_bytes >= 3 * 64 * 1024);
+        } else {
+            // The file system doesn't support punching holes, so the file was compacted
+            assert_eq!(size_after, 4 * 64 * 1024);
+            assert_eq!(report.moved_bytes, 4 * 64 * 1024);
+        }
+
+        for i in (1..8).step_by(2) {
+            assert_eq!(
+                db.get(format!("key{}", i).as_str()).unwrap().unwrap(),
+                value
+            );
+        }
+        db.put("key0", "new".as_bytes()).unwrap();
+        assert_eq!(db.get("key0").unwrap().unwrap(), "new".as_bytes());
+    }
+
+    #[test]
+    #[cfg(feature = "garbage-collection")]
+    fn test_punch_holes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        punch_holes_in(temp_dir.path(), supports_punching_holes(temp_dir.path()));
+
+        // tmpfs supports punching holes on Linux
+        let shm = std::path::Path::new("/dev/shm");
+        if cfg!(target_os = "linux") && shm.is_dir() {
+            let temp_dir = tempfile::tempdir_in(shm).unwrap();
+            punch_holes_in(temp_dir.path(), true);
+        }
+    }
+
+    #[test]
+    fn test_segmented_data() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let settings = || DatabaseSettings {
+            path: Some(temp_dir.path().to_path_buf()),
+            segment_size: Some(256),
+            ..Default::default()
+        };
+
+        {
+            let mut db = DefaultDatabase::new(settings());
+            for i in 0..10 {
+                db.put(format!("key{}", i).as_str(), &[i; 100]).unwrap();
+            }
+            db.persist().unwrap();
+        }
+
+        assert!(temp_dir.path().join(".rdb.data.4").exists());
+        assert!(temp_dir.path().join(".rdb.segments").exists());
+
+        let mut db = DefaultDatabase::new(settings());
+        for i in 0..10 {
+            assert_eq!(
+                db.get(format!("key{}", i).as_str()).unwrap().unwrap(),
+                vec![i; 100]
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "garbage-collection")]
+    fn test_segmented_garbage_collect() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut db = DefaultDatabase::new(DatabaseSettings {
+            path: Some(temp_dir.path().to_path_buf()),
+            segment_size: Some(256),
+            ..Default::default()
+        });
+
+        for i in 0..10 {
+            db.put(format!("key{}", i).as_str(), &[i; 100]).unwrap();
+        }
+        // Every value in the first segment dies
+        db.put("key0", &[42; 100]).unwrap();
+        db.delete("key1").unwrap();
+        db.persist().unwrap();
+
+        let report = db.gc_step(1024).unwrap();
+        assert_eq!(report.reclaimed_bytes, 200);
+        assert!(!temp_dir.path().join(".rdb.data").exists());
+
+        db.gc().unwrap();
+        assert_eq!(db.space_stats().dead_bytes, 0);
+        assert_eq!(db.get("key0").unwrap().unwrap(), vec![42; 100]);
+        assert!(db.get("key1").unwrap().is_none());
+        for i in 2..10 {
+            assert_eq!(
+                db.get(format!("key{}", i).as_str()).unwrap().unwrap(),
+                vec![i; 100]
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "garbage-collection")]
+    fn test_segmented_garbage_collect_empty_value() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let settings = || DatabaseSettings {
+            path: Some(temp_dir.path().to_path_buf()),
+            segment_size: Some(8),
+            ..Default::default()
+        };
+
+        {
+            let mut db = DefaultDatabase::new(settings());
+            db.put("a", &[1; 8]).unwrap();
+            // Located at the end of the first segment, which is full
+            db.put("empty", &[]).unwrap();
+            db.put("b", &[2; 8]).unwrap();
+            db.put("a", &[3; 8]).unwrap();
+            db.persist().unwrap();
+            db.gc().unwrap();
+            db.persist().unwrap();
+        }
+
+        let mut db = DefaultDatabase::new(settings());
+        assert_eq!(db.get("empty").unwrap().unwrap(), Vec::<u8>::new());
+        assert_eq!(db.get("a").unwrap().unwrap(), vec![3; 8]);
+        assert_eq!(db.get("b").unwrap().unwrap(), vec![2; 8]);
+    }
+
+    #[test]
+    fn test_cache_bytes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut db = DefaultDatabase::new(DatabaseSettings {
+            path: Some(temp_dir.path().to_path_buf()),
+            cache_bytes: Some(1024),
+            cache_max_entry_bytes: Some(512),
+            ..Default::default()
+        });
+
+        db.put("small", &[1; 256]).unwrap();
+        db.put("large", &[2; 4096]).unwrap();
+        for i in 1..=3 {
+            assert_eq!(db.get("small").unwrap().unwrap(), vec![1; 256]);
+            assert_eq!(db.get("large").unwrap().unwrap(), vec![2; 4096]);
+            // "large" is never cached, so every read loads it again
+            let stats = db.stats();
+            assert_eq!(stats.loader_reads, 1 + i);
+            assert_eq!(stats.cache.entries, 1);
+            assert_eq!(stats.cache.bytes, 256);
+        }
+
+        // Values are evicted once they exceed the size of the cache
+        for i in 0..5 {
+            db.put(format!("key{}", i).as_str(), &[3; 256]).unwrap();
+            db.get(format!("key{}", i).as_str()).unwrap();
+        }
+        let stats = db.stats();
+        assert_eq!(stats.cache.evictions, 2);
+        assert_eq!(stats.cache.entries, 4);
+        assert_eq!(stats.cache.bytes, 1024);
+
+        // A bound on single values alone keeps the default number of entries
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut db = DefaultDatabase::new(DatabaseSettings {
+            path: Some(temp_dir.path().to_path_buf()),
+            cache_max_entry_bytes: Some(512),
+            ..Default::default()
+        });
+        for i in 0..2000 {
+            db.put(format!("key{}", i).as_str(), &[1; 8]).unwrap();
+            db.get(format!("key{}", i).as_str()).unwrap();
+        }
+        assert_eq!(db.stats().cache.entries, 1024);
+    }
+
+    #[test]
+    fn test_stats() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut db = DefaultDatabase::new_default(temp_dir.path().to_path_buf());
+        db.put("key1", "value1".as_bytes()).unwrap();
+        db.put("key2", "value2".as_bytes()).unwrap();
+
+        db.get("key1").unwrap();
+        db.get("key1").unwrap();
+        db.get("key2").unwrap();
+
+        let stats = db.stats();
+        assert_eq!(stats.cache.hits, 1);
+        assert_eq!(stats.cache.misses, 2);
+        assert_eq!(stats.cache.entries, 2);
+        assert_eq!(stats.cache.bytes, 12);
+        assert_eq!(stats.loader_reads, 2);
+        assert_eq!(stats.bytes_read, 12);
+        assert_eq!(stats.index_entries, 2);
+        assert_eq!(stats.data_file_size, 12);
+    }
+
+    #[test]
+    fn test_preload() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let settings = |preload| DatabaseSettings {
+            path: Some(temp_dir.path().to_path_buf()),
+            cache_size: Some(4),
+            preload,
+            ..Default::default()
+        };
+
+        {
+            let mut db = DefaultDatabase::new(settings(Preload::None));
+            for i in 0..10 {
+                db.put(format!("key{}", i).as_str(), &[i; 10]).unwrap();
+            }
+            db.persist().unwrap();
+        }
+
+        // Values are loaded until the cache is full
+        let db = DefaultDatabase::new(settings(Preload::All));
+        assert_eq!(db.stats().cache.entries, 4);
+
+        let mut db = DefaultDatabase::new(settings(Preload::Keys(vec![
+            "key7".to_string(),
+            "missing".to_string(),
+        ])));
+        assert_eq!(db.get("key7").unwrap().unwrap(), vec![7; 10]);
+        assert_eq!(db.stats().cache.hits, 1);
+        assert_eq!(db.stats().loader_reads, 1);
+
+        // The keys read before persisting are preloaded on the next open
+        {
+            let mut db = DefaultDatabase::new(settings(Preload::HotSet));
+            db.get("key3").unwrap();
+            db.get("key5").unwrap();
+            db.persist().unwrap();
+        }
+        let mut db = DefaultDatabase::new(settings(Preload::HotSet));
+        let reads = db.stats().loader_reads;
+        assert_eq!(db.get("key3").unwrap().unwrap(), vec![3; 10]);
+        assert_eq!(db.get("key5").unwrap().unwrap(), vec![5; 10]);
+        assert_eq!(db.stats().loader_reads, reads);
+        assert_eq!(db.stats().cache.hits, 2);
+    }
+
+    #[test]
+    fn test_pinned_keys() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut db = DefaultDatabase::new(DatabaseSettings {
+            path: Some(temp_dir.path().to_path_buf()),
+            cache_size: Some(2),
+            ..Default::default()
+        });
+        db.put("config", "v1".as_bytes()).unwrap();
+        for i in 0..10 {
+            db.put(format!("key{}", i).as_str(), &[i; 10]).unwrap();
+        }
+        assert!(db.pin("missing").is_err());
+        db.pin("config").unwrap();
+
+        // Reading other keys never evicts the pinned value
+        for i in 0..10 {
+            db.get(format!("key{}", i).as_str()).unwrap();
+        }
+        let reads = db.stats().loader_reads;
+        assert_eq!(db.get("config").unwrap().unwrap(), "v1".as_bytes());
+        assert_eq!(db.stats().loader_reads, reads);
+
+        // The pin follows the key
+        db.put("config", "v2".as_bytes()).unwrap();
+        let reads = db.stats().loader_reads;
+        assert_eq!(db.get("config").unwrap().unwrap(), "v2".as_bytes());
+        assert_eq!(db.stats().loader_reads, reads);
+
+        db.unpin("config").unwrap();
+        for i in 0..10 {
+            db.get(format!("key{}", i).as_str()).unwrap();
+        }
+        let reads = db.stats().loader_reads;
+        db.get("config").unwrap();
+        assert_eq!(db.stats().loader_reads, reads + 1);
+    }
+
+    #[test]
+    #[cfg(feature = "garbage-collection")]
+    fn test_pinned_keys_moved_by_gc() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut db = DefaultDatabase::new_default(temp_dir.path().to_path_buf());
+        db.put("x", &[1; 5]).unwrap();
+        db.put("b", &[2; 5]).unwrap();
+        db.put("y", &[3; 5]).unwrap();
+        db.put("d", &[4; 5]).unwrap();
+        db.delete("x").unwrap();
+        db.delete("y").unwrap();
+        db.persist().unwrap();
+        db.pin("b").unwrap();
+        db.pin("d").unwrap();
+
+        // d moves to the previous location of b
+        db.gc().unwrap();
+        assert_eq!(db.get("b").unwrap().unwrap(), vec![2; 5]);
+        assert_eq!(db.get("d").unwrap().unwrap(), vec![4; 5]);
+    }
+
+    #[test]
+    fn test_bloom_filter() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let settings = |bloom_filter| DatabaseSettings {
+            path: Some(temp_dir.path().to_path_buf()),
+            bloom_filter,
+            ..Default::default()
+        };
+        let filter_path = temp_dir.path().join(".rdb.bloom");
+
+        let mut db = DefaultDatabase::new(settings(Some(0.01)));
+        for i in 0..100 {
+            db.put(&format!("key{}", i), format!("value{}", i).as_bytes())
+                .unwrap();
+        }
+        db.delete("key0").unwrap();
+        assert!(db.get("key0").unwrap().is_none());
+        assert!(db.get("missing").unwrap().is_none());
+        db.persist().unwrap();
+        assert!(filter_path.exists());
+
+        #[cfg(feature = "garbage-collection")]
+        {
+            db.gc().unwrap();
+            db.persist().unwrap();
+        }
+        drop(db);
+
+        let mut db = DefaultDatabase::new(settings(Some(0.01)));
+        for i in 1..100 {
+            assert_eq!(
+                db.get(&format!("key{}", i)).unwrap().unwrap(),
+                format!("value{}", i).as_bytes()
+            );
+        }
+
+        // Without a filter, the index may change, so the stale filter is dropped
+        drop(db);
+        let mut db = DefaultDatabase::new(settings(None));
+        assert!(!filter_path.exists());
+        db.put("key100", "value100".as_bytes()).unwrap();
+        db.persist().unwrap();
+        drop(db);
+        let mut db = DefaultDatabase::new(settings(Some(0.01)));
+        assert_eq!(db.get("key100").unwrap().unwrap(), "value100".as_bytes());
+    }
+
+    #[test]
+    fn test_in_memory() {
+        let mut db = DefaultDatabase::in_memory(DatabaseSettings::default());
+        db.put("key1", "value1".as_bytes()).unwrap();
+        db.put("key2", "value2".as_bytes()).unwrap();
+        db.link("key1", "alias").unwrap();
+        db.delete("key2").unwrap();
+        db.persist().unwrap();
+
+        let mut tx = db.tx().unwrap();
+        tx.put("key3", "value3".as_bytes()).unwrap();
+        tx.commit().unwrap();
+        drop(tx);
+
+        assert_eq!(db.get("alias").unwrap().unwrap(), "value1".as_bytes());
+        assert_eq!(db.get("key3").unwrap().unwrap(), "value3".as_bytes());
+        assert!(db.get("key2").unwrap().is_none());
+
+        #[cfg(feature = "garbage-collection")]
+        {
+            db.gc().unwrap();
+            assert_eq!(db.space_stats().dead_bytes, 0);
+            assert_eq!(db.get("key1").unwrap().unwrap(), "value1".as_bytes());
+        }
+
+        // The saved copy is a regular database
+        let temp_dir = tempfile::tempdir().unwrap();
+        db.save_to(temp_dir.path()).unwrap();
+        assert!(db.save_to(temp_dir.path()).is_err());
+        {
+            let mut on_disk = DefaultDatabase::new_default(temp_dir.path().to_path_buf());
+            assert_eq!(on_disk.get("alias").unwrap().unwrap(), "value1".as_bytes());
+            assert_eq!(on_disk.get("key3").unwrap().unwrap(), "value3".as_bytes());
+            assert!(on_disk.get("key2").unwrap().is_none());
+        }
+
+        // Changes to a loaded database stay in memory
+        let mut loaded =
+            DefaultDatabase::load_from(temp_dir.path(), DatabaseSettings::default()).unwrap();
+        assert_eq!(loaded.get("key1").unwrap().unwrap(), "value1".as_bytes());
+        loaded.put("key1", "changed".as_bytes()).unwrap();
+        loaded.persist().unwrap();
+        assert_eq!(loaded.get("key1").unwrap().unwrap(), "changed".as_bytes());
+
+        let mut on_disk = DefaultDatabase::new_default(temp_dir.path().to_path_buf());
+        assert_eq!(on_disk.get("key1").unwrap().unwrap(), "value1".as_bytes());
+        assert!(DefaultDatabase::load_from(
+            temp_dir.path().join("missing"),
+            DatabaseSettings::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_index_format() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let settings = |index_format| DatabaseSettings {
+            path: Some(temp_dir.path().to_path_buf()),
+            index_format,
+            ..Default::default()
+        };
+        let index_path = temp_dir.path().join(".rdb.index");
+
+        // By default the index stays readable by previous versions
+        let mut db = DefaultDatabase::new(settings(IndexFormat::Bincode));
+        for i in 0..100 {
+            db.put(&format!("user/{:05}", i), "value".as_bytes())
+                .unwrap();
+        }
+        db.persist().unwrap();
+        drop(db);
+        let index: std::collections::HashMap<String, (u64, usize)> =
+            bincode::deserialize(&std::fs::read(&index_path).unwrap()).unwrap();
+        assert_eq!(index.len(), 100);
+        let bincode_size = std::fs::metadata(&index_path).unwrap().len();
+
+        let mut db = DefaultDatabase::new(settings(IndexFormat::Compact));
+        db.persist().unwrap();
+        drop(db);
+        let compact = std::fs::read(&index_path).unwrap();
+        assert!(compact.starts_with(b"RDBIDX01"));
+        assert!((compact.len() as u64) < bincode_size);
+
+        // Both formats are read, and persisting switches back
+        let mut db = DefaultDatabase::new(settings(IndexFormat::Bincode));
+        assert_eq!(db.get("user/00042").unwrap().unwrap(), "value".as_bytes());
+        db.persist().unwrap();
+        drop(db);
+        assert_eq!(std::fs::metadata(&index_path).unwrap().len(), bincode_size);
+
+        // A damaged index in the compact format is an error rather than an empty index
+        std::fs::write(&index_path, &compact[..compact.len() - 1]).unwrap();
+        assert!(
+            DefaultDatabase::load_from(temp_dir.path(), settings(IndexFormat::Compact)).is_err()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "static-index")]
+    fn test_static_index() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        {
+            let mut db = DefaultDatabase::new_default(temp_dir.path().to_path_buf());
+            for i in 0..1000 {
+                db.put(
+                    format!("key{}", i).as_str(),
+                    format!("value{}", i).as_bytes(),
+                )
+                .unwrap();
+            }
+            db.persist().unwrap();
+        }
+        readb::convert_index(temp_dir.path(), IndexType::Static).unwrap();
+
+        let settings = |index_type| DatabaseSettings {
+            path: Some(temp_dir.path().to_path_buf()),
+            index_type,
+            ..Default::default()
+        };
+        let mut db = DefaultDatabase::new(settings(IndexType::Auto));
+        for i in 0..1000 {
+            assert_eq!(
+                db.get(format!("key{}", i).as_str()).unwrap().unwrap(),
+                format!("value{}", i).as_bytes()
+            );
+        }
+        assert!(db.get("key1000").unwrap().is_none());
+
+        // Rejected writes don't leave their data behind
+        let size = db.stats().data_file_size;
+        assert!(db.put("key1000", "value".as_bytes()).is_err());
+        assert!(db.delete("key1").is_err());
+        assert_eq!(db.stats().index_entries, 1000);
+        db.register_merge_operator(Box::new(|_, _, operand| Some(operand.to_vec())));
+        assert!(db.merge("key1", "value".as_bytes()).is_err());
+        let mut batch = Batch::new();
+        batch.put("key1000", "value".as_bytes());
+        assert!(db.apply_batch(batch).is_err());
+        let mut tx = db.tx().unwrap();
+        tx.put("key1000", "value".as_bytes()).unwrap();
+        assert!(tx.commit().is_err());
+        drop(tx);
+        assert_eq!(db.stats().data_file_size, size);
+
+        // The static index can be converted back, to write to the database again
+        drop(db);
+        readb::convert_index(temp_dir.path(), IndexType::HashMap).unwrap();
+        let mut db = DefaultDatabase::new(settings(IndexType::HashMap));
+        db.put("key1000", "value1000".as_bytes()).unwrap();
+        assert_eq!(db.get("key999").unwrap().unwrap(), "value999".as_bytes());
+    }
+
+    #[test]
+    fn test_range_and_prefix() {
+        let index_types = [
+            IndexType::HashMap,
+            IndexType::BTreeMap,
+            IndexType::BPlusTree,
+            #[cfg(feature = "fst-index")]
+            IndexType::Fst,
+        ];
+
+        for index_type in index_types {
+            let temp_dir = tempfile::tempdir().unwrap();
+            let mut db = DefaultDatabase::new(DatabaseSettings {
+                path: Some(temp_dir.path().to_path_buf()),
+                index_type,
+                ..Default::default()
+            });
+            for key in ["user:2", "user:1", "users", "order:1", "user:3"] {
+                db.put(key, key.as_bytes()).unwrap();
+            }
+            db.persist().unwrap();
+            db.delete("user:3").unwrap();
+            db.link("order:1", "user:0").unwrap();
+
+            let users = db.scan_prefix("user:").unwrap();
+            assert_eq!(
+                users,
+                vec![
+                    ("user:0".to_string(), b"order:1".to_vec()),
+                    ("user:1".to_string(), b"user:1".to_vec()),
+                    ("user:2".to_string(), b"user:2".to_vec()),
+                ]
+            );
+            let keys = |key_values: Vec<(String, Vec<u8>)>| {
+                key_values
+                    .into_iter()
+                    .map(|(key, _)| key)
+                    .collect::<Vec<_>>()
+            };
+            let range = db
+                .range(Bound::Excluded("user:0"), Bound::Unbounded)
+                .unwrap();
+            assert_eq!(keys(range), ["user:1", "user:2", "users"]);
+            let range = db
+                .range(Bound::Unbounded, Bound::Included("user:0"))
+                .unwrap();
+            assert_eq!(keys(range), ["order:1", "user:0"]);
+            assert!(db
+                .range(Bound::Included("z"), Bound::Excluded("a"))
+                .unwrap()
+                .is_empty());
+
+            // Survives reopening, the FST merges its changes into the file
+            db.persist().unwrap();
+            drop(db);
+            let mut db = DefaultDatabase::new(DatabaseSettings {
+                path: Some(temp_dir.path().to_path_buf()),
+                index_type,
+                ..Default::default()
+            });
+            assert_eq!(db.scan_prefix("user:").unwrap(), users);
+            assert_eq!(db.stats().index_entries, 5);
+        }
+    }
+
+    #[test]
+    fn test_trees() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        {
+            let mut db = DefaultDatabase::new_default(temp_dir.path().to_path_buf());
+            db.put("key", "default".as_bytes()).unwrap();
+            let mut users = db.open_tree("users").unwrap();
+            users.put("key", "user".as_bytes()).unwrap();
+            users.put("user:2", "bob".as_bytes()).unwrap();
+            users.link("key", "user:1").unwrap();
+            assert_eq!(users.len(), 3);
+            drop(users);
+
+            let mut orders = db
+                .open_tree_with_type("orders", IndexType::BTreeMap)
+                .unwrap();
+            orders.put("key", "order".as_bytes()).unwrap();
+            drop(orders);
+            assert!(db
+                .open_tree_with_type("orders", IndexType::HashMap)
+                .is_err());
+            assert!(db.open_tree("../escape").is_err());
+
+            assert_eq!(db.get("key").unwrap().unwrap(), "default".as_bytes());
+            db.persist().unwrap();
+        }
+
+        let mut db = DefaultDatabase::new_default(temp_dir.path().to_path_buf());
+        assert_eq!(db.tree_names(), vec!["orders", "users"]);
+        assert_eq!(db.stats().index_entries, 1);
+        let mut users = db.open_tree("users").unwrap();
+        assert_eq!(users.get("key").unwrap().unwrap(), "user".as_bytes());
+        assert_eq!(
+            users.scan_prefix("user:").unwrap(),
+            vec![
+                ("user:1".to_string(), "user".as_bytes().to_vec()),
+                ("user:2".to_string(), "bob".as_bytes().to_vec()),
+            ]
+        );
+        users.delete("key").unwrap();
+        assert!(users.get("key").unwrap().is_none());
+        drop(users);
+        let mut orders = db.open_tree("orders").unwrap();
+        assert_eq!(orders.get("key").unwrap().unwrap(), "order".as_bytes());
+        drop(orders);
+
+        // Trees share the data file, garbage collection keeps their values
+        #[cfg(feature = "garbage-collection")]
+        {
+            db.delete("key").unwrap();
+            db.gc().unwrap();
+            assert_eq!(db.space_stats().dead_bytes, 0);
+            let mut users = db.open_tree("users").unwrap();
+            assert_eq!(users.get("user:1").unwrap().unwrap(), "user".as_bytes());
+            assert_eq!(users.get("user:2").unwrap().unwrap(), "bob".as_bytes());
+        }
+
+        // Trees are saved along with the database
+        let copy_dir = tempfile::tempdir().unwrap();
+        db.save_to(copy_dir.path()).unwrap();
+        let mut copy =
+            DefaultDatabase::load_from(copy_dir.path(), DatabaseSettings::default()).unwrap();
+        let mut orders = copy.open_tree("orders").unwrap();
+        assert_eq!(orders.get("key").unwrap().unwrap(), "order".as_bytes());
+    }
+
+    #[test]
+    fn test_batch() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut db = DefaultDatabase::new_default(temp_dir.path().to_path_buf());
+        db.put("pending:alice", "1".as_bytes()).unwrap();
+
+        let mut batch = Batch::new();
+        batch.put_in("users", "alice", "Alice".as_bytes());
+        batch.put_in("emails", "alice@example.com", "alice".as_bytes());
+        batch.delete("pending:alice");
+        batch.put("count", "1".as_bytes());
+        assert_eq!(batch.len(), 4);
+        db.apply_batch(batch).unwrap();
+
+        assert!(db.get("pending:alice").unwrap().is_none());
+        assert_eq!(db.get("count").unwrap().unwrap(), "1".as_bytes());
+        let mut users = db.open_tree("users").unwrap();
+        assert_eq!(users.get("alice").unwrap().unwrap(), "Alice".as_bytes());
+        drop(users);
+
+        // A failing operation leaves every keyspace as it was
+        db.open_tree_with_type("paged", IndexType::BPlusTree)
+            .unwrap();
+        let mut batch = Batch::new();
+        batch.put("count", "2".as_bytes());
+        batch.delete_in("users", "alice");
+        batch.put_in("paged", &"k".repeat(2000), "too long".as_bytes());
+        assert!(db.apply_batch(batch).is_err());
+        assert_eq!(db.get("count").unwrap().unwrap(), "1".as_bytes());
+        let mut users = db.open_tree("users").unwrap();
+        assert_eq!(users.get("alice").unwrap().unwrap(), "Alice".as_bytes());
+        drop(users);
+
+        let mut batch = Batch::new();
+        batch.put_in("invalid name", "key", "value".as_bytes());
+        assert!(db.apply_batch(batch).is_err());
+        assert_eq!(db.tree_names(), vec!["emails", "paged", "users"]);
+    }
+
+    #[test]
+    #[cfg(feature = "garbage-collection")]
+    fn test_failed_batch_keeps_free_space() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut db = DefaultDatabase::new(DatabaseSettings {
+            path: Some(temp_dir.path().to_path_buf()),
+            allocation_mode: AllocationMode::BestFit,
+            ..Default::default()
+        });
+        db.put("a", &[1; 100]).unwrap();
+        db.put("a", &[2; 100]).unwrap();
+
+        // The previous value of a is released before the batch, which fails
+        db.open_tree_with_type("paged", IndexType::BPlusTree)
+            .unwrap();
+        let mut batch = Batch::new();
+        batch.put("b", "value".as_bytes());
+        batch.put_in("paged", &"k".repeat(2000), "too long".as_bytes());
+        assert!(db.apply_batch(batch).is_err());
+        db.persist().unwrap();
+
+        let size = db.stats().data_file_size;
+        db.put("c", &[3; 100]).unwrap();
+        assert_eq!(db.stats().data_file_size, size);
+        assert_eq!(db.get("a").unwrap().unwrap(), vec![2; 100]);
+        assert_eq!(db.get("c").unwrap().unwrap(), vec![3; 100]);
+    }
+
+    #[test]
+    fn test_secondary_index() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let open = || {
+            let mut db = DefaultDatabase::new_default(temp_dir.path().to_path_buf());
+            let email = |value: &[u8]| {
+                let value = std::str::from_utf8(value).ok()?;
+                value.split_once(',').map(|(email, _)| email.to_string())
+            };
+            db.register_index("email", Box::new(email)).unwrap();
+            db
+        };
+        let record = |key: &str, value: &str| (key.to_string(), value.as_bytes().to_vec());
+
+        {
+            let mut db = open();
+            db.put("user:1", "alice@example.com,Alice".as_bytes())
+                .unwrap();
+            db.put("user:2", "bob@example.com,Bob".as_bytes()).unwrap();
+            db.put("user:3", "unknown".as_bytes()).unwrap();
+            assert_eq!(
+                db.get_by_index("email", "alice@example.com").unwrap(),
+                vec![record("user:1", "alice@example.com,Alice")]
+            );
+            assert!(db.get_by_index("name", "Alice").is_err());
+
+            // Changed, linked and deleted values update the index
+            db.put("user:2", "robert@example.com,Bob".as_bytes())
+                .unwrap();
+            assert!(db
+                .get_by_index("email", "bob@example.com")
+                .unwrap()
+                .is_empty());
+            db.link("user:1", "admin").unwrap();
+            db.delete("user:1").unwrap();
+            assert_eq!(
+                db.get_by_index("email", "alice@example.com").unwrap(),
+                vec![record("admin", "alice@example.com,Alice")]
+            );
+
+            let mut tx = db.tx().unwrap();
+            tx.put("user:4", "carol@example.com,Carol".as_bytes())
+                .unwrap();
+            tx.commit().unwrap();
+            drop(tx);
+            let mut batch = Batch::new();
+            batch.put("user:5", "carol@example.com,Caroline".as_bytes());
+            db.apply_batch(batch).unwrap();
+            assert_eq!(
+                db.get_by_index("email", "carol@example.com").unwrap(),
+                vec![
+                    record("user:4", "carol@example.com,Carol"),
+                    record("user:5", "carol@example.com,Caroline"),
+                ]
+            );
+            db.persist().unwrap();
+        }
+
+        // The index is persisted, and dropped if the database changes while it isn't registered
+        {
+            let mut db = open();
+            assert_eq!(
+                db.get_by_index("email", "carol@example.com").unwrap().len(),
+                2
+            );
+        }
+        {
+            let mut db = DefaultDatabase::new_default(temp_dir.path().to_path_buf());
+            db.put("user:6", "dave@example.com,Dave".as_bytes())
+                .unwrap();
+            db.delete("user:5").unwrap();
+            db.persist().unwrap();
+        }
+        let mut db = open();
+        assert_eq!(
+            db.get_by_index("email", "dave@example.com").unwrap().len(),
+            1
+        );
+        assert_eq!(
+            db.get_by_index("email", "carol@example.com").unwrap().len(),
+            1
+        );
+
+        // A crash while persisting may leave an index of changes the database on disk doesn't
+        // have, it's rebuilt then
+        let backup = tempfile::tempdir().unwrap();
+        let copy_files = |from: &std::path::Path, to: &std::path::Path| {
+            for entry in std::fs::read_dir(from).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_file() {
+                    std::fs::copy(&path, to.join(path.file_name().unwrap())).unwrap();
+                }
+            }
+        };
+        copy_files(temp_dir.path(), backup.path());
+        db.delete("user:6").unwrap();
+        db.persist().unwrap();
+        drop(db);
+        copy_files(backup.path(), temp_dir.path());
+        let mut db = open();
+        assert_eq!(
+            db.get_by_index("email", "dave@example.com").unwrap(),
+            vec![record("user:6", "dave@example.com,Dave")]
+        );
+    }
+
+    // A clock that only moves when told to
+    #[derive(Default)]
+    struct TestClock(AtomicU64);
+
+    impl TestClock {
+        fn advance(&self, duration: Duration) {
+            self.0
+                .fetch_add(duration.as_millis() as u64, Ordering::SeqCst);
+        }
+    }
+
+    impl Clock for TestClock {
+        fn now(&self) -> SystemTime {
+            UNIX_EPOCH + Duration::from_millis(self.0.load(Ordering::SeqCst))
+        }
+    }
+
+    #[test]
+    fn test_ttl() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let clock = Arc::new(TestClock::default());
+        let open = || {
+            DefaultDatabase::new(DatabaseSettings {
+                path: Some(temp_dir.path().to_path_buf()),
+                clock: clock.clone(),
+                ..Default::default()
+            })
+        };
+
+        {
+            let mut db = open();
+            db.put_with_ttl("session:1", b"alice", Duration::from_secs(60))
+                .unwrap();
+            db.put_with_ttl("session:2", b"bob", Duration::from_secs(10))
+                .unwrap();
+            db.put_with_ttl("session:3", b"carol", Duration::from_secs(10))
+                .unwrap();
+            db.put("user:1", b"alice").unwrap();
+            db.link("session:2", "alias").unwrap();
+
+            // A plain write removes the expiry
+            db.put("session:3", b"carol").unwrap();
+            db.persist().unwrap();
+        }
+
+        let mut db = open();
+        assert_eq!(db.get("session:2").unwrap(), Some(b"bob".to_vec()));
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(db.get("session:2").unwrap(), None);
+        assert_eq!(db.get("alias").unwrap(), None);
+        assert!(db.link("session:2", "other").is_err());
+        assert_eq!(db.get("session:1").unwrap(), Some(b"alice".to_vec()));
+        assert_eq!(db.get("session:3").unwrap(), Some(b"carol".to_vec()));
+        assert_eq!(
+            db.scan_prefix("session:")
+                .unwrap()
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>(),
+            vec!["session:1", "session:3"]
+        );
+
+        // Expired keys are deleted on persist
+        assert_eq!(db.stats().index_entries, 5);
+        db.persist().unwrap();
+        assert_eq!(db.stats().index_entries, 3);
+        clock.advance(Duration::from_secs(50));
+        assert_eq!(db.get("session:1").unwrap(), None);
+        db.persist().unwrap();
+        drop(db);
+
+        let mut db = open();
+        assert_eq!(db.stats().index_entries, 2);
+        assert_eq!(db.get("user:1").unwrap(), Some(b"alice".to_vec()));
+
+        // Rewriting a key with a time to live expires it again
+        db.put_with_ttl("user:1", b"alice", Duration::from_secs(1))
+            .unwrap();
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(db.get("user:1").unwrap(), None);
+    }
+
+    #[cfg(feature = "garbage-collection")]
+    #[test]
+    fn test_ttl_gc_reclaims_space() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let clock = Arc::new(TestClock::default());
+        let mut db = DefaultDatabase::new(DatabaseSettings {
+            path: Some(temp_dir.path().to_path_buf()),
+            clock: clock.clone(),
+            ..Default::default()
+        });
+        db.put("kept", &[1; 100]).unwrap();
+        db.put_with_ttl("expiring", &[2; 1000], Duration::from_secs(1))
+            .unwrap();
+        clock.advance(Duration::from_secs(1));
+
+        db.gc().unwrap();
+        assert_eq!(db.space_stats().live_bytes, 100);
+        assert_eq!(db.space_stats().dead_bytes, 0);
+        assert_eq!(db.get("kept").unwrap(), Some(vec![1; 100]));
+    }
+
+    #[test]
+    fn test_compare_and_swap() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut db = DefaultDatabase::new_default(temp_dir.path().to_path_buf());
+
+        assert!(db
+            .compare_and_swap("key", None, Some(b"a"))
+            .unwrap()
+            .is_ok());
+        let error = db
+            .compare_and_swap("key", None, Some(b"b"))
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(error.current, Some(b"a".to_vec()));
+        assert_eq!(error.proposed, Some(b"b".to_vec()));
+
+        assert!(db
+            .compare_and_swap("key", Some(b"a"), Some(b"b"))
+            .unwrap()
+            .is_ok());
+        assert_eq!(db.get("key").unwrap(), Some(b"b".to_vec()));
+        assert!(db
+            .compare_and_swap("key", Some(b"a"), None)
+            .unwrap()
+            .is_err());
+        assert!(db
+            .compare_and_swap("key", Some(b"b"), None)
+            .unwrap()
+            .is_ok());
+        assert_eq!(db.get("key").unwrap(), None);
+
+        // An expired key is missing
+        db.put_with_ttl("lock", b"a", Duration::ZERO).unwrap();
+        assert!(db
+            .compare_and_swap("lock", None, Some(b"b"))
+            .unwrap()
+            .is_ok());
+        assert_eq!(db.get("lock").unwrap(), Some(b"b".to_vec()));
+    }
+
+    #[test]
+    fn test_update_and_fetch() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut db = DefaultDatabase::new_default(temp_dir.path().to_path_buf());
+        let append = |value: Option<&[u8]>| {
+            let mut value = value.map(<[u8]>::to_vec).unwrap_or_default();
+            value.push(b'x');
+            Some(value)
+        };
+
+        assert_eq!(
+            db.update_and_fetch("key", append).unwrap(),
+            Some(b"x".to_vec())
+        );
+        assert_eq!(
+            db.update_and_fetch("key", append).unwrap(),
+            Some(b"xx".to_vec())
+        );
+        assert_eq!(db.update_and_fetch("key", |_| None).unwrap(), None);
+        assert_eq!(db.get("key").unwrap(), None);
+    }
+
+    #[test]
+    fn test_fetch_add() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = DefaultDatabase::new_default(temp_dir.path().to_path_buf());
+        let db = Arc::new(std::sync::Mutex::new(db));
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let db = db.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        db.lock().unwrap().fetch_add("counter", 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let mut db = db.lock().unwrap();
+        assert_eq!(db.fetch_add("counter", -400).unwrap(), 400);
+        assert_eq!(
+            db.get("counter").unwrap(),
+            Some(0i64.to_le_bytes().to_vec())
+        );
+        db.put("name", b"alice").unwrap();
+        assert!(db.fetch_add("name", 1).is_err());
+    }
+
+    fn append(_key: &str, value: Option<&[u8]>, operand: &[u8]) -> Option<Vec<u8>> {
+        let mut value = value.map(<[u8]>::to_vec).unwrap_or_default();
+        value.extend_from_slice(operand);
+        Some(value)
+    }
+
+    #[test]
+    fn test_merge() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        {
+            let mut db = DefaultDatabase::new_default(temp_dir.path().to_path_buf());
+            assert!(db.merge("events", b"a").is_err());
+            db.register_merge_operator(Box::new(append));
+
+            db.merge("events", b"a").unwrap();
+            db.merge("events", b"b").unwrap();
+            db.merge("events", b"c").unwrap();
+            assert_eq!(db.get("events").unwrap(), Some(b"abc".to_vec()));
+            db.link("events", "alias").unwrap();
+            db.merge("events", b"d").unwrap();
+            assert_eq!(db.get("alias").unwrap(), Some(b"abc".to_vec()));
+            assert_eq!(
+                db.scan_prefix("").unwrap(),
+                vec![
+                    ("alias".to_string(), b"abc".to_vec()),
+                    ("events".to_string(), b"abcd".to_vec()),
+                ]
+            );
+
+            // Writes drop the operands
+            db.put("other", b"x").unwrap();
+            db.merge("other", b"y").unwrap();
+            db.put("other", b"z").unwrap();
+            assert_eq!(db.get("other").unwrap(), Some(b"z".to_vec()));
+            db.persist().unwrap();
+        }
+
+        // Operands are persisted, but the operator has to be registered again
+        let mut db = DefaultDatabase::new_default(temp_dir.path().to_path_buf());
+        assert!(db.get("events").is_err());
+        db.register_merge_operator(Box::new(append));
+        assert_eq!(db.get("events").unwrap(), Some(b"abcd".to_vec()));
+        db.merge("events", b"e").unwrap();
+        assert_eq!(db.get("events").unwrap(), Some(b"abcde".to_vec()));
+
+        db.register_merge_operator(Box::new(|_, _, _| None));
+        db.merge("other", b"x").unwrap();
+        assert_eq!(db.get("other").unwrap(), None);
+        db.delete("other").unwrap();
+        assert_eq!(db.stats().index_entries, 2);
+    }
+
+    #[test]
+    fn test_merge_secondary_index() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut db = DefaultDatabase::new_default(temp_dir.path().to_path_buf());
+        db.register_merge_operator(Box::new(append));
+        db.register_index(
+            "last",
+            Box::new(|value| value.last().map(|byte| (*byte as char).to_string())),
+        )
+        .unwrap();
+
+        db.put("key", b"a").unwrap();
+        db.merge("key", b"b").unwrap();
+        assert!(db.get_by_index("last", "a").unwrap().is_empty());
+        assert_eq!(
+            db.get_by_index("last", "b").unwrap(),
+            vec![("key".to_string(), b"ab".to_vec())]
+        );
+        db.put("key", b"c").unwrap();
+        assert!(db.get_by_index("last", "b").unwrap().is_empty());
+        assert_eq!(db.get_by_index("last", "c").unwrap().len(), 1);
+    }
+
+    #[cfg(feature = "garbage-collection")]
+    #[test]
+    fn test_merge_gc_folds_operands() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut db = DefaultDatabase::new_default(temp_dir.path().to_path_buf());
+        db.register_merge_operator(Box::new(append));
+        db.put("events", &[1; 10]).unwrap();
+        for _ in 0..10 {
+            db.merge("events", &[2; 10]).unwrap();
+        }
+        db.link("events", "alias").unwrap();
+        assert_eq!(db.space_stats().live_bytes, 110);
+
+        db.gc().unwrap();
+        let mut expected = vec![1; 10];
+        expected.extend(vec![2; 100]);
+        assert_eq!(db.get("events").unwrap(), Some(expected.clone()));
+        assert_eq!(db.get("alias").unwrap(), Some(expected));
+        assert_eq!(db.space_stats().live_bytes, 220);
+        assert_eq!(db.space_stats().dead_bytes, 0);
+        db.persist().unwrap();
+        drop(db);
+
+        // Nothing is left to fold
+        let mut db = DefaultDatabase::new_default(temp_dir.path().to_path_buf());
+        db.register_merge_operator(Box::new(append));
+        assert_eq!(db.get("events").unwrap().unwrap().len(), 110);
+
+        db.put("junk", &[9; 100]).unwrap();
+        for _ in 0..5 {
+            db.merge("events", &[3; 10]).unwrap();
+        }
+        db.persist().unwrap();
+        drop(db);
+
+        // Without an operator the operands aren't folded, incremental steps move them instead
+        let mut db = DefaultDatabase::new_default(temp_dir.path().to_path_buf());
+        db.delete("junk").unwrap();
+        while !db.gc_step(1024).unwrap().finished {}
+        drop(db);
+
+        let mut db = DefaultDatabase::new_default(temp_dir.path().to_path_buf());
+        db.register_merge_operator(Box::new(append));
+        let mut expected = vec![1; 10];
+        expected.extend(vec![2; 100]);
+        expected.extend(vec![3; 50]);
+        assert_eq!(db.get("events").unwrap(), Some(expected));
+        assert_eq!(db.space_stats().dead_bytes, 0);
+    }
+
+    #[test]
+    fn test_watch_prefix() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut db = DefaultDatabase::new_default(temp_dir.path().to_path_buf());
+        let mut users = db.watch_prefix("user:");
+        let all = db.watch_prefix("");
+        let insert = |key: &str, value: &[u8]| Event::Insert {
+            key: key.to_string(),
+            value: value.to_vec(),
+        };
+        let delete = |key: &str| Event::Delete {
+            key: key.to_string(),
+        };
+
+        db.put("user:1", b"alice").unwrap();
+        db.put("order:1", b"book").unwrap();
+        db.link("user:1", "user:admin").unwrap();
+        db.delete("user:1").unwrap();
+        let mut tx = db.tx().unwrap();
+        tx.put("user:2", b"bob").unwrap();
+        tx.commit().unwrap();
+        drop(tx);
+        let mut batch = Batch::new();
+        batch.put("user:3", b"carol");
+        batch.delete("user:2");
+        batch.put_in("users", "user:4", b"dave");
+        db.apply_batch(batch).unwrap();
+
+        let events: Vec<Event> = std::iter::from_fn(|| users.try_next()).collect();
+        assert_eq!(
+            events,
+            vec![
+                insert("user:1", b"alice"),
+                Event::Link {
+                    old: "user:1".to_string(),
+                    new: "user:admin".to_string(),
+                },
+                delete("user:1"),
+                insert("user:2", b"bob"),
+                insert("user:3", b"carol"),
+                delete("user:2"),
+            ]
+        );
+
+        // The blocking iterator ends with the database
+        drop(db);
+        assert_eq!(all.count(), 7);
+    }
+
+    #[cfg(feature = "async-watch")]
+    #[tokio::test]
+    async fn test_watch_prefix_stream() {
+        use futures_core::Stream;
+        use std::pin::Pin;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut db = DefaultDatabase::new_default(temp_dir.path().to_path_buf());
+        let mut subscriber = db.watch_prefix("key");
+        let handle = tokio::spawn(async move {
+            let mut keys = Vec::new();
+            while let Some(event) =
+                std::future::poll_fn(|cx| Pin::new(&mut subscriber).poll_next(cx)).await
+            {
+                keys.push(event.key().to_string());
+            }
+            keys
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        db.put("key1", b"a").unwrap();
+        db.delete("key1").unwrap();
+        db.put("other", b"b").unwrap();
+        drop(db);
+        assert_eq!(handle.await.unwrap(), vec!["key1", "key1"]);
+    }
+
+    #[test]
+    fn test_transaction_commit() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut db = DefaultDatabase::new(DatabaseSettings {
+            path: Some(temp_dir.path().to_path_buf()),
+            ..Default::default()
+        });
+        db.put("key1", "value1".as_bytes()).unwrap();
+
+        let mut tx = db.tx().unwrap();
+        tx.put("key1", "new1".as_bytes()).unwrap();
+        tx.put("key2", "new2".as_bytes()).unwrap();
+        tx.commit().unwrap();
+        drop(tx);
+
+        assert_eq!(db.get("key1").unwrap().unwrap(), "new1".as_bytes());
+        assert_eq!(db.get("key2").unwrap().unwrap(), "new2".as_bytes());
+    }
+
     #[test]
     fn test_create_dir() {
         let temp_dir = tempfile::tempdir().unwrap();
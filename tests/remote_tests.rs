@@ -2,7 +2,7 @@
 mod tests {
     use readb::{clone_from, Database, DatabaseSettings, DefaultDatabase, IndexType};
     use std::fs;
-    use std::path::PathBuf;
+    use std::path::{Path, PathBuf};
     use tokio::sync::OnceCell;
     use warp::Filter;
 
@@ -17,7 +17,7 @@ mod tests {
 
     static SERVER_STARTED: OnceCell<()> = OnceCell::const_new();
 
-    fn create_database(location: &PathBuf) {
+    fn create_database(location: &Path) {
         let mut db = DefaultDatabase::new(DatabaseSettings {
             path: Some(location.to_path_buf()),
             cache_size: None,
@@ -25,7 +25,7 @@ mod tests {
             ..Default::default()
         });
 
-        for (_, (key, val)) in RANDOM_STRINGS_WITH_KEYS.iter().enumerate() {
+        for (key, val) in RANDOM_STRINGS_WITH_KEYS.iter() {
             db.put(key, val.as_bytes()).unwrap();
         }
 
@@ -41,14 +41,21 @@ mod tests {
                     .map(move |filetype: String| {
                         println!("Reading file: {}", filetype);
                         let file_path = format!("{}/.rdb.{}", d.as_str(), filetype);
-                        let content = std::fs::read_to_string(file_path).unwrap();
-                        warp::http::Response::builder()
-                            .header("content-type", "text/plain")
-                            .body(content)
+                        // Files the database doesn't have are missing, like on a file server
+                        match std::fs::read_to_string(file_path) {
+                            Ok(content) => warp::http::Response::builder()
+                                .header("content-type", "text/plain")
+                                .body(content),
+                            Err(_) => warp::http::Response::builder()
+                                .status(warp::http::StatusCode::NOT_FOUND)
+                                .body(String::new()),
+                        }
                     })
                     .with(warp::log("mock_server"));
 
-                let _ = tokio::spawn(warp::serve(content).run(([127, 0, 0, 1], 3030)));
+                drop(tokio::spawn(
+                    warp::serve(content).run(([127, 0, 0, 1], 3030)),
+                ));
             })
             .await;
     }
@@ -76,7 +83,7 @@ mod tests {
         );
         clone_from(
             "http://localhost:3030/content",
-            &database_dir.as_os_str().to_str().unwrap(),
+            database_dir.as_os_str().to_str().unwrap(),
             None,
         )
         .await